@@ -0,0 +1,184 @@
+use std::path::Path;
+
+use crate::{
+    env::{self, Env},
+    job::resolve_step,
+    runfile::{JobId, Runfile},
+    secrets::{self, SecretProvider},
+    step::find_program,
+};
+
+/// Check that every step of the jobs in `order` could be started in `workdir`, or its own
+/// `workdir` relative to the runfile, without running any of them, returning how many steps were
+/// checked and a message for each that couldn't.
+/// Steps see `base_env` along with their env files and `env`, like in a real run.
+pub fn check_steps(
+    runfile: &Runfile,
+    order: &[JobId],
+    base_env: &Env,
+    secrets: Option<&dyn SecretProvider>,
+    workdir: &Path,
+) -> (usize, Vec<String>) {
+    let mut checked = 0;
+    let mut problems = Vec::new();
+    for id in order {
+        let job = &runfile.jobs[id];
+        let job_env = match job
+            .env_file
+            .as_ref()
+            .map(|path| env::load_file(&runfile.dir.join(path)))
+            .transpose()
+        {
+            Ok(job_env) => job_env.unwrap_or_default(),
+            Err(e) => {
+                problems.push(format!("{id}: {e}"));
+                continue;
+            }
+        };
+
+        for (index, step) in job.steps.iter().enumerate() {
+            if !step.runs_on(std::env::consts::OS) {
+                continue;
+            }
+            checked += 1;
+            let label = format!("{id}/{index}");
+
+            let mut file_env = job_env.clone();
+            match step
+                .env_file
+                .as_ref()
+                .map(|path| env::load_file(&runfile.dir.join(path)))
+                .transpose()
+            {
+                Ok(step_env) => file_env.extend(step_env.unwrap_or_default()),
+                Err(e) => {
+                    problems.push(format!("{label}: {e}"));
+                    continue;
+                }
+            }
+            let mut step = resolve_step(runfile, step, &file_env);
+            if let Err(e) = secrets::resolve_env(&mut step.env, secrets) {
+                problems.push(format!("{label}: {e}"));
+                continue;
+            }
+            let mut env = base_env.clone();
+            env.extend(step.env.clone());
+
+            let workdir = match &step.workdir {
+                Some(dir) => runfile.dir.join(dir),
+                None => workdir.to_owned(),
+            };
+            if !workdir.is_dir() {
+                problems.push(format!(
+                    "{label}: working directory '{}' does not exist",
+                    workdir.display()
+                ));
+                continue;
+            }
+            match step.program(&env) {
+                Ok(program) if find_program(&program, &workdir, &env).is_none() => {
+                    problems.push(format!("{label}: program not found: `{program}`"));
+                }
+                Ok(_) => {}
+                Err(e) => problems.push(format!("{label}: {e}")),
+            }
+        }
+    }
+    (checked, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        runfile::{Job, Step},
+        testing::TempDir,
+    };
+
+    use super::*;
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_check_steps() {
+        let step = |command: &str| Step {
+            command: command.into(),
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("test"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        steps: vec![step("sh -c 'exit 1'"), step("unirun-missing-program")],
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        steps: vec![
+                            step("./missing-script.sh"),
+                            Step {
+                                platforms: vec![String::from("unirun-os")],
+                                ..step("unirun-missing-program")
+                            },
+                            Step {
+                                script: Some(String::from("exit 0")),
+                                ..step("")
+                            },
+                        ],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let order = vec![String::from("build"), String::from("test")];
+        let env = Env::from([(String::from("PATH"), String::from("/bin:/usr/bin"))]);
+
+        let (checked, problems) = check_steps(&runfile, &order, &env, None, &std::env::temp_dir());
+
+        assert_eq!(checked, 4);
+        assert_eq!(
+            problems,
+            vec![
+                "build/1: program not found: `unirun-missing-program`",
+                "test/0: program not found: `./missing-script.sh`",
+                "test/2: a `script` step needs an `interpreter`",
+            ]
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_check_steps_env_file() {
+        let dir = TempDir::new("check-env-file");
+        std::fs::write(dir.join("build.env"), "PROGRAM=true\n").unwrap();
+        let job = |env_file: &str| Job {
+            env_file: Some(env_file.into()),
+            steps: vec![Step {
+                command: "$PROGRAM".into(),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        // Relative to the runfile rather than the working directory
+        let runfile = Runfile {
+            jobs: HashMap::from([
+                ("build".into(), job("build.env")),
+                ("test".into(), job("test.env")),
+            ]),
+            dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let order = vec![String::from("build"), String::from("test")];
+        let env = Env::from([(String::from("PATH"), String::from("/bin:/usr/bin"))]);
+
+        let (_, problems) = check_steps(&runfile, &order, &env, None, &dir);
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("test: Unable to load env file"));
+    }
+}
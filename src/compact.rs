@@ -0,0 +1,221 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    events::{Event, EventSink, Outcome},
+    runfile::JobId,
+};
+
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
+/// How often the line of the running job is redrawn
+const TICK: Duration = Duration::from_millis(100);
+
+/// How the compact format is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Style {
+    /// Redraw a single line for the running job, rather than printing start and done lines
+    pub live: bool,
+    pub color: bool,
+}
+
+impl Style {
+    /// Live on a terminal, and colored too unless `NO_COLOR` is set
+    pub fn detect() -> Style {
+        let live = std::io::stdout().is_terminal();
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        Style {
+            live,
+            color: live && !no_color,
+        }
+    }
+}
+
+/// Renders a run as one line per job for `--format compact`. On a terminal the line of the
+/// running job shows a spinner and the time elapsed, and is replaced by the result once the job
+/// finishes. Elsewhere, such as in CI, each job gets a plain start and a done line.
+#[derive(Debug)]
+pub struct CompactRenderer {
+    style: Style,
+    running: Arc<Mutex<Option<(JobId, Instant)>>>,
+    stop: Arc<AtomicBool>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl CompactRenderer {
+    pub fn new(style: Style) -> CompactRenderer {
+        let running: Arc<Mutex<Option<(JobId, Instant)>>> = Arc::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let ticker = style.live.then(|| {
+            let (running, stop) = (Arc::clone(&running), Arc::clone(&stop));
+            std::thread::spawn(move || {
+                let mut frame = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    if let Some((job, started)) = running.lock().unwrap().as_ref() {
+                        let spinner = SPINNER[frame % SPINNER.len()];
+                        let elapsed = started.elapsed().as_secs_f64();
+                        print!("\r\x1b[2K{spinner} {job} {elapsed:.1}s");
+                        _ = std::io::stdout().flush();
+                        frame += 1;
+                    }
+                    std::thread::sleep(TICK);
+                }
+            })
+        });
+
+        CompactRenderer {
+            style,
+            running,
+            stop,
+            ticker,
+        }
+    }
+}
+
+impl EventSink for CompactRenderer {
+    fn event(&self, event: &Event) {
+        // Hold the lock while printing, so the ticker doesn't draw over the line
+        let mut running = self.running.lock().unwrap();
+        if self.style.live && running.take().is_some() {
+            print!("\r\x1b[2K");
+        }
+        if let Event::Started { job } = event {
+            if self.style.live {
+                *running = Some((job.clone(), Instant::now()));
+                return;
+            }
+        }
+        if let Event::Finished {
+            output: Some(output),
+            ..
+        } = event
+        {
+            eprint!("{output}");
+        }
+        println!("{}", format_event(event, self.style));
+    }
+}
+
+impl Drop for CompactRenderer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(ticker) = self.ticker.take() {
+            _ = ticker.join();
+        }
+    }
+}
+
+/// The line printed for `event`
+fn format_event(event: &Event, style: Style) -> String {
+    let paint = |text: &str, color: u8| match style.color {
+        true => format!("\x1b[{color}m{text}\x1b[0m"),
+        false => text.to_owned(),
+    };
+    let elapsed = |elapsed: &Duration| Duration::from_millis(elapsed.as_millis() as u64);
+
+    match (event, style.live) {
+        (Event::Started { job }, _) => format!("start {job}"),
+        (Event::Skipped { job, reason }, true) => format!("{} {job}: {reason}", paint("-", 33)),
+        (Event::Skipped { job, reason }, false) => format!("skipped {job}: {reason}"),
+        (
+            Event::Finished {
+                job,
+                outcome,
+                elapsed: time,
+                ..
+            },
+            true,
+        ) => {
+            let mark = match outcome {
+                Outcome::Succeeded => paint("✓", 32),
+                Outcome::Failed => paint("✗", 31),
+                Outcome::Cancelled => paint("-", 33),
+            };
+            format!("{mark} {job} {:?}", elapsed(time))
+        }
+        (
+            Event::Finished {
+                job,
+                outcome,
+                elapsed: time,
+                ..
+            },
+            false,
+        ) => {
+            let word = match outcome {
+                Outcome::Succeeded => "done",
+                Outcome::Failed => "failed",
+                Outcome::Cancelled => "cancelled",
+            };
+            format!("{word} {job} ({:?})", elapsed(time))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(outcome: Outcome) -> Event {
+        Event::Finished {
+            job: "build".into(),
+            outcome,
+            elapsed: Duration::from_micros(1_523_400),
+            output: None,
+        }
+    }
+
+    #[test]
+    fn test_format_event_plain() {
+        let style = Style {
+            live: false,
+            color: false,
+        };
+
+        let started = Event::Started {
+            job: "build".into(),
+        };
+        assert_eq!(format_event(&started, style), "start build");
+        assert_eq!(
+            format_event(&finished(Outcome::Succeeded), style),
+            "done build (1.523s)"
+        );
+        assert_eq!(
+            format_event(&finished(Outcome::Failed), style),
+            "failed build (1.523s)"
+        );
+        let skipped = Event::Skipped {
+            job: "docs".into(),
+            reason: "no changes".into(),
+        };
+        assert_eq!(format_event(&skipped, style), "skipped docs: no changes");
+    }
+
+    #[test]
+    fn test_format_event_live() {
+        let uncolored = Style {
+            live: true,
+            color: false,
+        };
+        let colored = Style {
+            live: true,
+            color: true,
+        };
+
+        assert_eq!(
+            format_event(&finished(Outcome::Succeeded), uncolored),
+            "✓ build 1.523s"
+        );
+        assert_eq!(
+            format_event(&finished(Outcome::Failed), colored),
+            "\x1b[31m✗\x1b[0m build 1.523s"
+        );
+    }
+}
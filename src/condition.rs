@@ -0,0 +1,137 @@
+use std::{collections::HashMap, error::Error, fmt::Display};
+
+use crate::expression;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConditionError {
+    condition: String,
+    message: String,
+}
+
+impl Error for ConditionError {}
+
+impl Display for ConditionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid condition '{}': {}",
+            self.condition, self.message
+        )
+    }
+}
+
+/// Evaluate `condition` with `params` substituted for its `${{ params.<name> }}` expressions.
+///
+/// After substitution a condition is either `<left> == <right>` or `<left> != <right>`, comparing
+/// both sides as strings with surrounding whitespace and quotes removed, or a single value that
+/// holds unless it is empty, `false` or `0`.
+pub fn evaluate(condition: &str, params: &HashMap<String, String>) -> Result<bool, ConditionError> {
+    let expanded = substitute(condition, params).map_err(|message| ConditionError {
+        condition: condition.to_owned(),
+        message,
+    })?;
+
+    let operand = |s: &str| {
+        let s = s.trim();
+        ['"', '\'']
+            .iter()
+            .find_map(|&q| s.strip_prefix(q).and_then(|s| s.strip_suffix(q)))
+            .unwrap_or(s)
+            .to_owned()
+    };
+    if let Some((left, right)) = expanded.split_once("!=") {
+        return Ok(operand(left) != operand(right));
+    }
+    if let Some((left, right)) = expanded.split_once("==") {
+        return Ok(operand(left) == operand(right));
+    }
+    Ok(!matches!(operand(&expanded).as_str(), "" | "false" | "0"))
+}
+
+/// Substitute `params` for the `${{ params.<name> }}` expressions in `text`
+pub fn substitute(text: &str, params: &HashMap<String, String>) -> Result<String, String> {
+    expression::substitute(text, |expression| {
+        let Some(name) = expression.strip_prefix("params.") else {
+            return Err(format!(
+                "unknown expression `{expression}`, expected `params.<name>`"
+            ));
+        };
+        match params.get(name) {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(format!("param '{name}' is not defined")),
+        }
+    })
+}
+
+/// Parse a `--param <name>=<value>` argument
+pub fn parse_param(text: &str) -> Result<(String, String), String> {
+    match text.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_owned(), value.to_owned())),
+        _ => Err(format!("invalid param '{text}', expected <name>=<value>")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let params = HashMap::from([
+            (String::from("skip_tests"), String::from("true")),
+            (String::from("target"), String::from("prod")),
+            (String::from("empty"), String::new()),
+        ]);
+
+        assert!(!evaluate("${{ params.skip_tests }} != true", &params).unwrap());
+        assert!(evaluate("${{params.target}} == 'prod'", &params).unwrap());
+        assert!(evaluate("${{ params.skip_tests }}", &params).unwrap());
+        assert!(!evaluate("${{ params.empty }}", &params).unwrap());
+        assert!(!evaluate("false", &params).unwrap());
+    }
+
+    #[test]
+    fn test_evaluate_invalid() {
+        let params = HashMap::new();
+
+        let err = evaluate("${{ params.skip_tests }} != true", &params).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid condition '${{ params.skip_tests }} != true': param 'skip_tests' is not defined"
+        );
+        assert!(evaluate("${{ env.CI }}", &params).is_err());
+        assert!(evaluate("${{ params.skip_tests", &params).is_err());
+    }
+
+    #[test]
+    fn test_substitute() {
+        let params = HashMap::from([(String::from("svc"), String::from("auth"))]);
+
+        assert_eq!(
+            substitute("services/${{ params.svc }}/api", &params),
+            Ok(String::from("services/auth/api"))
+        );
+        assert_eq!(
+            substitute("services", &params),
+            Ok(String::from("services"))
+        );
+        assert_eq!(
+            substitute("${{ params.region }}", &params),
+            Err(String::from("param 'region' is not defined"))
+        );
+    }
+
+    #[test]
+    fn test_parse_param() {
+        assert_eq!(
+            parse_param("skip_tests=true"),
+            Ok((String::from("skip_tests"), String::from("true")))
+        );
+        assert_eq!(
+            parse_param("empty="),
+            Ok((String::from("empty"), String::new()))
+        );
+        assert!(parse_param("skip_tests").is_err());
+        assert!(parse_param("=true").is_err());
+    }
+}
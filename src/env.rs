@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::glob::glob_match;
+
+pub type Env = HashMap<String, String>;
+
+/// Env file loaded when no `--env-file` is given, if it exists
+pub const DEFAULT_ENV_FILE: &str = ".env";
+
+#[derive(Clone, Debug)]
+pub struct EnvFileError {
+    path: PathBuf,
+    message: String,
+}
+
+impl Error for EnvFileError {}
+
+impl Display for EnvFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unable to load env file '{}': {}",
+            self.path.display(),
+            self.message
+        )
+    }
+}
+
+/// Load env files in order, later files overriding variables set by earlier ones. Without any
+/// `paths`, [`DEFAULT_ENV_FILE`] and then `.env.<profile>` are loaded if they exist.
+pub fn load_files(paths: &[PathBuf], profile: Option<&str>) -> Result<Env, EnvFileError> {
+    let mut env = Env::new();
+
+    if paths.is_empty() {
+        let defaults = std::iter::once(PathBuf::from(DEFAULT_ENV_FILE))
+            .chain(profile.map(|profile| PathBuf::from(format!("{DEFAULT_ENV_FILE}.{profile}"))));
+        for path in defaults.filter(|path| path.is_file()) {
+            env.extend(load_file(&path)?);
+        }
+        return Ok(env);
+    }
+
+    for path in paths {
+        env.extend(load_file(path)?);
+    }
+    Ok(env)
+}
+
+pub fn load_file(path: &Path) -> Result<Env, EnvFileError> {
+    let error = |message: String| EnvFileError {
+        path: path.to_owned(),
+        message,
+    };
+
+    let content = std::fs::read_to_string(path).map_err(|e| error(e.to_string()))?;
+    parse_env_file(&content).map_err(error)
+}
+
+/// Parse `KEY=VALUE` lines. Blank lines and `#` comments are ignored, an `export ` prefix is
+/// allowed, and values may be wrapped in single or double quotes.
+fn parse_env_file(content: &str) -> Result<Env, String> {
+    let mut env = Env::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected KEY=VALUE", number + 1));
+        };
+
+        let value = value.trim();
+        let value = [('"', '"'), ('\'', '\'')]
+            .into_iter()
+            .find_map(|(open, close)| value.strip_prefix(open)?.strip_suffix(close))
+            .unwrap_or(value);
+
+        env.insert(key.trim().to_owned(), value.to_owned());
+    }
+    Ok(env)
+}
+
+/// Environment steps start from: variables from env files, overridden by the environment
+/// inherited from the parent process
+pub fn base(files: &Env, clean: bool, pass_env: &[String]) -> Env {
+    let mut env = files.clone();
+    env.extend(inherited(clean, pass_env));
+    env
+}
+
+/// Build the environment inherited from the parent process. In clean mode only the variables
+/// matching an entry in `pass_env` are kept.
+pub fn inherited(clean: bool, pass_env: &[String]) -> Env {
+    if clean {
+        filter_vars(std::env::vars(), pass_env)
+    } else {
+        std::env::vars().collect()
+    }
+}
+
+fn filter_vars(vars: impl Iterator<Item = (String, String)>, allowlist: &[String]) -> Env {
+    vars.filter(|(key, _)| allowlist.iter().any(|pattern| glob_match(pattern, key)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_env_file() {
+        let content = r#"
+            # database
+            export DB_HOST=localhost
+            DB_PASSWORD="hunter 2"
+            GREETING='hi'
+            EMPTY=
+        "#;
+
+        let expected = Env::from([
+            ("DB_HOST".into(), "localhost".into()),
+            ("DB_PASSWORD".into(), "hunter 2".into()),
+            ("GREETING".into(), "hi".into()),
+            ("EMPTY".into(), "".into()),
+        ]);
+
+        assert_eq!(parse_env_file(content), Ok(expected));
+        assert!(parse_env_file("NOT A VARIABLE").is_err());
+    }
+
+    #[test]
+    fn test_load_files_in_order() {
+        let dir = TempDir::new("env");
+        std::fs::write(dir.join(".env"), "A=1\nB=1\n").unwrap();
+        std::fs::write(dir.join(".env.local"), "B=2\n").unwrap();
+
+        let env = load_files(&[dir.join(".env"), dir.join(".env.local")], None).unwrap();
+        assert_eq!(env["A"], "1");
+        assert_eq!(env["B"], "2");
+
+        assert!(load_files(&[dir.join(".env.missing")], None).is_err());
+    }
+
+    #[test]
+    fn test_filter_vars() {
+        let vars = [
+            ("PATH", "/bin"),
+            ("HOME", "/root"),
+            ("CARGO_HOME", "/cargo"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_owned(), v.to_owned()));
+
+        let expected = Env::from([
+            ("PATH".into(), "/bin".into()),
+            ("CARGO_HOME".into(), "/cargo".into()),
+        ]);
+        let actual = filter_vars(vars, &["PATH".into(), "CARGO_*".into()]);
+
+        assert_eq!(actual, expected);
+    }
+}
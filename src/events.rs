@@ -0,0 +1,35 @@
+use std::{fmt::Debug, time::Duration};
+
+use crate::runfile::JobId;
+
+/// What happened to a job during a run, reported as it happens
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Started {
+        job: JobId,
+    },
+    Finished {
+        job: JobId,
+        outcome: Outcome,
+        elapsed: Duration,
+        /// Output of the job's steps if it failed, when output is kept rather than printed
+        output: Option<String>,
+    },
+    Skipped {
+        job: JobId,
+        reason: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Succeeded,
+    Failed,
+    /// The run was cancelled while the job was running
+    Cancelled,
+}
+
+/// Receives the events of a run, e.g. to render its progress
+pub trait EventSink: Debug + Send + Sync {
+    fn event(&self, event: &Event);
+}
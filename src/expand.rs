@@ -0,0 +1,216 @@
+use std::{error::Error, fmt::Display};
+
+use crate::{
+    condition::ConditionError,
+    env::Env,
+    runfile::{Job, JobId, Need, Runfile},
+    step::capture_stdout,
+};
+
+#[derive(Clone, Debug)]
+pub struct ForEachError {
+    job_id: JobId,
+    message: String,
+}
+
+impl Error for ForEachError {}
+
+impl Display for ForEachError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "for_each of job '{}' {}", self.job_id, self.message)
+    }
+}
+
+/// Id of the instance of `job_id` created for `value`
+pub fn instance_id(job_id: &str, value: &str) -> JobId {
+    format!("{job_id}[{value}]")
+}
+
+/// Expand every `for_each` job needed by `targets` into one instance per discovered value. The
+/// original job is kept without steps and needs all of its instances, so anything needing it
+/// still waits for every instance. Returns the ids of the created instances. A command that
+/// prints no values is an error, unless `allow_empty` is set and the job then has no instances.
+pub fn expand_for_each(
+    runfile: &mut Runfile,
+    targets: &[JobId],
+    env: &Env,
+    allow_empty: bool,
+) -> Result<Vec<JobId>, Box<dyn Error>> {
+    let mut instances = Vec::new();
+
+    for job_id in closure(runfile, targets)? {
+        let Some(for_each) = runfile.jobs[&job_id].for_each.clone() else {
+            continue;
+        };
+
+        let error = |message: String| ForEachError {
+            job_id: job_id.clone(),
+            message,
+        };
+
+        let (status, stdout) = capture_stdout(for_each.command.as_str(), env)
+            .map_err(|e| error(format!("command could not be run: {e}")))?;
+        if !status.success() {
+            return Err(Box::new(error(format!("command failed with {status}"))));
+        }
+
+        let values = stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        if values.is_empty() && !allow_empty {
+            return Err(Box::new(error(String::from("command produced no values"))));
+        }
+
+        let base = runfile.jobs.remove(&job_id).unwrap();
+        let mut ids = Vec::new();
+        for value in values {
+            let id = instance_id(&job_id, value);
+            runfile
+                .jobs
+                .insert(id.clone(), instantiate(&base, &for_each.var, value));
+            ids.push(id);
+        }
+
+        runfile.jobs.insert(
+            job_id,
+            Job {
+                needs: ids.iter().cloned().map(Need::from).collect(),
+                steps: Vec::new(),
+                for_each: None,
+                ..base
+            },
+        );
+        instances.extend(ids);
+    }
+
+    Ok(instances)
+}
+
+fn instantiate(base: &Job, var: &str, value: &str) -> Job {
+    let mut job = base.clone();
+    job.name = base.name.as_ref().map(|name| format!("{name} ({value})"));
+    job.tags = Vec::new();
+    job.for_each = None;
+    for step in job.steps.iter_mut() {
+        step.env.insert(var.to_owned(), value.to_owned());
+    }
+    job
+}
+
+/// Ids of `targets` and every job they need, directly or transitively
+fn closure(runfile: &Runfile, targets: &[JobId]) -> Result<Vec<JobId>, ConditionError> {
+    let mut seen = Vec::new();
+    let mut stack = targets.to_vec();
+
+    while let Some(id) = stack.pop() {
+        if seen.contains(&id) {
+            continue;
+        }
+        if let Some(job) = runfile.jobs.get(&id) {
+            stack.extend(runfile.needs(job)?.into_iter().cloned());
+            seen.push(id);
+        }
+    }
+
+    Ok(seen)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::runfile::{ForEach, Step};
+
+    use super::*;
+
+    fn runfile(command: &str) -> Runfile {
+        Runfile {
+            default: String::from("ci"),
+            jobs: HashMap::from([
+                ("build".into(), Job::default()),
+                (
+                    "test".into(),
+                    Job {
+                        needs: vec!["build".into()],
+                        steps: vec![Step {
+                            command: "cargo test -p $crate".into(),
+                            ..Default::default()
+                        }],
+                        for_each: Some(ForEach {
+                            command: command.into(),
+                            var: "crate".into(),
+                        }),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "ci".into(),
+                    Job {
+                        needs: vec!["test".into()],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_expand_for_each() {
+        let mut runfile = runfile("printf 'api\\n\\nweb\\n'");
+
+        let instances = expand_for_each(&mut runfile, &["ci".into()], &Env::new(), false).unwrap();
+
+        assert_eq!(
+            instances,
+            vec![String::from("test[api]"), String::from("test[web]")]
+        );
+        assert_eq!(
+            runfile.jobs["test"].needs,
+            instances
+                .iter()
+                .cloned()
+                .map(Need::from)
+                .collect::<Vec<_>>()
+        );
+        assert!(runfile.jobs["test"].steps.is_empty());
+
+        let api = &runfile.jobs["test[api]"];
+        assert_eq!(api.needs, vec![Need::from("build")]);
+        assert_eq!(api.steps[0].env["crate"], "api");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_expand_for_each_no_values() {
+        let mut runfile = runfile("true");
+
+        assert!(expand_for_each(&mut runfile, &["ci".into()], &Env::new(), false).is_err());
+
+        let instances = expand_for_each(&mut runfile, &["ci".into()], &Env::new(), true).unwrap();
+        assert!(instances.is_empty());
+        assert!(runfile.jobs["test"].steps.is_empty());
+        assert!(runfile.jobs["test"].needs.is_empty());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_expand_for_each_command_fails() {
+        let mut runfile = runfile("false");
+
+        assert!(expand_for_each(&mut runfile, &["ci".into()], &Env::new(), false).is_err());
+    }
+
+    #[test]
+    fn test_expand_for_each_only_needed_jobs() {
+        let mut runfile = runfile("false");
+
+        let instances =
+            expand_for_each(&mut runfile, &["build".into()], &Env::new(), false).unwrap();
+
+        assert!(instances.is_empty());
+    }
+}
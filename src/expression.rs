@@ -0,0 +1,55 @@
+/// Replace the `${{ <expression> }}` expressions in `text` with what `resolve` returns for each
+/// of them, trimmed. An expression `resolve` returns `None` for is left as written, for another
+/// resolver to handle, e.g. `${{ secret(...) }}` once the runfile's `vars` are substituted.
+pub fn substitute(
+    text: &str,
+    mut resolve: impl FnMut(&str) -> Result<Option<String>, String>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${{") {
+        expanded.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            return Err(String::from("unclosed `${{`"));
+        };
+        let expression = &rest[start..start + end + 2];
+        match resolve(expression[3..expression.len() - 2].trim())? {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(expression),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let upper = |expression: &str| match expression.strip_prefix("upper.") {
+            Some(word) => Ok(Some(word.to_uppercase())),
+            None if expression == "fail" => Err(String::from("failed")),
+            None => Ok(None),
+        };
+
+        assert_eq!(
+            substitute("a ${{ upper.b }}${{upper.c}} d", upper),
+            Ok(String::from("a BC d"))
+        );
+        assert_eq!(
+            substitute("${{ other.b }} $HOME {{ x }}", upper),
+            Ok(String::from("${{ other.b }} $HOME {{ x }}"))
+        );
+        assert_eq!(
+            substitute("${{ fail }}", upper),
+            Err(String::from("failed"))
+        );
+        assert_eq!(
+            substitute("${{ upper.b", upper),
+            Err(String::from("unclosed `${{`"))
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::{path::Path, process::Command};
+
+use crate::glob::glob_match;
+
+/// Files changed in the working tree relative to `base`, as paths relative to the current
+/// directory
+pub fn changed_files(base: &str) -> Result<Vec<String>, String> {
+    ensure_repo(Path::new("."))?;
+
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--relative", base])
+        .output()
+        .map_err(|e| format!("unable to run git: {e}"))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Whether the working tree containing `dir` has no uncommitted changes, untracked files included.
+/// `state_dir` is left out, since unirun writes to it before the check runs.
+pub fn is_clean(dir: &Path, state_dir: &Path) -> Result<bool, String> {
+    // An empty path is the working directory, like for `Path::join`
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    ensure_repo(dir)?;
+
+    let mut command = Command::new("git");
+    command.args(["status", "--porcelain"]).current_dir(dir);
+    // Paths outside of `dir` aren't part of its working tree anyway
+    let relative = std::path::absolute(state_dir)
+        .ok()
+        .zip(std::path::absolute(dir).ok())
+        .and_then(|(state_dir, dir)| Some(state_dir.strip_prefix(dir).ok()?.to_owned()))
+        .filter(|relative| !relative.as_os_str().is_empty());
+    if let Some(relative) = relative {
+        command
+            .arg("--")
+            .arg(format!(":(exclude){}", relative.display()));
+    }
+    let output = command
+        .output()
+        .map_err(|e| format!("unable to run git: {e}"))?;
+
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_owned());
+    }
+
+    Ok(output.stdout.iter().all(u8::is_ascii_whitespace))
+}
+
+fn ensure_repo(dir: &Path) -> Result<(), String> {
+    let inside_repo = Command::new("git")
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("unable to run git: {e}"))?;
+    match inside_repo.status.success() {
+        true => Ok(()),
+        false => Err(String::from("not a git repository")),
+    }
+}
+
+/// Whether `file` is matched by `path`, either as the path itself, a file under it, or a glob
+pub fn path_matches(path: &str, file: &str) -> bool {
+    let path = path.trim_end_matches('/');
+
+    file == path
+        || file
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.starts_with('/'))
+        || glob_match(path, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_is_clean() {
+        let dir = TempDir::new("clean");
+        let state_dir = dir.join(".unirun");
+        let not_repo = is_clean(&dir, &state_dir);
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q"]);
+        let empty = is_clean(&dir, &state_dir);
+        std::fs::create_dir_all(&state_dir).unwrap();
+        std::fs::write(state_dir.join("plan.bin"), "").unwrap();
+        let with_state = is_clean(&dir, &state_dir);
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let untracked = is_clean(&dir, &state_dir);
+
+        assert_eq!(not_repo, Err(String::from("not a git repository")));
+        assert_eq!(empty, Ok(true));
+        assert_eq!(with_state, Ok(true));
+        assert_eq!(untracked, Ok(false));
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("src", "src/main.rs"));
+        assert!(path_matches("src/", "src/main.rs"));
+        assert!(path_matches("Cargo.toml", "Cargo.toml"));
+        assert!(path_matches("*.toml", "Cargo.toml"));
+        assert!(path_matches("src/*.rs", "src/main.rs"));
+        assert!(!path_matches("src", "srcs/main.rs"));
+        assert!(!path_matches("docs", "src/main.rs"));
+    }
+}
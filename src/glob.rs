@@ -0,0 +1,49 @@
+/// Match `name` against a glob pattern supporting `*` (any sequence) and `?` (any character)
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("PATH", "PATH"));
+        assert!(!glob_match("PATH", "PATHS"));
+        assert!(glob_match("CARGO_*", "CARGO_HOME"));
+        assert!(glob_match("CARGO_*", "CARGO_"));
+        assert!(!glob_match("CARGO_*", "RUSTUP_HOME"));
+        assert!(glob_match("*_HOME", "CARGO_HOME"));
+        assert!(glob_match("LC_?", "LC_A"));
+        assert!(!glob_match("LC_?", "LC_ALL"));
+        assert!(glob_match("*", "ANYTHING"));
+    }
+}
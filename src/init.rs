@@ -0,0 +1,91 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::runfile::{self, RUNFILE_NAMES};
+
+/// Runfile written by `uni init`
+pub const STARTER_RUNFILE: &str = r#"# Job that `uni run` runs when no job is given
+default: dev
+
+jobs:
+  build:
+    steps:
+      - run: echo "Replace this with your build command"
+
+  dev:
+    # Jobs listed in `needs` run first
+    needs: [build]
+    steps:
+      # Persistent steps keep running in the background until the run is stopped, e.g. with
+      # Ctrl-C
+      - run: npm run dev
+        persistent: true
+"#;
+
+#[derive(Clone, Debug)]
+pub struct RunfileExistsError {
+    path: PathBuf,
+}
+
+impl Error for RunfileExistsError {}
+
+impl Display for RunfileExistsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' already exists, pass --force to overwrite it",
+            self.path.file_name().unwrap_or_default().to_string_lossy()
+        )
+    }
+}
+
+/// Write the starter runfile to `dir`, unless it already has a runfile and `force` isn't set.
+/// With `force`, the existing runfile is overwritten, so `dir` doesn't end up with two of them.
+pub fn init(dir: &Path, force: bool) -> Result<PathBuf, Box<dyn Error>> {
+    let path = match runfile::discover(dir) {
+        Ok(path) if !force => return Err(Box::new(RunfileExistsError { path })),
+        Ok(path) => path,
+        Err(_) => dir.join(RUNFILE_NAMES[0]),
+    };
+    std::fs::write(&path, STARTER_RUNFILE)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{runfile::Runfile, testing::TempDir, validate::validate};
+
+    use super::*;
+
+    #[test]
+    fn test_starter_runfile_is_valid() {
+        let runfile: Runfile = serde_yaml::from_str(STARTER_RUNFILE).unwrap();
+
+        assert_eq!(validate(&runfile), Vec::new());
+    }
+
+    #[test]
+    fn test_init_refuses_to_overwrite() {
+        let dir = TempDir::new("init");
+        std::fs::write(dir.join("uni.yml"), "default: a\njobs: {}\n").unwrap();
+
+        let refused = init(&dir, false).map_err(|e| e.to_string());
+        let forced = init(&dir, true).unwrap();
+
+        assert_eq!(
+            refused,
+            Err(String::from(
+                "'uni.yml' already exists, pass --force to overwrite it"
+            ))
+        );
+        assert_eq!(forced, dir.join("uni.yml"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("uni.yml")).unwrap(),
+            STARTER_RUNFILE
+        );
+        assert!(!dir.join(RUNFILE_NAMES[0]).exists());
+    }
+}
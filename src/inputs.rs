@@ -0,0 +1,250 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::runfile::{JobId, Runfile};
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher hashes the same bytes the same way
+/// in every build, so hashes can be compared across versions of `uni`
+struct StableHasher(u64);
+
+impl StableHasher {
+    fn new() -> StableHasher {
+        StableHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    /// Write `bytes` after their length, so consecutive values can't run into each other
+    fn write_value(&mut self, bytes: &[u8]) {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+}
+
+/// Hash of what a run of the job depends on, or `None` if it has no `inputs`: the content of its
+/// `inputs` and env files under `root`, and its steps as written in the runfile. Directories are
+/// hashed with every file in them, and missing paths hash differently from empty files, so
+/// adding, removing and editing files all change the hash.
+pub fn hash_inputs(runfile: &Runfile, job_id: &str, root: &Path) -> Option<u64> {
+    let job = runfile.jobs.get(job_id)?;
+    if job.inputs.is_empty() {
+        return None;
+    }
+
+    let mut hasher = StableHasher::new();
+    let env_files = job
+        .env_file
+        .iter()
+        .chain(job.steps.iter().filter_map(|s| s.env_file.as_ref()));
+    for path in job.inputs.iter().chain(env_files) {
+        hasher.write_value(path.as_os_str().as_encoded_bytes());
+        hash_path(&root.join(path), &mut hasher);
+    }
+    // Maps serialize with sorted keys, so the same steps always hash the same
+    let steps = serde_json::to_value(&job.steps).unwrap_or_default();
+    hasher.write_value(steps.to_string().as_bytes());
+    Some(hasher.0)
+}
+
+fn hash_path(path: &Path, hasher: &mut StableHasher) {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .collect::<Vec<_>>();
+        entries.sort();
+        hasher.write(b"d");
+        hasher.write(&(entries.len() as u64).to_le_bytes());
+        for entry in entries {
+            hasher.write_value(entry.file_name().unwrap_or_default().as_encoded_bytes());
+            hash_path(&entry, hasher);
+        }
+    } else {
+        match std::fs::read(path) {
+            Ok(content) => {
+                hasher.write(b"f");
+                hasher.write_value(&content);
+            }
+            Err(_) => hasher.write(b"-"),
+        }
+    }
+}
+
+/// The jobs of `order` that need to run for `--only-changed-jobs`: those without `inputs`,
+/// those whose inputs hash differently from the last time they succeeded, and every job needing
+/// one of them. `order` must list the jobs a job needs before it.
+pub fn changed_jobs(
+    runfile: &Runfile,
+    order: &[JobId],
+    current: &HashMap<JobId, Option<u64>>,
+    previous: &HashMap<JobId, u64>,
+) -> HashSet<JobId> {
+    let mut changed = HashSet::new();
+    for id in order {
+        let own = match current.get(id).copied().flatten() {
+            Some(hash) => previous.get(id) != Some(&hash),
+            None => true,
+        };
+        let needed = runfile.jobs[id]
+            .needs
+            .iter()
+            .any(|need| changed.contains(&need.job));
+        if own || needed {
+            changed.insert(id.clone());
+        }
+    }
+    changed
+}
+
+/// Input hashes of the jobs that succeeded in previous runs, see [`hash_inputs`]
+pub struct InputHashes {
+    path: PathBuf,
+}
+
+impl InputHashes {
+    pub fn new(path: PathBuf) -> InputHashes {
+        InputHashes { path }
+    }
+
+    /// Missing or malformed state counts as no job having succeeded
+    pub fn load(&self) -> HashMap<JobId, u64> {
+        std::fs::read(&self.path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Store the hashes. The state is best-effort, so failures are ignored.
+    pub fn store(&self, hashes: &HashMap<JobId, u64>) {
+        if let Some(parent) = self.path.parent() {
+            _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_vec(hashes) {
+            _ = std::fs::write(&self.path, json);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        runfile::{Job, Step},
+        testing::TempDir,
+    };
+
+    use super::*;
+
+    fn runfile() -> Runfile {
+        let job = |inputs: &[&str], needs: &[&str]| Job {
+            inputs: inputs.iter().map(PathBuf::from).collect(),
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            ..Default::default()
+        };
+        Runfile {
+            jobs: HashMap::from([
+                ("api".into(), job(&["api"], &[])),
+                ("web".into(), job(&["web"], &[])),
+                ("test".into(), job(&["tests"], &["api"])),
+                ("ci".into(), job(&[], &["test", "web"])),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hash_inputs() {
+        let root = TempDir::new("inputs");
+        std::fs::create_dir_all(root.join("api/src")).unwrap();
+        std::fs::write(root.join("api/src/main.rs"), "fn main() {}").unwrap();
+        let runfile = runfile();
+
+        let hash = hash_inputs(&runfile, "api", &root);
+        assert!(hash.is_some());
+        assert_eq!(hash_inputs(&runfile, "api", &root), hash);
+        assert_eq!(hash_inputs(&runfile, "ci", &root), None);
+
+        std::fs::write(root.join("api/src/main.rs"), "fn main() { }").unwrap();
+        let edited = hash_inputs(&runfile, "api", &root);
+        assert_ne!(edited, hash);
+
+        std::fs::write(root.join("api/src/lib.rs"), "").unwrap();
+        assert_ne!(hash_inputs(&runfile, "api", &root), edited);
+    }
+
+    #[test]
+    fn test_hash_inputs_steps_and_env() {
+        let root = TempDir::new("inputs-steps");
+        let mut runfile = runfile();
+        let hash = hash_inputs(&runfile, "api", &root);
+
+        let api = runfile.jobs.get_mut("api").unwrap();
+        api.steps.push(Step {
+            command: "cargo build".into(),
+            env: HashMap::from([("PROFILE".into(), "dev".into())]),
+            ..Default::default()
+        });
+        let with_step = hash_inputs(&runfile, "api", &root);
+        assert_ne!(with_step, hash);
+
+        let api = runfile.jobs.get_mut("api").unwrap();
+        api.steps[0].env.insert("PROFILE".into(), "release".into());
+        let with_env = hash_inputs(&runfile, "api", &root);
+        assert_ne!(with_env, with_step);
+
+        runfile.jobs.get_mut("api").unwrap().env_file = Some(".env".into());
+        std::fs::write(root.join(".env"), "PROFILE=dev\n").unwrap();
+        let with_env_file = hash_inputs(&runfile, "api", &root);
+        assert_ne!(with_env_file, with_env);
+        std::fs::write(root.join(".env"), "PROFILE=test\n").unwrap();
+        assert_ne!(hash_inputs(&runfile, "api", &root), with_env_file);
+    }
+
+    #[test]
+    fn test_hash_inputs_is_stable() {
+        let root = TempDir::new("inputs-stable");
+        std::fs::create_dir_all(root.join("api")).unwrap();
+        std::fs::write(root.join("api/main.rs"), "fn main() {}").unwrap();
+
+        // Changes to the hash invalidate what earlier versions stored
+        assert_eq!(
+            hash_inputs(&runfile(), "api", &root),
+            Some(0x68f8_6e66_11ba_b854)
+        );
+    }
+
+    #[test]
+    fn test_changed_jobs() {
+        let runfile = runfile();
+        let order = ["api", "test", "web", "ci"].map(JobId::from);
+        let current = HashMap::from([
+            ("api".into(), Some(1)),
+            ("test".into(), Some(2)),
+            ("web".into(), Some(3)),
+            ("ci".into(), None),
+        ]);
+
+        // Nothing succeeded before, so everything runs
+        let changed = changed_jobs(&runfile, &order, &current, &HashMap::new());
+        assert_eq!(changed.len(), 4);
+
+        // Jobs without inputs always run
+        let previous = HashMap::from([("api".into(), 1), ("test".into(), 2), ("web".into(), 3)]);
+        let changed = changed_jobs(&runfile, &order, &current, &previous);
+        assert_eq!(changed, HashSet::from(["ci".into()]));
+
+        // A change runs the jobs needing it, but not the other branches
+        let previous = HashMap::from([("api".into(), 0), ("test".into(), 2), ("web".into(), 3)]);
+        let changed = changed_jobs(&runfile, &order, &current, &previous);
+        assert_eq!(
+            changed,
+            HashSet::from(["api".into(), "test".into(), "ci".into()])
+        );
+    }
+}
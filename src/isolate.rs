@@ -0,0 +1,127 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Temporary directory an isolated job runs in, removed along with everything in it once dropped
+#[derive(Debug)]
+pub struct IsolatedDir {
+    pub path: PathBuf,
+}
+
+impl IsolatedDir {
+    /// Create an empty directory for the job `job_id` and link its `inputs`, paths relative to
+    /// `project_dir`, into it at the same relative paths
+    pub fn create(
+        job_id: &str,
+        project_dir: &Path,
+        inputs: &[PathBuf],
+    ) -> std::io::Result<IsolatedDir> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        // Job ids may contain characters that aren't valid in file names on every platform
+        let name = job_id
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c,
+                false => '-',
+            })
+            .collect::<String>();
+        let path = std::env::temp_dir().join(format!(
+            "uni-{name}-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir(&path)?;
+        let dir = IsolatedDir { path };
+
+        for input in inputs {
+            let escapes = input
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+            if escapes {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "input '{}' must be a path inside the project",
+                        input.display()
+                    ),
+                ));
+            }
+
+            let src = project_dir.join(input);
+            if !src.exists() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("input '{}' doesn't exist", input.display()),
+                ));
+            }
+            let dst = dir.path.join(input);
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            link(&src, &dst)?;
+        }
+
+        Ok(dir)
+    }
+}
+
+impl Drop for IsolatedDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Make `src` available at `dst`, as a symlink where symlinks don't need special privileges and
+/// as a copy elsewhere
+fn link(src: &Path, dst: &Path) -> std::io::Result<()> {
+    #[cfg(target_family = "unix")]
+    {
+        std::os::unix::fs::symlink(src, dst)
+    }
+    #[cfg(not(target_family = "unix"))]
+    {
+        copy(src, dst)
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn copy(src: &Path, dst: &Path) -> std::io::Result<()> {
+    if !src.is_dir() {
+        return std::fs::copy(src, dst).map(|_| ());
+    }
+    std::fs::create_dir_all(dst)?;
+    for entry in std::fs::read_dir(src)? {
+        let entry = entry?;
+        copy(&entry.path(), &dst.join(entry.file_name()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_create() {
+        let project = TempDir::new("project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(project.join("src/main.rs"), "fn main() {}").unwrap();
+
+        let dir = IsolatedDir::create("test:unit", &project, &[PathBuf::from("src")]).unwrap();
+        let path = dir.path.clone();
+
+        assert!(path.join("src/main.rs").exists());
+        assert!(!path.join("Cargo.toml").exists());
+        drop(dir);
+        assert!(!path.exists());
+
+        let missing = IsolatedDir::create("test", &project, &[PathBuf::from("Cargo.toml")]);
+        assert!(missing.is_err());
+        let outside = IsolatedDir::create("test", &project, &[PathBuf::from("../etc")]);
+        assert!(outside.is_err());
+    }
+}
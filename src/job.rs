@@ -1,15 +1,50 @@
-use std::{collections::HashMap, error::Error, fmt::Display};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::{Child, ExitStatus},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant, SystemTime},
+};
 
 use itertools::Itertools;
 use petgraph::{
     acyclic::Acyclic,
-    algo::toposort,
+    algo::astar,
     data::Build,
     graph::{DiGraph, NodeIndex},
-    visit::{DfsPostOrder, NodeFiltered},
+    visit::DfsPostOrder,
+    Direction,
+};
+use tracing::{debug, debug_span, trace, trace_span, warn};
+
+use crate::{
+    env::{self, Env},
+    events::{Event, EventSink, Outcome},
+    git,
+    isolate::IsolatedDir,
+    keys::{SkipListener, SKIP_KEY},
+    output::CAPTURE_LIMIT,
+    plan::PlanCache,
+    report::{RunReport, Termination},
+    rng::Rng,
+    runfile::{Job, JobId, RestartPolicy, Runfile, Step, TimeoutAction},
+    secrets::{self, SecretProvider},
+    step::{
+        probe, stop, terminate_within, CommandNotFoundError, Process, Run, ScriptFile, StepContext,
+        StepError, SHUTDOWN_TIMEOUT,
+    },
+    watch::Snapshot,
 };
 
-use crate::{runfile::Runfile, step::Run};
+#[cfg(target_family = "unix")]
+use crate::step::send_signal;
 
 #[derive(Clone, Debug)]
 pub struct JobNotFoundError {
@@ -32,325 +67,2779 @@ impl Display for JobNotFoundError {
     }
 }
 
-pub fn run_job(runfile: &Runfile, job_id: &str) -> Result<(), Box<dyn Error>> {
+#[derive(Clone, Debug)]
+pub struct DuplicateJobError {
+    job_id: String,
+}
+
+impl Error for DuplicateJobError {}
+
+impl Display for DuplicateJobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Job '{}' is defined more than once", self.job_id)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CycleError {
+    job_id: String,
+    need: String,
+}
+
+impl Error for CycleError {}
+
+impl Display for CycleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Job '{}' needing '{}' creates a dependency cycle",
+            self.job_id, self.need
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ManualDefaultError {
+    job_id: String,
+}
+
+impl Error for ManualDefaultError {}
+
+impl Display for ManualDefaultError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Job '{}' is manual and can't be the default, run it by name instead",
+            self.job_id
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NoDefaultJobError;
+
+impl Error for NoDefaultJobError {}
+
+impl Display for NoDefaultJobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No job given and the runfile has no `default` or `defaults` job"
+        )
+    }
+}
+
+/// A run stopped by an error, e.g. a step that couldn't be started, along with the report of
+/// everything that ran until then. The job it stopped at is one of the report's failures.
+#[derive(Debug)]
+pub struct RunError {
+    message: String,
+    pub report: RunReport,
+}
+
+impl Error for RunError {}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    /// Only forward the parent environment variables listed in the runfile's `pass_env`
+    pub clean_env: bool,
+
+    /// Reuse run orders computed by previous runs of an unchanged runfile
+    pub plan_cache: Option<PlanCache>,
+
+    /// Print a reminder at this interval while a non-persistent step keeps running
+    pub heartbeat: Option<Duration>,
+
+    /// Expose the files changed since this git revision to steps as `UNIRUN_CHANGED_FILES`
+    pub changed_since: Option<String>,
+
+    /// Keep running jobs that don't depend on a failed job, instead of stopping at the first
+    /// failure
+    pub keep_going: bool,
+
+    /// Warn about non-persistent steps still running after this long
+    pub soft_timeout: Option<Duration>,
+
+    /// Fail non-persistent steps exceeding `soft_timeout` instead of only warning about them
+    pub fail_on_soft_timeout: bool,
+
+    /// How timed-out steps are stopped, unless a step sets `on_timeout`
+    pub timeout_action: TimeoutAction,
+
+    /// Jobs that succeeded in a previous run, which are skipped instead of running again
+    pub resume: HashSet<JobId>,
+
+    /// Jobs whose inputs didn't change since they last succeeded, which are skipped, see
+    /// [`crate::inputs::changed_jobs`]
+    pub unchanged: HashSet<JobId>,
+
+    /// Run every job in a temporary directory, as if it set `isolate`
+    pub isolate: bool,
+
+    /// Reload persistent steps when their job's `watch` paths change
+    pub watch: bool,
+
+    /// Directory state is kept in, which is never watched, see [`crate::state::StateDir`]
+    pub state_dir: PathBuf,
+
+    /// Retries for every non-persistent step that doesn't set its own `retries`
+    pub retry_all: Option<u32>,
+
+    /// How long steps with `ignore_signals` may keep running in total once the run is cancelled
+    pub signal_grace: Duration,
+
+    /// Variables loaded from env files, overridden by the inherited environment
+    pub file_env: Env,
+
+    /// Stop the run on SIGINT or SIGTERM. This registers process-wide signal handlers, so it is
+    /// left to the binary and off by default.
+    pub handle_signals: bool,
+
+    /// Run only the targets, assuming the jobs they need already ran, e.g. in an earlier CI stage
+    pub assume_deps_done: bool,
+
+    /// Seed for randomizing restart delays, so runs with the same seed restart at the same
+    /// times. Runs without one differ
+    pub seed: Option<u64>,
+
+    /// Stop the run once this flag is set. Running steps are terminated and no further jobs are
+    /// started.
+    pub cancel: Option<Arc<AtomicBool>>,
+
+    /// Listen for a key on an interactive stdin that skips the running non-persistent step. This
+    /// changes the terminal settings while the run lasts, so it is left to the binary and off by
+    /// default.
+    pub skip_key: bool,
+
+    /// Where `${{ secret('<path>') }}` expressions in step env are resolved from
+    pub secrets: Option<Arc<dyn SecretProvider>>,
+
+    /// Where the run reports jobs starting and finishing as it goes
+    pub events: Option<Arc<dyn EventSink>>,
+
+    /// Keep the output of non-persistent steps instead of printing it, and include it in the
+    /// `events` of jobs that fail
+    pub buffer_output: bool,
+
+    /// Print each step's command before running it, as if the runfile set `print_commands`
+    pub print_command: bool,
+}
+
+/// Pick the job to run when none was given on the command line. `override_id` (from
+/// `UNIRUN_DEFAULT`) takes precedence over the runfile's `defaults`, of which the first that is
+/// defined and not manual is picked.
+pub fn resolve_default_job(
+    runfile: &Runfile,
+    override_id: Option<&str>,
+) -> Result<JobId, Box<dyn Error>> {
+    let candidates = match override_id.filter(|id| !id.is_empty()) {
+        Some(id) => vec![id],
+        None => runfile.default_jobs(),
+    };
+
+    let mut first_error = None;
+    for (i, &id) in candidates.iter().enumerate() {
+        let error: Box<dyn Error> = match runfile.resolve_alias(id) {
+            Err(e) => Box::new(e),
+            Ok(job_id) => match runfile.jobs.get(job_id) {
+                None => Box::new(JobNotFoundError::new(job_id)),
+                Some(job) if job.manual => Box::new(ManualDefaultError {
+                    job_id: job_id.to_owned(),
+                }),
+                Some(_) => return Ok(job_id.to_owned()),
+            },
+        };
+        if let Some(next) = candidates.get(i + 1) {
+            warn!("{error}, trying default job '{next}'");
+        }
+        first_error.get_or_insert(error);
+    }
+
+    Err(first_error.unwrap_or_else(|| Box::new(NoDefaultJobError)))
+}
+
+#[derive(Clone, Debug)]
+pub struct NoMatchingJobsError {
+    tags: Vec<String>,
+}
+
+impl Error for NoMatchingJobsError {}
+
+impl Display for NoMatchingJobsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No jobs tagged {}",
+            self.tags.iter().map(|t| format!("'{t}'")).join(" or ")
+        )
+    }
+}
+
+/// Ids of all jobs carrying at least one of `tags`, sorted for a deterministic run order. Manual
+/// jobs are never selected.
+pub fn select_tagged(
+    runfile: &Runfile,
+    tags: &[String],
+) -> Result<Vec<JobId>, NoMatchingJobsError> {
+    let selected = runfile
+        .jobs
+        .iter()
+        .filter(|(_, job)| !job.manual && job.tags.iter().any(|t| tags.contains(t)))
+        .map(|(id, _)| id.to_owned())
+        .sorted()
+        .collect::<Vec<_>>();
+
+    if selected.is_empty() {
+        return Err(NoMatchingJobsError {
+            tags: tags.to_vec(),
+        });
+    }
+
+    Ok(selected)
+}
+
+struct PersistentStep<'a> {
+    job_id: &'a str,
+    index: usize,
+    step: Step,
+    ctx: StepContext,
+    proc: Child,
+    script: Option<ScriptFile>,
+    /// Set once the process has exited and been reaped
+    exited: bool,
+    restarts: u32,
+    restart_at: Option<Instant>,
+}
+
+impl PersistentStep<'_> {
+    fn should_restart(&self, status: ExitStatus) -> bool {
+        let wants_restart = match self.step.restart {
+            RestartPolicy::No => false,
+            RestartPolicy::OnFailure => !self.step.exit_ok(status),
+            RestartPolicy::Always => true,
+        };
+        wants_restart && self.restarts < self.step.max_restarts
+    }
+
+    /// Send the step its `reload_signal`, or restart it if it has none
+    fn reload(&mut self) {
+        match &self.step.reload_signal {
+            #[cfg(target_family = "unix")]
+            Some(signal) => match send_signal(&self.proc, signal) {
+                Ok(()) => println!("Sent {signal} to {}", self.ctx.label),
+                Err(e) => warn!("unable to send {signal} to {}: {e}", self.ctx.label),
+            },
+            #[cfg(not(target_family = "unix"))]
+            Some(_) => {
+                warn!(
+                    "`reload_signal` isn't supported on this platform, restarting {}",
+                    self.ctx.label
+                );
+                self.restart_now();
+            }
+            None => self.restart_now(),
+        }
+    }
+
+    fn restart_now(&mut self) {
+        println!("Restarting {}", self.ctx.label);
+        _ = stop(&mut self.proc, TimeoutAction::Terminate);
+        self.exited = true;
+        self.restart_at = Some(Instant::now());
+    }
+}
+
+/// Record that the job `id`, which started at `started`, has just finished, and report how it
+/// went along with its buffered `output` if it failed
+fn finish_job(
+    report: &mut RunReport,
+    options: &RunOptions,
+    id: &str,
+    started: SystemTime,
+    output: Option<&Mutex<Vec<u8>>>,
+) {
+    report.record_timing(id, started);
+    let Some(events) = &options.events else {
+        return;
+    };
+    let failed = report.is_failed(id);
+    let output = output
+        .filter(|_| failed)
+        .and_then(|o| o.lock().ok())
+        .map(|o| String::from_utf8_lossy(&o).into_owned());
+    events.event(&Event::Finished {
+        job: id.to_owned(),
+        outcome: match failed {
+            true => Outcome::Failed,
+            false => Outcome::Succeeded,
+        },
+        elapsed: started.elapsed().unwrap_or_default(),
+        output,
+    });
+}
+
+/// A run of [`run_job`] on a thread of its own, which can be cancelled from any other thread
+#[derive(Debug)]
+pub struct RunHandle {
+    cancel: Arc<AtomicBool>,
+    thread: JoinHandle<Result<RunReport, Box<dyn Error + Send + Sync>>>,
+}
+
+impl RunHandle {
+    /// Start running the `targets` jobs. The run is cancelled by [`RunHandle::cancel`] as well as
+    /// by `options.cancel` and, with `options.handle_signals`, by SIGINT and SIGTERM.
+    pub fn spawn(runfile: Runfile, targets: Vec<JobId>, mut options: RunOptions) -> RunHandle {
+        let cancel = Arc::clone(options.cancel.get_or_insert_with(Arc::default));
+        let thread = std::thread::spawn(move || {
+            run_job(&runfile, &targets, &options).map_err(|e| match e.downcast::<RunError>() {
+                Ok(e) => e as Box<dyn Error + Send + Sync>,
+                Err(e) => e.to_string().into(),
+            })
+        });
+        RunHandle { cancel, thread }
+    }
+
+    /// Stop the run the same way a signal does: no further jobs start, and running steps are
+    /// terminated, gracefully at first
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the run to end once its steps have exited, returning the report of the jobs that
+    /// ran, up to where it was cancelled. A run stopped by an error fails with a [`RunError`].
+    pub fn join(self) -> Result<RunReport, Box<dyn Error>> {
+        match self.thread.join() {
+            Ok(report) => report.map_err(|e| e as Box<dyn Error>),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+/// Run the `targets` jobs along with everything they need. Once a job has been reached, errors
+/// are [`RunError`]s.
+pub fn run_job(
+    runfile: &Runfile,
+    targets: &[JobId],
+    options: &RunOptions,
+) -> Result<RunReport, Box<dyn Error>> {
     use signal_hook::consts::{SIGINT, SIGTERM};
-    use std::sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+
+    let term = options.cancel.clone().unwrap_or_default();
+    if options.handle_signals {
+        let _span = debug_span!("signals").entered();
+        signal_hook::flag::register(SIGINT, Arc::clone(&term))?;
+        signal_hook::flag::register(SIGTERM, Arc::clone(&term))?;
+        debug!("handling SIGINT and SIGTERM");
+    }
+
+    let mut order = plan_run_order(runfile, targets, options.plan_cache.as_ref())?;
+    let skip_listener = options.skip_key.then(SkipListener::start).flatten();
+    if skip_listener.is_some() {
+        eprintln!("Press '{SKIP_KEY}' to skip the running step");
+    }
+    let mut assumed = Vec::new();
+    if options.assume_deps_done {
+        (order, assumed) = order.into_iter().partition(|id| targets.contains(id));
+        if !assumed.is_empty() {
+            let names = assumed.iter().map(|id| format!("'{id}'")).join(", ");
+            println!("Assuming dependencies already ran: {names}");
+        }
+        for id in assumed.iter() {
+            if runfile.jobs[id].steps.iter().any(|s| s.is_persistent()) {
+                warn!("dependency '{id}' has persistent steps, which won't be running");
+            }
+        }
+    }
+
+    let mut resumed = Vec::new();
+    if !options.resume.is_empty() {
+        (resumed, order) = order
+            .into_iter()
+            .partition(|id| options.resume.contains(id));
+        if !resumed.is_empty() {
+            let names = resumed.iter().map(|id| format!("'{id}'")).join(", ");
+            println!("Resuming, skipping jobs that already succeeded: {names}");
+        }
+        for id in resumed.iter() {
+            if runfile.jobs[id].steps.iter().any(|s| s.is_persistent()) {
+                warn!("job '{id}' has persistent steps, which won't be running");
+            }
+        }
+    }
+
+    let mut unchanged = Vec::new();
+    if !options.unchanged.is_empty() {
+        (unchanged, order) = order
+            .into_iter()
+            .partition(|id| options.unchanged.contains(id));
+        if !unchanged.is_empty() {
+            let names = unchanged.iter().map(|id| format!("'{id}'")).join(", ");
+            println!("Skipping jobs whose inputs didn't change: {names}");
+        }
+    }
+
+    let mut base_env = env::base(&options.file_env, options.clean_env, &runfile.pass_env);
+    if let Some(base) = &options.changed_since {
+        let files = git::changed_files(base).unwrap_or_else(|e| {
+            warn!("unable to detect changes since '{base}': {e}");
+            Vec::new()
+        });
+        base_env.insert("UNIRUN_CHANGED_FILES".into(), files.join("\n"));
+    }
+
+    let mut report = RunReport::default();
+    let mut persistent_steps: Vec<PersistentStep> = Vec::new();
+    let mut changes = HashMap::new();
+    // Returned once the persistent steps that already started have been stopped
+    let mut error: Option<Box<dyn Error>> = None;
+    // The job and step an error stops the run at, which count as failed
+    let mut running = None;
+    let mut rng = options.seed.map_or_else(Rng::from_entropy, Rng::new);
+    let cancelled_at = Arc::new(OnceLock::new());
+    // Kept until the run ends, since persistent steps keep running in them
+    let mut isolated_dirs = Vec::new();
+
+    'jobs: for (id, job) in order.iter().map(|j| (j, &runfile.jobs[j])) {
+        if term.load(Ordering::Relaxed) {
+            report.interrupted = true;
+            break;
+        }
+        if let Some((reason, inputs)) = skip_reason(job, &report, &mut changes) {
+            if let Some(events) = &options.events {
+                events.event(&Event::Skipped {
+                    job: id.clone(),
+                    reason: reason.clone(),
+                });
+            }
+            report.record_skip(id, reason, inputs);
+            continue;
+        }
+
+        let started = SystemTime::now();
+        let output = options
+            .buffer_output
+            .then(|| Arc::new(Mutex::new(Vec::new())));
+        running = Some((id, 0, started, output.clone()));
+        if let Some(events) = &options.events {
+            events.event(&Event::Started { job: id.clone() });
+        }
+
+        if job.requires_clean_git {
+            let problem = match git::is_clean(&runfile.dir, &options.state_dir) {
+                Ok(true) => None,
+                Ok(false) => Some(String::from("working tree has uncommitted changes")),
+                Err(e) => Some(format!("unable to check the working tree: {e}")),
+            };
+            if let Some(problem) = problem {
+                error = Some(format!("Unable to run job '{id}': {problem}").into());
+                break 'jobs;
+            }
+        }
+
+        let step_order = match job.step_order() {
+            Ok(step_order) => step_order,
+            Err(e) => {
+                error = Some(format!("Unable to run job '{id}': {e}").into());
+                break 'jobs;
+            }
+        };
+
+        let job_env = match job
+            .env_file
+            .as_ref()
+            .map(|path| env::load_file(&runfile.dir.join(path)))
+            .transpose()
+        {
+            Ok(job_env) => job_env.unwrap_or_default(),
+            Err(e) => {
+                error = Some(format!("Unable to run job '{id}': {e}").into());
+                break 'jobs;
+            }
+        };
+        let workdir = match job.isolate || options.isolate {
+            true => match IsolatedDir::create(id, &runfile.dir, &job.inputs) {
+                Ok(dir) => {
+                    let path = dir.path.clone();
+                    isolated_dirs.push(dir);
+                    Some(path)
+                }
+                Err(e) => {
+                    error = Some(format!("Unable to run job '{id}': {e}").into());
+                    break 'jobs;
+                }
+            },
+            false => None,
+        };
+        let deadline = job
+            .timeout
+            .or(runfile.default_timeout)
+            .map(|timeout| Instant::now() + timeout);
+        let mut prev_stdout = String::new();
+        for (position, &index) in step_order.iter().enumerate() {
+            let step = &job.steps[index];
+            running = Some((id, index, started, output.clone()));
+            if !step.runs_on(std::env::consts::OS) {
+                println!("{id}/{index} skipped (platform)");
+                report.record_step_skip(
+                    id,
+                    index,
+                    String::from("not for this platform"),
+                    vec![
+                        format!("platforms: {}", step.platforms.join(", ")),
+                        format!("os: {}", std::env::consts::OS),
+                    ],
+                );
+                continue;
+            }
+            // Only the steps ignoring signals, such as cleanup, still run once the run is cancelled
+            if term.load(Ordering::Relaxed) && (!step.ignore_signals || step.is_persistent()) {
+                continue;
+            }
+
+            let mut file_env = job_env.clone();
+            match step
+                .env_file
+                .as_ref()
+                .map(|path| env::load_file(&runfile.dir.join(path)))
+                .transpose()
+            {
+                Ok(step_env) => file_env.extend(step_env.unwrap_or_default()),
+                Err(e) => {
+                    error = Some(format!("Unable to run {id}/{index}: {e}").into());
+                    break 'jobs;
+                }
+            }
+            let mut step = resolve_step(runfile, step, &file_env);
+            let secrets = match secrets::resolve_env(&mut step.env, options.secrets.as_deref()) {
+                Ok(secrets) => secrets,
+                Err(e) => {
+                    error = Some(format!("Unable to run {id}/{index}: {e}").into());
+                    break 'jobs;
+                }
+            };
+
+            let mut env = base_env.clone();
+            env.insert("PREV_STDOUT".into(), std::mem::take(&mut prev_stdout));
+            if workdir.is_some() {
+                env.insert(
+                    "UNIRUN_PROJECT_DIR".into(),
+                    runfile.dir.to_string_lossy().into_owned(),
+                );
+            }
+            env.extend(step.env.clone());
+
+            // Capturing stdout keeps the step from writing to a terminal, so only do it when the
+            // next step uses it
+            let capture_stdout = step_order[position + 1..]
+                .iter()
+                .map(|&next| &job.steps[next])
+                .find(|next| next.runs_on(std::env::consts::OS))
+                .is_some_and(uses_prev_stdout)
+                .then(|| Arc::new(Mutex::new(Vec::new())));
+
+            let ctx = StepContext {
+                env,
+                label: format!("{id}/{index}"),
+                heartbeat: options.heartbeat,
+                cancel: Arc::clone(&term),
+                deadline,
+                soft_timeout: options.soft_timeout,
+                fail_on_soft_timeout: options.fail_on_soft_timeout,
+                timeout_action: step.on_timeout.unwrap_or(options.timeout_action),
+                signal_grace: step.ignore_signals.then_some(options.signal_grace),
+                cancelled_at: Arc::clone(&cancelled_at),
+                capture_stdout: capture_stdout.clone(),
+                workdir: match &step.workdir {
+                    Some(dir) => Some(workdir.as_ref().unwrap_or(&runfile.dir).join(dir)),
+                    None => workdir.clone(),
+                },
+                skip: skip_listener.as_ref().map(SkipListener::flag),
+                buffer_output: output.clone(),
+                print_command: options.print_command || runfile.print_commands,
+                secrets,
+            };
+
+            if let Some(reference) = &step.after_ready {
+                let Some(target) = job
+                    .step_index(reference)
+                    .filter(|i| step_order[..position].contains(i))
+                else {
+                    error = Some(
+                        format!(
+                            "Unable to run {}: `after_ready` step '{reference}' is not an earlier step of the job",
+                            ctx.label
+                        )
+                        .into(),
+                    );
+                    break 'jobs;
+                };
+                // The step may not have started, e.g. on another platform
+                let waited_on = persistent_steps
+                    .iter_mut()
+                    .find(|s| s.job_id == id && s.index == target);
+                if let Some(Err(reason)) = waited_on.map(|s| wait_ready(s, &term)) {
+                    let error = StepError::new(id, index, &step, &ctx.env, ExitStatus::default());
+                    report.record_rejection(error, reason);
+                    finish_job(&mut report, options, id, started, output.as_deref());
+                    if options.keep_going {
+                        continue 'jobs;
+                    }
+                    break 'jobs;
+                }
+            }
+
+            let retries = step
+                .retries
+                .or(options.retry_all)
+                .filter(|_| !step.is_persistent())
+                .unwrap_or(0);
+            let mut attempt = 0;
+            let process = loop {
+                let process = match step.run(&ctx) {
+                    Ok(process) => process,
+                    // Already names the step
+                    Err(e) if e.is::<CommandNotFoundError>() => {
+                        error = Some(e);
+                        break 'jobs;
+                    }
+                    Err(e) => {
+                        error = Some(format!("Unable to run {}: {e}", ctx.label).into());
+                        break 'jobs;
+                    }
+                };
+                let failed = match &process {
+                    Process::Finished(status) => {
+                        !step.exit_ok(*status)
+                            && step.stop_run.is_none_or(|code| status.code() != Some(code))
+                    }
+                    Process::Rejected { .. } => true,
+                    Process::Persistent { .. } => false,
+                };
+                if !failed || attempt >= retries || term.load(Ordering::Relaxed) {
+                    break process;
+                }
+                let delay = jitter(
+                    restart_backoff(step.retry_delay, attempt),
+                    step.retry_jitter,
+                    &mut rng,
+                );
+                attempt += 1;
+                if delay.is_zero() {
+                    println!("{} failed, retrying ({attempt} of {retries})", ctx.label);
+                } else {
+                    println!(
+                        "{} failed, retrying in {delay:?} ({attempt} of {retries})",
+                        ctx.label
+                    );
+                    sleep_unless_cancelled(delay, &term);
+                }
+            };
+            // A step stopped by cancellation did not fail on its own
+            if term.load(Ordering::Relaxed) {
+                cancelled_at.get_or_init(Instant::now);
+                if let Process::Persistent { mut proc, .. } = process {
+                    let grace = step.shutdown_timeout.unwrap_or(SHUTDOWN_TIMEOUT);
+                    _ = terminate_within(&mut proc, grace);
+                    continue;
+                }
+                if !step.ignore_signals {
+                    continue;
+                }
+            }
+
+            match process {
+                Process::Persistent { proc, script } => persistent_steps.push(PersistentStep {
+                    job_id: id,
+                    index,
+                    step,
+                    ctx,
+                    proc,
+                    script,
+                    exited: false,
+                    restarts: 0,
+                    restart_at: None,
+                }),
+                Process::Finished(status)
+                    if step.stop_run.is_some() && status.code() == step.stop_run =>
+                {
+                    report.record_success(id);
+                    finish_job(&mut report, options, id, started, output.as_deref());
+                    report.stopped_by = Some(id.clone());
+                    break 'jobs;
+                }
+                Process::Finished(status) if !step.exit_ok(status) => {
+                    report.record_failure(StepError::new(id, index, &step, &ctx.env, status));
+                    finish_job(&mut report, options, id, started, output.as_deref());
+                    if options.keep_going {
+                        continue 'jobs;
+                    }
+                    break 'jobs;
+                }
+                Process::Rejected { status, reason } => {
+                    let error = StepError::new(id, index, &step, &ctx.env, status);
+                    report.record_rejection(error, reason);
+                    finish_job(&mut report, options, id, started, output.as_deref());
+                    if options.keep_going {
+                        continue 'jobs;
+                    }
+                    break 'jobs;
+                }
+                Process::Finished(_) => {}
+            }
+            if let Some(captured) = capture_stdout.and_then(|c| c.lock().ok().map(|c| c.clone())) {
+                // A step checking its stdout captures all of it, but only as much as any other
+                // step's is passed on
+                let captured = &captured[..captured.len().min(CAPTURE_LIMIT)];
+                prev_stdout = String::from_utf8_lossy(captured).trim().to_owned();
+            }
+        }
+        if term.load(Ordering::Relaxed) {
+            report.record_cancel(id);
+            report.record_timing(id, started);
+            if let Some(events) = &options.events {
+                events.event(&Event::Finished {
+                    job: id.clone(),
+                    outcome: Outcome::Cancelled,
+                    elapsed: started.elapsed().unwrap_or_default(),
+                    output: None,
+                });
+            }
+            break;
+        }
+
+        report.record_success(id);
+        finish_job(&mut report, options, id, started, output.as_deref());
+    }
+    if let (Some(e), Some((id, index, started, output))) = (&error, running) {
+        let step = runfile.jobs[id]
+            .steps
+            .get(index)
+            .cloned()
+            .unwrap_or_default();
+        let failure = StepError::new(id, index, &step, &base_env, ExitStatus::default());
+        report.record_rejection(failure, e.to_string());
+        finish_job(&mut report, options, id, started, output.as_deref());
+    }
+
+    // Snapshots of the watched files of every job with persistent steps
+    let mut watched = persistent_steps
+        .iter()
+        .map(|s| s.job_id)
+        .filter(|&id| options.watch && !runfile.jobs[id].watch.is_empty())
+        .unique()
+        .map(|id| {
+            (
+                id,
+                runfile.watch_set(&runfile.jobs[id].watch, &options.state_dir),
+            )
+        })
+        .map(|(id, set)| (id, Snapshot::scan(Path::new("."), &set), set))
+        .collect::<Vec<_>>();
+
+    'outer: while error.is_none()
+        && report.stopped_by.is_none()
+        && (report.failures.is_empty() || options.keep_going)
+        && !persistent_steps.is_empty()
+        && !term.load(Ordering::Relaxed)
+    {
+        for step in persistent_steps.iter_mut() {
+            if let Some(restart_at) = step.restart_at {
+                if Instant::now() >= restart_at {
+                    match step.step.run(&step.ctx) {
+                        Ok(Process::Persistent { proc, script }) => {
+                            step.proc = proc;
+                            step.script = script;
+                            step.exited = false;
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            error =
+                                Some(format!("Unable to restart {}: {e}", step.ctx.label).into());
+                            break 'outer;
+                        }
+                    }
+                    step.restart_at = None;
+                }
+                continue;
+            }
+
+            let status = match step.proc.try_wait() {
+                Ok(status) => status,
+                Err(e) => {
+                    error = Some(e.into());
+                    break 'outer;
+                }
+            };
+            if let Some(status) = status {
+                step.exited = true;
+
+                if step.should_restart(status) {
+                    let delay = jitter(
+                        restart_backoff(step.step.restart_delay, step.restarts),
+                        step.step.restart_jitter,
+                        &mut rng,
+                    );
+                    step.restarts += 1;
+                    println!(
+                        "{} exited, restarting in {delay:?} (restart {} of {})",
+                        step.ctx.label, step.restarts, step.step.max_restarts
+                    );
+                    step.restart_at = Some(Instant::now() + delay);
+                    continue;
+                }
+
+                // A Ctrl-C from the terminal reaches steps too, which may exit on their own before
+                // the loop notices the run was cancelled. Those exits are part of the shutdown.
+                let during_shutdown = term.load(Ordering::Relaxed);
+                report.record_persistent_exit(step.job_id, step.index, status, during_shutdown);
+                if !step.step.exit_ok(status) && !during_shutdown {
+                    report.record_failure(StepError::new(
+                        step.job_id,
+                        step.index,
+                        &step.step,
+                        &step.ctx.env,
+                        status,
+                    ));
+                }
+                break 'outer;
+            }
+        }
+
+        for (job_id, snapshot, set) in watched.iter_mut() {
+            let newer = Snapshot::scan(Path::new("."), set);
+            let changes = snapshot.changes(&newer);
+            *snapshot = newer;
+            if changes.is_empty() {
+                continue;
+            }
+
+            println!("{} changed, reloading '{job_id}'", summarize(&changes, 3));
+            // Steps waiting to be restarted start with the changes anyway
+            for step in persistent_steps
+                .iter_mut()
+                .filter(|s| s.job_id == *job_id && s.restart_at.is_none())
+            {
+                step.reload();
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+    if term.load(Ordering::Relaxed) {
+        let _span = debug_span!("signals").entered();
+        debug!(
+            "run cancelled, stopping {} persistent steps",
+            persistent_steps.len()
+        );
+        println!("Run cancelled, terminating...")
+    }
+
+    let priorities = persistent_steps
+        .iter()
+        .map(|s| s.step.shutdown_priority)
+        .collect::<Vec<_>>();
+    for i in shutdown_order(&priorities) {
+        // An exited process has already been reaped, and its pid may belong to another process
+        let step = &mut persistent_steps[i];
+        if !step.exited {
+            let grace = step.step.shutdown_timeout.unwrap_or(SHUTDOWN_TIMEOUT);
+            if let Ok(status) = terminate_within(&mut step.proc, grace) {
+                report.record_persistent_exit(step.job_id, step.index, status, true);
+            }
+        }
+    }
+
+    // Recorded last, so the targets don't count them as skipped dependencies
+    for id in resumed {
+        report.record_skip(
+            &id,
+            String::from("succeeded in the previous run"),
+            vec![String::from("--resume")],
+        );
+    }
+    for id in assumed {
+        report.record_skip(
+            &id,
+            String::from("assumed to have run already"),
+            vec![String::from("--assume-deps-done")],
+        );
+    }
+    for id in unchanged {
+        report.record_skip(
+            &id,
+            String::from("inputs unchanged since it last succeeded"),
+            vec![String::from("--only-changed-jobs")],
+        );
+    }
+
+    match error {
+        Some(error) => Err(Box::new(RunError {
+            message: error.to_string(),
+            report,
+        })),
+        None => Ok(report),
+    }
+}
+
+/// Reason a job should not run, if any, along with the values it was determined from. A job is
+/// skipped when its `when_changed` gate matches no changed files, or when one of its dependencies
+/// failed or was skipped. Git diffs are cached in `changes` per base revision. If git can't be
+/// queried the gate fails open and the job runs.
+fn skip_reason(
+    job: &Job,
+    report: &RunReport,
+    changes: &mut HashMap<String, Option<Vec<String>>>,
+) -> Option<(String, Vec<String>)> {
+    // Needs whose condition doesn't hold never run, so they are neither failed nor skipped
+    let mut needs = job.needs.iter().map(|need| &need.job);
+    if let Some(dep) = needs.clone().find(|dep| report.is_failed(dep)) {
+        return Some((format!("dependency '{dep}' failed"), Vec::new()));
+    }
+    if let Some(dep) = needs.find(|dep| report.is_skipped(dep)) {
+        return Some((format!("dependency '{dep}' was skipped"), Vec::new()));
+    }
+
+    let gate = job.when_changed.as_ref()?;
+    let files = changes
+        .entry(gate.base.clone())
+        .or_insert_with(|| match git::changed_files(gate.base.as_str()) {
+            Ok(files) => Some(files),
+            Err(e) => {
+                warn!("unable to detect changes since '{}': {e}", gate.base);
+                None
+            }
+        })
+        .as_ref()?;
+
+    let changed = files
+        .iter()
+        .any(|file| gate.paths.iter().any(|path| git::path_matches(path, file)));
+
+    (!changed).then(|| {
+        let inputs = vec![
+            format!("paths: {}", gate.paths.join(", ")),
+            format!("changed files: {}", summarize(files, 5)),
+        ];
+        (format!("no changes since '{}'", gate.base), inputs)
+    })
+}
+
+/// The first `limit` of `items`, mentioning how many more there are
+pub fn summarize(items: &[String], limit: usize) -> String {
+    if items.is_empty() {
+        return String::from("none");
+    }
+    let mut summary = items.iter().take(limit).join(", ");
+    if items.len() > limit {
+        summary.push_str(&format!(" and {} more", items.len() - limit));
+    }
+    summary
+}
+
+/// Whether a step refers to `$PREV_STDOUT`, the output of the step before it
+fn uses_prev_stdout(step: &Step) -> bool {
+    [
+        step.command.as_str(),
+        step.script.as_deref().unwrap_or_default(),
+    ]
+    .into_iter()
+    .chain(step.env.values().map(String::as_str))
+    .any(|value| value.contains("PREV_STDOUT"))
+}
+
+/// Apply the runfile's defaults and the variables of the job's and step's env files, `file_env`,
+/// to a step, so it can run without knowing about them
+pub fn resolve_step(runfile: &Runfile, step: &Step, file_env: &Env) -> Step {
+    let mut step = step.clone();
+    step.max_output_bytes = step.max_output_bytes.or(runfile.max_output_bytes);
+    step.shell = step.shell.or_else(|| runfile.default_shell.clone());
+    // Still relative, so it's resolved against the runfile's directory or an isolated job's
+    step.workdir = step.workdir.or_else(|| runfile.default_workdir.clone());
+
+    let mut env = runfile.default_env.clone();
+    env.extend(file_env.clone());
+    env.extend(step.env);
+    step.env = env;
+
+    step
+}
+
+/// Wait until the `ready` command of a persistent step succeeds. Fails if the step exits or
+/// `ready_timeout` passes first, and returns early if the run is cancelled.
+fn wait_ready(step: &mut PersistentStep, cancel: &AtomicBool) -> Result<(), String> {
+    let Some(check) = &step.step.ready else {
+        return Ok(());
     };
 
-    let term = Arc::new(AtomicBool::new(false));
-    signal_hook::flag::register(SIGINT, Arc::clone(&term))?;
-    signal_hook::flag::register(SIGTERM, Arc::clone(&term))?;
+    println!("Waiting for {} to be ready", step.ctx.label);
+    let deadline = Instant::now() + step.step.ready_timeout;
+    loop {
+        if cancel.load(Ordering::Relaxed) || probe(check, &step.ctx.env) {
+            return Ok(());
+        }
+        if let Ok(Some(status)) = step.proc.try_wait() {
+            return Err(format!(
+                "{} {} before it was ready",
+                step.ctx.label,
+                Termination::from_status(status)
+            ));
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "{} was not ready after {:?}",
+                step.ctx.label, step.step.ready_timeout
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Delay before restarting a persistent step that has already been restarted `restarts` times.
+/// The delay doubles with every restart, up to 32 times the base delay.
+fn restart_backoff(delay: Duration, restarts: u32) -> Duration {
+    delay.saturating_mul(1 << restarts.min(5))
+}
+
+/// Sleep for `delay`, or until `cancel` is set
+fn sleep_unless_cancelled(delay: Duration, cancel: &AtomicBool) {
+    let until = Instant::now() + delay;
+    while !cancel.load(Ordering::Relaxed) {
+        let Some(left) = until.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        std::thread::sleep(left.min(Duration::from_millis(100)));
+    }
+}
+
+/// Randomly lengthen or shorten `delay` by up to `fraction` of it
+fn jitter(delay: Duration, fraction: f64, rng: &mut Rng) -> Duration {
+    let fraction = fraction.clamp(0.0, 1.0);
+    if fraction == 0.0 {
+        return delay;
+    }
+    delay.mul_f64(1.0 + fraction * (2.0 * rng.next_f64() - 1.0))
+}
+
+/// Order in which persistent steps are stopped, given their shutdown priorities in start order.
+/// Higher priorities are stopped first, and steps with equal priority are stopped in reverse of
+/// the order they were started, so a step's dependencies outlive it.
+fn shutdown_order(priorities: &[i32]) -> Vec<usize> {
+    let mut order = (0..priorities.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse((priorities[i], i)));
+    order
+}
+
+/// Order in which the `targets` jobs and everything they need run, reusing the order stored in
+/// `cache` if there is one
+pub fn plan_run_order(
+    runfile: &Runfile,
+    targets: &[JobId],
+    cache: Option<&PlanCache>,
+) -> Result<Vec<JobId>, Box<dyn Error>> {
+    let _span = debug_span!("plan_run_order", ?targets).entered();
+    if let Some(order) = cache.and_then(|c| c.load(targets)) {
+        debug!("cached run order: {}", order.join(", "));
+        return Ok(order);
+    }
+
+    let graph = collect_dependencies(runfile)?;
+    let order = create_run_order(runfile, targets, graph)?;
+    debug!("run order: {}", order.join(", "));
+
+    if let Some(cache) = cache {
+        cache.store(targets, &order);
+    }
+
+    Ok(order)
+}
+
+/// Find the shortest chain of `needs` leading from `target` to `job`, or `None` if `job` is not
+/// a dependency of `target`
+pub fn find_dependency_path(
+    runfile: &Runfile,
+    target: &str,
+    job: &str,
+) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    let graph = collect_dependencies(runfile)?;
+    let start_node = find_node(&graph, target)?;
+    let end_node = find_node(&graph, job)?;
+
+    let path = astar(&graph, start_node, |n| n == end_node, |_| 1, |_| 0);
+
+    Ok(path.map(|(_, nodes)| {
+        nodes
+            .into_iter()
+            .filter_map(|n| graph.node_weight(n).cloned())
+            .collect()
+    }))
+}
+
+/// Node of the job `job_id`. Job ids are unique in a runfile, but the graph itself doesn't
+/// guarantee it, so duplicates are reported rather than mistaken for a missing job.
+pub fn find_node(
+    graph: &Acyclic<DiGraph<String, ()>>,
+    job_id: &str,
+) -> Result<NodeIndex, Box<dyn Error>> {
+    let mut nodes = graph
+        .nodes_iter()
+        .filter(|&n| graph.node_weight(n).is_some_and(|id| id == job_id));
+
+    match (nodes.next(), nodes.next()) {
+        (Some(node), None) => Ok(node),
+        (None, _) => Err(Box::new(JobNotFoundError::new(job_id))),
+        (Some(_), Some(_)) => Err(Box::new(DuplicateJobError {
+            job_id: job_id.to_owned(),
+        })),
+    }
+}
+
+/// Order in which the `targets` and the jobs they need run. Among jobs whose dependencies have
+/// all run, the one with the highest `weight` goes first, with ties broken by id.
+fn create_run_order(
+    runfile: &Runfile,
+    targets: &[JobId],
+    graph: Acyclic<DiGraph<String, ()>>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut reachable_nodes = HashSet::new();
+    let mut dfs = DfsPostOrder::empty(&graph);
+    for target in targets {
+        dfs.move_to(find_node(&graph, target)?);
+        while let Some(node) = dfs.next(&graph) {
+            reachable_nodes.insert(node);
+        }
+    }
+
+    let priority = |node: NodeIndex| -> Result<_, Box<dyn Error>> {
+        let id = graph
+            .node_weight(node)
+            .cloned()
+            .ok_or_else(|| format!("No job in the dependency graph at node {}", node.index()))?;
+        let weight = runfile.jobs.get(&id).map_or(0, |job| job.weight);
+        Ok((weight, Reverse(id), node))
+    };
+
+    // Edges point from a job to the jobs it needs, all of which are reachable as well
+    let mut pending_needs = reachable_nodes
+        .iter()
+        .map(|&node| (node, graph.neighbors(node).count()))
+        .collect::<HashMap<_, _>>();
+    let mut ready = pending_needs
+        .iter()
+        .filter(|(_, &count)| count == 0)
+        .map(|(&node, _)| priority(node))
+        .collect::<Result<BinaryHeap<_>, _>>()?;
+
+    let mut order = Vec::with_capacity(reachable_nodes.len());
+    while let Some((_, Reverse(id), node)) = ready.pop() {
+        order.push(id);
+        for dependent in graph.neighbors_directed(node, Direction::Incoming) {
+            if let Some(count) = pending_needs.get_mut(&dependent) {
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(priority(dependent)?);
+                }
+            }
+        }
+    }
+
+    Ok(order)
+}
+
+pub fn collect_dependencies(
+    runfile: &Runfile,
+) -> Result<Acyclic<DiGraph<String, ()>>, Box<dyn Error>> {
+    let _span = trace_span!("collect_dependencies").entered();
+    let mut deps: Acyclic<DiGraph<String, ()>> = Acyclic::new();
+
+    let nodes = runfile
+        .jobs
+        .keys()
+        .map(|id| (id.as_str(), deps.add_node(id.to_owned())))
+        .collect::<HashMap<&str, NodeIndex>>();
+
+    for (id, job) in runfile.jobs.iter() {
+        let Some(&job_node) = nodes.get(id.as_str()) else {
+            return Err(Box::new(JobNotFoundError::new(id)));
+        };
+
+        for dep in runfile.needs(job)? {
+            let Some(&dep_node) = nodes.get(dep.as_str()) else {
+                return Err(Box::new(JobNotFoundError::new(dep)));
+            };
+            trace!("'{id}' needs '{dep}'");
+
+            if deps.try_add_edge(job_node, dep_node, ()).is_err() {
+                return Err(Box::new(CycleError {
+                    job_id: id.to_owned(),
+                    need: dep.to_owned(),
+                }));
+            }
+        }
+    }
+
+    Ok(deps)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        report::ExitCodePolicy,
+        runfile::{Need, WhenChanged},
+        testing::TempDir,
+    };
+
+    use super::*;
+
+    /// Step running `command`
+    fn step(command: impl Into<String>) -> Step {
+        Step {
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Job with a single step running `command`, needing `needs`
+    fn job(command: &str, needs: &[&str]) -> Job {
+        Job {
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            steps: vec![step(command)],
+            ..Default::default()
+        }
+    }
+
+    /// Runfile whose default job, `start`, runs `steps`
+    fn single_job(steps: Vec<Step>) -> Runfile {
+        Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([(
+                "start".into(),
+                Job {
+                    steps,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    /// Run the default job of `runfile`
+    fn run_default(runfile: &Runfile, options: &RunOptions) -> Result<RunReport, Box<dyn Error>> {
+        run_job(runfile, std::slice::from_ref(&runfile.default), options)
+    }
+
+    #[test]
+    fn test_create_run_order_single_node() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([(
+                "start".into(),
+                Job {
+                    name: None,
+                    needs: Vec::new(),
+                    steps: Vec::new(),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        assert_eq!(graph.node_count(), 1, "Incorrect number of nodes in graph");
+        assert_eq!(graph.edge_count(), 0, "Incorrect number of edges in graph");
+
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order.len(), 1, "Incorrect number of items in run order");
+    }
+
+    #[test]
+    fn test_create_run_order_two_nodes() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "start".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        assert_eq!(graph.node_count(), 2, "Incorrect number of nodes in graph");
+        assert_eq!(graph.edge_count(), 1, "Incorrect number of edges in graph");
+
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order.len(), 2, "Incorrect number of items in run order");
+    }
+
+    #[test]
+    fn test_create_run_order_nodes_diverge() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "start".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
+        assert_eq!(graph.edge_count(), 2, "Incorrect number of edges in graph");
+
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order.len(), 2, "Incorrect number of items in run order");
+        assert_eq!(order, vec![String::from("build"), String::from("start")])
+    }
+
+    #[test]
+    fn test_create_run_order_chained_nodes() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "start".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["test".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
+        assert_eq!(graph.edge_count(), 2, "Incorrect number of edges in graph");
+
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order.len(), 3, "Incorrect number of items in run order");
+        assert_eq!(
+            order,
+            vec![
+                String::from("build"),
+                String::from("test"),
+                String::from("start")
+            ]
+        )
+    }
+
+    #[test]
+    fn test_create_run_order_overlapping_needs() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "start".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into(), "test".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
+        assert_eq!(graph.edge_count(), 3, "Incorrect number of edges in graph");
+
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order.len(), 3, "Incorrect number of items in run order");
+        assert_eq!(
+            order,
+            vec![
+                String::from("build"),
+                String::from("test"),
+                String::from("start")
+            ]
+        )
+    }
+
+    #[test]
+    fn test_create_run_order_multiple_targets() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                ("build".into(), Job::default()),
+                (
+                    "test".into(),
+                    Job {
+                        needs: vec!["build".into()],
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "lint".into(),
+                    Job {
+                        needs: vec!["build".into()],
+                        ..Default::default()
+                    },
+                ),
+                ("start".into(), Job::default()),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+
+        let order = create_run_order(
+            &runfile,
+            &[String::from("lint"), String::from("test")],
+            graph,
+        )
+        .unwrap();
+
+        assert_eq!(order.len(), 3, "Incorrect number of items in run order");
+        assert_eq!(order[0], "build");
+        assert!(order.contains(&String::from("lint")));
+        assert!(order.contains(&String::from("test")));
+    }
+
+    #[test]
+    fn test_create_run_order_weights() {
+        let job = |needs: &[&str], weight: u32| Job {
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            weight,
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("all"),
+            jobs: HashMap::from([
+                ("all".into(), job(&["docs", "lint", "test", "e2e"], 0)),
+                ("build".into(), job(&[], 0)),
+                ("docs".into(), job(&[], 0)),
+                ("lint".into(), job(&[], 0)),
+                ("test".into(), job(&["build"], 0)),
+                ("e2e".into(), job(&["build"], 10)),
+            ]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+        let order =
+            create_run_order(&runfile, std::slice::from_ref(&runfile.default), graph).unwrap();
+
+        assert_eq!(order, vec!["build", "e2e", "docs", "lint", "test", "all"]);
+    }
+
+    #[test]
+    fn test_select_tagged() {
+        let tagged = |tags: &[&str]| Job {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                ("api".into(), tagged(&["smoke"])),
+                ("web".into(), tagged(&["smoke", "slow"])),
+                ("e2e".into(), tagged(&["slow"])),
+                ("start".into(), tagged(&[])),
+                (
+                    "db:reset".into(),
+                    Job {
+                        manual: true,
+                        ..tagged(&["smoke"])
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            select_tagged(&runfile, &["smoke".into()]).unwrap(),
+            vec![String::from("api"), String::from("web")]
+        );
+        assert_eq!(
+            select_tagged(&runfile, &["smoke".into(), "slow".into()]).unwrap(),
+            vec![
+                String::from("api"),
+                String::from("e2e"),
+                String::from("web")
+            ]
+        );
+        assert!(select_tagged(&runfile, &["smok".into()]).is_err());
+    }
+
+    #[test]
+    fn test_find_dependency_path() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["build".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "lint".into(),
+                    Job {
+                        name: None,
+                        needs: Vec::new(),
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "start".into(),
+                    Job {
+                        name: None,
+                        needs: vec!["test".into()],
+                        steps: Vec::new(),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let path = find_dependency_path(&runfile, "start", "build").unwrap();
+        assert_eq!(
+            path,
+            Some(vec![
+                String::from("start"),
+                String::from("test"),
+                String::from("build")
+            ])
+        );
+
+        let path = find_dependency_path(&runfile, "start", "lint").unwrap();
+        assert_eq!(path, None);
+
+        assert!(find_dependency_path(&runfile, "start", "missing").is_err());
+    }
+
+    #[test]
+    fn test_collect_dependencies_cycle() {
+        let job = |need: &str| Job {
+            needs: vec![need.into()],
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("a"),
+            jobs: HashMap::from([("a".into(), job("b")), ("b".into(), job("a"))]),
+            ..Default::default()
+        };
+
+        let err = collect_dependencies(&runfile).unwrap_err();
+        assert!(err.to_string().contains("creates a dependency cycle"));
+        assert!(run_job(&runfile, &[String::from("a")], &RunOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_collect_dependencies_conditional() {
+        let runfile = Runfile {
+            default: String::from("deploy"),
+            jobs: HashMap::from([
+                ("test".into(), Job::default()),
+                (
+                    "deploy".into(),
+                    Job {
+                        needs: vec![Need {
+                            job: "test".into(),
+                            condition: Some("${{ params.skip_tests }} != true".into()),
+                        }],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            params: HashMap::from([("skip_tests".into(), "true".into())]),
+            ..Default::default()
+        };
+
+        let graph = collect_dependencies(&runfile).unwrap();
+        assert_eq!(graph.edge_count(), 0);
+
+        let runfile = Runfile {
+            params: HashMap::new(),
+            ..runfile
+        };
+        let err = collect_dependencies(&runfile).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("param 'skip_tests' is not defined"));
+    }
+
+    #[test]
+    fn test_resolve_default_job() {
+        let runfile = Runfile {
+            default: String::from("start"),
+            jobs: HashMap::from([
+                ("start".into(), Job::default()),
+                ("test".into(), Job::default()),
+                (
+                    "db:reset".into(),
+                    Job {
+                        manual: true,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(resolve_default_job(&runfile, None).unwrap(), "start");
+        assert_eq!(resolve_default_job(&runfile, Some("")).unwrap(), "start");
+        assert_eq!(resolve_default_job(&runfile, Some("test")).unwrap(), "test");
+        assert!(resolve_default_job(&runfile, Some("missing")).is_err());
+        assert!(resolve_default_job(&runfile, Some("db:reset")).is_err());
+    }
+
+    #[test]
+    fn test_resolve_default_job_defaults() {
+        let mut runfile = Runfile {
+            defaults: vec!["missing".into(), "db:reset".into(), "test".into()],
+            jobs: HashMap::from([
+                ("start".into(), Job::default()),
+                ("test".into(), Job::default()),
+                (
+                    "db:reset".into(),
+                    Job {
+                        manual: true,
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(resolve_default_job(&runfile, None).unwrap(), "test");
+        assert_eq!(
+            resolve_default_job(&runfile, Some("start")).unwrap(),
+            "start"
+        );
+
+        runfile.defaults = vec!["missing".into(), "db:reset".into()];
+        let err = resolve_default_job(&runfile, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            JobNotFoundError::new("missing").to_string()
+        );
+
+        runfile.defaults.clear();
+        let err = resolve_default_job(&runfile, None).unwrap_err();
+        assert_eq!(err.to_string(), NoDefaultJobError.to_string());
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let gated = Job {
+            when_changed: Some(WhenChanged {
+                paths: vec!["src".into()],
+                base: "main".into(),
+            }),
+            ..Default::default()
+        };
+        let mut changes = HashMap::from([("main".into(), Some(vec!["docs/README.md".into()]))]);
+        let mut report = RunReport::default();
+
+        assert_eq!(
+            skip_reason(&gated, &report, &mut changes),
+            Some((
+                String::from("no changes since 'main'"),
+                vec![
+                    String::from("paths: src"),
+                    String::from("changed files: docs/README.md")
+                ]
+            ))
+        );
+
+        changes.insert("main".into(), Some(vec!["src/main.rs".into()]));
+        assert_eq!(skip_reason(&gated, &report, &mut changes), None);
+
+        // Unable to query git, so the gate fails open
+        changes.insert("main".into(), None);
+        assert_eq!(skip_reason(&gated, &report, &mut changes), None);
+
+        report.record_skip("build", "no changes".into(), Vec::new());
+        let dependent = Job {
+            needs: vec!["build".into()],
+            ..Default::default()
+        };
+        assert_eq!(
+            skip_reason(&dependent, &report, &mut changes),
+            Some((String::from("dependency 'build' was skipped"), Vec::new()))
+        );
+    }
+
+    #[test]
+    fn test_summarize() {
+        let items = ["a", "b", "c"].map(String::from);
+
+        assert_eq!(summarize(&items, 5), "a, b, c");
+        assert_eq!(summarize(&items, 2), "a, b and 1 more");
+        assert_eq!(summarize(&[], 2), "none");
+    }
+
+    #[test]
+    fn test_resolve_step_defaults() {
+        let runfile = Runfile {
+            default_shell: Some("bash -eu".into()),
+            default_env: HashMap::from([
+                ("RUST_LOG".into(), "info".into()),
+                ("CI".into(), "1".into()),
+            ]),
+            default_workdir: Some("packages/app".into()),
+            ..Default::default()
+        };
+        let step = Step {
+            command: "cargo test".into(),
+            env: HashMap::from([("RUST_LOG".into(), "debug".into())]),
+            ..Default::default()
+        };
+
+        let file_env = Env::from([
+            ("CI".into(), "true".into()),
+            ("TOKEN".into(), "secret".into()),
+        ]);
+        let resolved = resolve_step(&runfile, &step, &file_env);
+
+        assert_eq!(resolved.shell.as_deref(), Some("bash -eu"));
+        assert_eq!(resolved.workdir, Some(PathBuf::from("packages/app")));
+        assert_eq!(resolved.env["RUST_LOG"], "debug");
+        assert_eq!(resolved.env["CI"], "true");
+        assert_eq!(resolved.env["TOKEN"], "secret");
+
+        let step = Step {
+            shell: Some("zsh".into()),
+            workdir: Some("tools".into()),
+            ..step
+        };
+        let resolved = resolve_step(&runfile, &step, &Env::new());
+        assert_eq!(resolved.shell.as_deref(), Some("zsh"));
+        assert_eq!(resolved.workdir, Some(PathBuf::from("tools")));
+    }
+
+    #[test]
+    fn test_jitter() {
+        let delay = Duration::from_secs(10);
+        let mut rng = Rng::new(1);
+
+        assert_eq!(jitter(delay, 0.0, &mut rng), delay);
+        for _ in 0..100 {
+            let jittered = jitter(delay, 0.5, &mut rng);
+            assert!(jittered >= Duration::from_secs(5) && jittered <= Duration::from_secs(15));
+        }
+        assert_eq!(
+            jitter(delay, 0.5, &mut Rng::new(3)),
+            jitter(delay, 0.5, &mut Rng::new(3))
+        );
+    }
+
+    #[test]
+    fn test_restart_backoff() {
+        let delay = Duration::from_secs(1);
+
+        assert_eq!(restart_backoff(delay, 0), Duration::from_secs(1));
+        assert_eq!(restart_backoff(delay, 1), Duration::from_secs(2));
+        assert_eq!(restart_backoff(delay, 3), Duration::from_secs(8));
+        assert_eq!(restart_backoff(delay, 5), Duration::from_secs(32));
+        assert_eq!(restart_backoff(delay, 50), Duration::from_secs(32));
+    }
+
+    #[test]
+    fn test_shutdown_order_reverses_start_order() {
+        // A database started before the server that depends on it must be stopped after it
+        let order = shutdown_order(&[0, 0]);
+
+        assert_eq!(order, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_shutdown_order_priority() {
+        let order = shutdown_order(&[0, 0, 10, -5, 10]);
+
+        assert_eq!(order, vec![4, 2, 1, 0, 3]);
+    }
+
+    #[test]
+    fn test_find_node_duplicate() {
+        let mut graph: Acyclic<DiGraph<String, ()>> = Acyclic::new();
+        graph.add_node("build".into());
+        graph.add_node("build".into());
+
+        let err = find_node(&graph, "build").unwrap_err();
+        assert!(err.downcast_ref::<DuplicateJobError>().is_some());
+
+        let err = find_node(&graph, "test").unwrap_err();
+        assert!(err.downcast_ref::<JobNotFoundError>().is_some());
+    }
+
+    #[test]
+    fn test_plan_run_order_cache_hit() {
+        let dir = TempDir::new("plan");
+        let cache = PlanCache::new(dir.join("plan.bin"), "content");
+
+        let runfile = single_job(Vec::new());
+
+        let order = plan_run_order(&runfile, &[String::from("start")], Some(&cache)).unwrap();
+        assert_eq!(order, vec![String::from("start")]);
+
+        // A cache hit must not consult the runfile at all
+        let order =
+            plan_run_order(&Runfile::default(), &[String::from("start")], Some(&cache)).unwrap();
+        assert_eq!(order, vec![String::from("start")]);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_cancelled() {
+        let dir = TempDir::new("cancel");
+        let marker = dir.join("marker");
+        let runfile = single_job(vec![step(format!("touch {}", marker.display()))]);
+        let options = RunOptions {
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..Default::default()
+        };
+
+        let report = run_default(&runfile, &options).unwrap();
+
+        assert!(report.failures.is_empty());
+        assert!(!marker.exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_handle_cancel() {
+        let dir = TempDir::new("handle");
+        let marker = dir.join("marker");
+        let runfile = Runfile {
+            default: String::from("slow"),
+            jobs: HashMap::from([
+                ("slow".into(), job("sleep 10", &[])),
+                (
+                    "after".into(),
+                    job(&format!("touch {}", marker.display()), &["slow"]),
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let handle = RunHandle::spawn(runfile, vec!["after".into()], RunOptions::default());
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!handle.is_finished());
+        handle.cancel();
+        let report = handle.join().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(report.succeeded.is_empty());
+        assert_eq!(report.cancelled, vec![String::from("slow")]);
+        assert!(!marker.exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_cancelled_persistent_exit() {
+        let runfile = single_job(vec![Step {
+            persistent: Some(true),
+            ..step("sh -c \"trap 'exit 3' TERM; while true; do sleep 0.05; done\"")
+        }]);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = RunOptions {
+            cancel: Some(Arc::clone(&cancel)),
+            ..Default::default()
+        };
+
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let report = run_default(&runfile, &options).unwrap();
+        canceller.join().unwrap();
+
+        // Exiting non-zero because the run stopped it isn't a failure
+        assert!(report.failures.is_empty());
+        assert_eq!(report.persistent_exits.len(), 1);
+        assert_eq!(
+            report.persistent_exits[0].termination,
+            Termination::Exited(3)
+        );
+        assert!(report.persistent_exits[0].during_shutdown);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_shutdown_timeout() {
+        // Takes half a second to shut down, longer than the default allows
+        let server = |shutdown_timeout| Step {
+            persistent: Some(true),
+            shutdown_timeout,
+            ..step("sh -c \"trap 'sleep 0.5; exit 0' TERM; while true; do sleep 0.05; done\"")
+        };
+        let runfile = single_job(vec![server(None), server(Some(Duration::from_secs(5)))]);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = RunOptions {
+            cancel: Some(Arc::clone(&cancel)),
+            ..Default::default()
+        };
+
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let report = run_default(&runfile, &options).unwrap();
+        canceller.join().unwrap();
+
+        let mut exits = report
+            .persistent_exits
+            .iter()
+            .map(|e| (e.step, e.termination))
+            .collect::<Vec<_>>();
+        exits.sort_by_key(|&(step, _)| step);
+        assert_eq!(
+            exits,
+            vec![(0, Termination::Signaled(9)), (1, Termination::Exited(0))]
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_watch_reloads_persistent_steps() {
+        let dir = TempDir::new("reload");
+        let server = |script: String, reload_signal: Option<&str>| Step {
+            persistent: Some(true),
+            reload_signal: reload_signal.map(String::from),
+            ..step(format!(
+                "sh -c \"{script}; while true; do sleep 0.05; done\""
+            ))
+        };
+        let mut runfile = single_job(vec![
+            server(
+                format!("trap 'touch {}' HUP", dir.join("reloaded").display()),
+                Some("SIGHUP"),
+            ),
+            server(format!("echo >> {}", dir.join("starts").display()), None),
+        ]);
+        runfile.jobs.get_mut("start").unwrap().watch =
+            vec![dir.join("config").display().to_string()];
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = RunOptions {
+            cancel: Some(Arc::clone(&cancel)),
+            watch: true,
+            ..Default::default()
+        };
+
+        let config = dir.join("config");
+        let changer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(700));
+            std::fs::write(config, "reload").unwrap();
+            std::thread::sleep(Duration::from_millis(2500));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let report = run_default(&runfile, &options).unwrap();
+        changer.join().unwrap();
+
+        assert!(report.failures.is_empty());
+        assert!(dir.join("reloaded").exists());
+        let starts = std::fs::read_to_string(dir.join("starts")).unwrap();
+        assert_eq!(starts.lines().count(), 2);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_cancelled_runs_cleanup() {
+        let dir = TempDir::new("cleanup");
+        let cleanup = |command: String| Step {
+            ignore_signals: true,
+            ..step(command)
+        };
+        let runfile = single_job(vec![
+            step("sleep 10"),
+            step(format!("touch {}", dir.join("skipped").display())),
+            cleanup(format!("touch {}", dir.join("cleaned").display())),
+            cleanup("sleep 10".into()),
+        ]);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let options = RunOptions {
+            cancel: Some(Arc::clone(&cancel)),
+            signal_grace: Duration::from_millis(500),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            cancel.store(true, Ordering::Relaxed);
+        });
+        let report = run_default(&runfile, &options).unwrap();
+        canceller.join().unwrap();
+
+        assert!(!dir.join("skipped").exists());
+        assert!(dir.join("cleaned").exists());
+        assert_eq!(
+            report.failures[0].reason.as_deref(),
+            Some("still running 500ms after the run was cancelled")
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_step_needs() {
+        let dir = TempDir::new("step-needs");
+        let marker = dir.join("marker");
+        let runfile = single_job(vec![
+            Step {
+                needs: vec![String::from("fixtures")],
+                ..step(format!("test -f {}", marker.display()))
+            },
+            Step {
+                name: Some(String::from("fixtures")),
+                ..step(format!("touch {}", marker.display()))
+            },
+        ]);
+
+        let report = run_default(&runfile, &RunOptions::default()).unwrap();
+
+        assert!(report.failures.is_empty());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_keep_going() {
+        let runfile = Runfile {
+            default: String::from("all"),
+            jobs: HashMap::from([
+                ("all".into(), job("true", &["broken", "fine"])),
+                ("broken".into(), job("false", &[])),
+                ("fine".into(), job("true", &[])),
+            ]),
+            ..Default::default()
+        };
+        let options = RunOptions {
+            keep_going: true,
+            ..Default::default()
+        };
+
+        let report = run_default(&runfile, &options).unwrap();
+
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].error.job_id, "broken");
+        assert_eq!(report.succeeded, vec![String::from("fine")]);
+        assert_eq!(report.skipped[0].job_id, "all");
+        assert_eq!(report.skipped[0].reason, "dependency 'broken' failed");
+        // The exit code comes from every failure needed by the target, even though the last job
+        // to finish succeeded
+        assert_eq!(report.timings.last().unwrap().job_id, "fine");
+        assert_eq!(report.exit_code(ExitCodePolicy::Last), 1);
+        // Skipped jobs never ran, so they have no timing
+        let mut timed = report
+            .timings
+            .iter()
+            .map(|t| t.job_id.as_str())
+            .collect::<Vec<_>>();
+        timed.sort();
+        assert_eq!(timed, vec!["broken", "fine"]);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_after_ready() {
+        let dir = TempDir::new("ready");
+        let marker = dir.join("marker");
+        let check = format!("test -f {}", marker.display());
+        let runfile = |ready: &str, ready_timeout| {
+            single_job(vec![
+                Step {
+                    name: Some("db".into()),
+                    persistent: Some(true),
+                    ready: Some(ready.into()),
+                    ready_timeout,
+                    ..step(format!(
+                        "sh -c 'sleep 0.2; touch {}; exec sleep 1'",
+                        marker.display()
+                    ))
+                },
+                Step {
+                    after_ready: Some("db".into()),
+                    ..step(check.clone())
+                },
+            ])
+        };
+
+        let report = run_default(
+            &runfile(&check, Duration::from_secs(5)),
+            &RunOptions::default(),
+        );
+        let report = report.unwrap();
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        _ = std::fs::remove_file(&marker);
 
-    let graph = collect_dependencies(runfile)?;
-    let order = create_run_order(job_id, graph)?;
+        let never_ready = runfile("false", Duration::from_millis(100));
+        let report = run_default(&never_ready, &RunOptions::default()).unwrap();
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(
+            report.failures[0].reason.as_deref(),
+            Some("start/0 was not ready after 100ms")
+        );
+    }
 
-    let mut persistent_steps = Vec::new();
+    #[derive(Debug, Default)]
+    struct RecordedEvents(Mutex<Vec<Event>>);
 
-    for job in order.iter().map(|j| &runfile.jobs[j]) {
-        for step in job.steps.iter() {
-            if let Some(proc) = step.run()? {
-                persistent_steps.push(proc);
-            }
+    impl EventSink for RecordedEvents {
+        fn event(&self, event: &Event) {
+            self.0.lock().unwrap().push(event.clone());
         }
     }
 
-    'outer: while !persistent_steps.is_empty() && !term.load(Ordering::Relaxed) {
-        for proc in persistent_steps.iter_mut() {
-            if (proc.try_wait()?).is_some() {
-                break 'outer;
-            }
+    impl RecordedEvents {
+        /// One line per event, with the output of failed jobs
+        fn summary(&self) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|event| match event {
+                    Event::Started { job } => format!("started {job}"),
+                    Event::Finished {
+                        job,
+                        outcome,
+                        output,
+                        ..
+                    } => format!("{outcome:?} {job} {output:?}"),
+                    Event::Skipped { job, .. } => format!("skipped {job}"),
+                })
+                .collect()
         }
-        std::thread::sleep(std::time::Duration::from_millis(500));
-    }
-    if term.load(Ordering::Relaxed) {
-        println!("Exit signal received, terminating...")
     }
 
-    for proc in persistent_steps.iter_mut() {
-        #[cfg(target_family = "unix")]
-        {
-            use nix::sys::signal::{self, Signal};
-            use nix::unistd::Pid;
-            // We don't really care if we failed to kill a process, and exiting on error would
-            // prevent subsequent processes from being stopped.
-            _ = signal::kill(Pid::from_raw(proc.id() as i32), Signal::SIGTERM);
-
-            std::thread::sleep(std::time::Duration::from_millis(250));
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_events() {
+        let runfile = Runfile {
+            default: String::from("deploy"),
+            jobs: HashMap::from([
+                ("build".into(), job("echo built", &[])),
+                (
+                    "test".into(),
+                    job("sh -c 'echo boom >&2; exit 1'", &["build"]),
+                ),
+                ("deploy".into(), job("true", &["test"])),
+            ]),
+            ..Default::default()
         };
-        #[cfg(target_family = "windows")]
-        {
-            _ = proc.kill();
-        }
+        let events = Arc::new(RecordedEvents::default());
+        let options = RunOptions {
+            keep_going: true,
+            buffer_output: true,
+            events: Some(Arc::clone(&events) as Arc<dyn EventSink>),
+            ..Default::default()
+        };
+
+        run_default(&runfile, &options).unwrap();
+
+        assert_eq!(
+            events.summary(),
+            vec![
+                "started build",
+                "Succeeded build None",
+                "started test",
+                "Failed test Some(\"boom\\n\")",
+                "skipped deploy",
+            ]
+        );
     }
 
-    Ok(())
-}
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_error_events() {
+        let runfile = single_job(vec![
+            step("echo partial"),
+            Step {
+                workdir: Some(PathBuf::from("/nonexistent/unirun")),
+                ..step("true")
+            },
+        ]);
+        let events = Arc::new(RecordedEvents::default());
+        let options = RunOptions {
+            buffer_output: true,
+            events: Some(Arc::clone(&events) as Arc<dyn EventSink>),
+            ..Default::default()
+        };
 
-fn create_run_order(
-    job_id: &str,
-    graph: Acyclic<DiGraph<String, ()>>,
-) -> Result<Vec<String>, Box<dyn Error>> {
-    let Ok(start_node) = graph
-        .nodes_iter()
-        .filter(|&n| graph.node_weight(n).unwrap() == job_id)
-        .exactly_one()
-    else {
-        return Err(Box::new(JobNotFoundError::new(job_id)));
-    };
+        run_default(&runfile, &options).unwrap_err();
 
-    let mut reachable_nodes = vec![];
-    let mut dfs = DfsPostOrder::new(&graph, start_node);
-    while let Some(node) = dfs.next(&graph) {
-        reachable_nodes.push(node);
+        assert_eq!(
+            events.summary(),
+            vec!["started start", "Failed start Some(\"partial\\n\")"]
+        );
     }
 
-    let filtered_graph = NodeFiltered::from_fn(&graph, |node| reachable_nodes.contains(&node));
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_success_exit_codes() {
+        let exit = |code: i32| Step {
+            success_exit_codes: vec![0, 1, 3],
+            ..step(format!("sh -c 'exit {code}'"))
+        };
+        let runfile = single_job(vec![exit(1), exit(3), exit(2), exit(0)]);
 
-    let results = toposort(&filtered_graph, None).unwrap();
+        let report = run_default(&runfile, &RunOptions::default()).unwrap();
 
-    Ok(results
-        .into_iter()
-        .rev()
-        .map(|n| graph.node_weight(n).unwrap().to_owned())
-        .collect())
-}
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].error.step, 2);
+        assert_eq!(report.failures[0].error.exit_code, 2);
+    }
 
-fn collect_dependencies(runfile: &Runfile) -> Result<Acyclic<DiGraph<String, ()>>, Box<dyn Error>> {
-    let mut deps: Acyclic<DiGraph<String, ()>> = Acyclic::new();
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_retries() {
+        let dir = TempDir::new("retries");
+        // Fails the first time it runs for a marker, and succeeds from then on
+        let flaky = |marker: &str, retries: Option<u32>| Step {
+            raw: true,
+            retries,
+            ..step(format!(
+                "sh -c 'test -e {0} || {{ touch {0}; exit 1; }}'",
+                dir.join(marker).display()
+            ))
+        };
+        let run = |step: Step, retry_all: Option<u32>| {
+            let options = RunOptions {
+                retry_all,
+                ..Default::default()
+            };
+            run_default(&single_job(vec![step]), &options).unwrap()
+        };
 
-    let nodes = runfile
-        .jobs
-        .keys()
-        .map(|id| (id.as_str(), deps.add_node(id.to_owned())))
-        .collect::<HashMap<&str, NodeIndex>>();
+        let report = run(flaky("a", Some(1)), None);
+        assert!(report.failures.is_empty());
 
-    for (id, job) in runfile.jobs.iter() {
-        let Some(&job_node) = nodes.get(id.as_str()) else {
-            return Err(Box::new(JobNotFoundError::new(id)));
-        };
+        let report = run(flaky("b", None), Some(2));
+        assert!(report.failures.is_empty());
 
-        for dep in job.needs.iter() {
-            let Some(&dep_node) = nodes.get(dep.as_str()) else {
-                return Err(Box::new(JobNotFoundError::new(dep)));
-            };
+        // A step's own `retries` takes precedence
+        let report = run(flaky("c", Some(0)), Some(2));
+        assert_eq!(report.failures.len(), 1);
 
-            if let Err(e) = deps.try_add_edge(job_node, dep_node, ()) {
-                eprintln!("unable to add edge to acyclic graph: {:?}", e);
-            }
-        }
+        let delayed = Step {
+            retry_delay: Duration::from_millis(300),
+            ..flaky("d", Some(1))
+        };
+        let started = Instant::now();
+        let report = run(delayed, None);
+        assert!(report.failures.is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(300));
     }
 
-    Ok(deps)
-}
-
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_isolated() {
+        let dir = TempDir::new("isolated");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        // The project is the runfile's directory rather than the working directory
+        let mut runfile = Runfile {
+            dir: dir.to_path_buf(),
+            ..single_job(vec![
+                step("test ! -e notes.txt"),
+                step("test -e src/main.rs"),
+                step("test -e $UNIRUN_PROJECT_DIR/notes.txt"),
+            ])
+        };
+        let job = runfile.jobs.get_mut("start").unwrap();
+        job.isolate = true;
+        job.inputs = vec![PathBuf::from("src")];
 
-    use crate::runfile::Job;
+        let report = run_default(&runfile, &RunOptions::default()).unwrap();
 
-    use super::*;
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+    }
 
+    #[cfg(target_family = "unix")]
     #[test]
-    fn test_create_run_order_single_node() {
+    fn test_run_job_resume() {
         let runfile = Runfile {
-            default: String::from("start"),
-            jobs: HashMap::from([(
-                "start".into(),
-                Job {
-                    name: None,
-                    needs: Vec::new(),
-                    steps: Vec::new(),
-                },
-            )]),
+            default: String::from("test"),
+            jobs: HashMap::from([
+                ("build".into(), job("false", &[])),
+                ("test".into(), job("true", &["build"])),
+            ]),
+            ..Default::default()
+        };
+        let options = RunOptions {
+            resume: HashSet::from([JobId::from("build")]),
+            ..Default::default()
         };
 
-        let graph = collect_dependencies(&runfile).unwrap();
+        let report = run_default(&runfile, &options).unwrap();
 
-        assert_eq!(graph.node_count(), 1, "Incorrect number of nodes in graph");
-        assert_eq!(graph.edge_count(), 0, "Incorrect number of edges in graph");
+        assert_eq!(report.succeeded, vec![String::from("test")]);
+        assert_eq!(report.skipped[0].reason, "succeeded in the previous run");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_step_workdir() {
+        let dir = TempDir::new("step-workdir");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let run = |workdir: PathBuf| {
+            let runfile = Runfile {
+                dir: dir.to_path_buf(),
+                ..single_job(vec![Step {
+                    workdir: Some(workdir),
+                    ..step("sh -c 'pwd > where.txt'")
+                }])
+            };
+            run_default(&runfile, &RunOptions::default())
+        };
 
-        let order = create_run_order(runfile.default.as_str(), graph).unwrap();
+        run(dir.to_path_buf()).unwrap();
+        let written = std::fs::read_to_string(dir.join("where.txt")).unwrap();
+        assert_eq!(Path::new(written.trim()), dir.canonicalize().unwrap());
 
-        assert_eq!(order.len(), 1, "Incorrect number of items in run order");
+        // Relative to the runfile rather than the working directory, e.g. after a deferred `-C`
+        run(PathBuf::from("sub")).unwrap();
+        let written = std::fs::read_to_string(dir.join("sub/where.txt")).unwrap();
+        assert_eq!(
+            Path::new(written.trim()),
+            dir.join("sub").canonicalize().unwrap()
+        );
+
+        let missing = dir.join("missing");
+        let err = run(missing.clone()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Unable to run start/0: working directory '{}' does not exist",
+                missing.display()
+            )
+        );
     }
 
     #[test]
-    fn test_create_run_order_two_nodes() {
+    fn test_run_job_error_report() {
         let runfile = Runfile {
             default: String::from("start"),
             jobs: HashMap::from([
-                (
-                    "build".into(),
-                    Job {
-                        name: None,
-                        needs: Vec::new(),
-                        steps: Vec::new(),
-                    },
-                ),
+                ("build".into(), job("true", &[])),
                 (
                     "start".into(),
                     Job {
-                        name: None,
+                        steps: vec![
+                            step("true"),
+                            Step {
+                                workdir: Some(PathBuf::from("/nonexistent/unirun")),
+                                ..step("true")
+                            },
+                        ],
                         needs: vec!["build".into()],
-                        steps: Vec::new(),
+                        ..Default::default()
                     },
                 ),
             ]),
+            ..Default::default()
         };
 
-        let graph = collect_dependencies(&runfile).unwrap();
+        let err = run_default(&runfile, &RunOptions::default()).unwrap_err();
+        let err = err.downcast::<RunError>().unwrap();
+        let message =
+            "Unable to run start/1: working directory '/nonexistent/unirun' does not exist";
+        assert_eq!(err.to_string(), message);
+        assert_eq!(err.report.succeeded, vec![JobId::from("build")]);
+        let failure = &err.report.failures[0];
+        assert_eq!(
+            (failure.error.job_id.as_str(), failure.error.step),
+            ("start", 1)
+        );
+        assert_eq!(failure.error.exit_code, 0);
+        assert_eq!(failure.reason.as_deref(), Some(message));
+        assert!(err.report.timings.iter().any(|t| t.job_id == "start"));
+    }
 
-        assert_eq!(graph.node_count(), 2, "Incorrect number of nodes in graph");
-        assert_eq!(graph.edge_count(), 1, "Incorrect number of edges in graph");
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_requires_clean_git() {
+        let dir = TempDir::new("requires-clean-git");
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap()
+        };
+        git(&["init", "-q"]);
+        std::fs::write(dir.join("uni.yaml"), "").unwrap();
+        git(&["add", "uni.yaml"]);
+        git(&[
+            "-c",
+            "user.name=uni",
+            "-c",
+            "user.email=uni@localhost",
+            "commit",
+            "-qm",
+            "init",
+        ]);
+        // Written by the run itself before any job runs, like the plan cache
+        std::fs::create_dir_all(dir.join(".unirun")).unwrap();
+        std::fs::write(dir.join(".unirun/plan.bin"), "").unwrap();
 
-        let order = create_run_order(runfile.default.as_str(), graph).unwrap();
+        let mut runfile = Runfile {
+            dir: dir.to_path_buf(),
+            ..single_job(vec![step("true")])
+        };
+        runfile.jobs.get_mut("start").unwrap().requires_clean_git = true;
+        let options = RunOptions {
+            state_dir: dir.join(".unirun"),
+            ..Default::default()
+        };
 
-        assert_eq!(order.len(), 2, "Incorrect number of items in run order");
+        let report = run_default(&runfile, &options).unwrap();
+        assert_eq!(report.succeeded, vec![String::from("start")]);
+
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let err = run_default(&runfile, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unable to run job 'start': working tree has uncommitted changes"
+        );
     }
 
+    #[cfg(target_family = "unix")]
     #[test]
-    fn test_create_run_order_nodes_diverge() {
+    fn test_run_job_unchanged() {
         let runfile = Runfile {
-            default: String::from("start"),
+            default: String::from("ci"),
             jobs: HashMap::from([
-                (
-                    "build".into(),
-                    Job {
-                        name: None,
-                        needs: Vec::new(),
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "start".into(),
-                    Job {
-                        name: None,
-                        needs: vec!["build".into()],
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "test".into(),
-                    Job {
-                        name: None,
-                        needs: vec!["build".into()],
-                        steps: Vec::new(),
-                    },
-                ),
+                ("api".into(), job("true", &[])),
+                ("web".into(), job("false", &[])),
+                ("ci".into(), job("true", &["api", "web"])),
             ]),
+            ..Default::default()
+        };
+        let options = RunOptions {
+            unchanged: HashSet::from([JobId::from("web")]),
+            ..Default::default()
         };
 
-        let graph = collect_dependencies(&runfile).unwrap();
+        let report = run_default(&runfile, &options).unwrap();
 
-        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
-        assert_eq!(graph.edge_count(), 2, "Incorrect number of edges in graph");
+        assert!(report.failures.is_empty());
+        assert_eq!(
+            report.succeeded,
+            vec![String::from("api"), String::from("ci")]
+        );
+        assert_eq!(report.skipped[0].job_id, "web");
+        assert_eq!(
+            report.skipped[0].reason,
+            "inputs unchanged since it last succeeded"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_prev_stdout() {
+        let runfile = single_job(vec![
+            Step {
+                raw: true,
+                ..step(r#"sh -c 'test -z "$PREV_STDOUT"'"#)
+            },
+            step("echo '  target/out.txt '"),
+            step("test $PREV_STDOUT = target/out.txt"),
+            Step {
+                raw: true,
+                ..step(r#"sh -c 'test -z "$PREV_STDOUT"'"#)
+            },
+        ]);
+        let options = RunOptions {
+            file_env: std::env::vars().collect(),
+            ..Default::default()
+        };
 
-        let order = create_run_order(runfile.default.as_str(), graph).unwrap();
+        let report = run_default(&runfile, &options).unwrap();
 
-        assert_eq!(order.len(), 2, "Incorrect number of items in run order");
-        assert_eq!(order, vec![String::from("build"), String::from("start")])
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
     }
 
+    #[cfg(target_family = "unix")]
     #[test]
-    fn test_create_run_order_chained_nodes() {
+    fn test_run_job_env_files() {
+        let dir = TempDir::new("env-files");
+        std::fs::write(dir.join("job.env"), "A=job\nB=job\nC=job\n").unwrap();
+        std::fs::write(dir.join("step.env"), "B=step\nC=step\n").unwrap();
+
+        // Relative to the runfile rather than the working directory
+        let run = |job_file: &str| {
+            let mut runfile = Runfile {
+                dir: dir.to_path_buf(),
+                ..single_job(vec![Step {
+                    env_file: Some("step.env".into()),
+                    env: HashMap::from([("C".into(), "inline".into())]),
+                    ..step("test $A-$B-$C = job-step-inline")
+                }])
+            };
+            runfile.jobs.get_mut("start").unwrap().env_file = Some(job_file.into());
+            run_default(&runfile, &RunOptions::default())
+        };
+
+        let report = run("job.env").unwrap();
+        let missing = run("missing.env").unwrap_err();
+
+        assert!(report.failures.is_empty(), "{:?}", report.failures);
+        assert!(missing
+            .to_string()
+            .starts_with("Unable to run job 'start': Unable to load env file"));
+    }
+
+    #[test]
+    fn test_run_job_assume_deps_done() {
         let runfile = Runfile {
-            default: String::from("start"),
+            default: String::from("deploy"),
             jobs: HashMap::from([
-                (
-                    "build".into(),
-                    Job {
-                        name: None,
-                        needs: Vec::new(),
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "test".into(),
-                    Job {
-                        name: None,
-                        needs: vec!["build".into()],
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "start".into(),
-                    Job {
-                        name: None,
-                        needs: vec!["test".into()],
-                        steps: Vec::new(),
-                    },
-                ),
+                ("deploy".into(), job("true", &["build"])),
+                ("build".into(), job("false", &[])),
             ]),
+            ..Default::default()
+        };
+        let options = RunOptions {
+            assume_deps_done: true,
+            ..Default::default()
         };
 
-        let graph = collect_dependencies(&runfile).unwrap();
-
-        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
-        assert_eq!(graph.edge_count(), 2, "Incorrect number of edges in graph");
-
-        let order = create_run_order(runfile.default.as_str(), graph).unwrap();
+        let report = run_default(&runfile, &options).unwrap();
 
-        assert_eq!(order.len(), 3, "Incorrect number of items in run order");
-        assert_eq!(
-            order,
-            vec![
-                String::from("build"),
-                String::from("test"),
-                String::from("start")
-            ]
-        )
+        assert!(report.failures.is_empty());
+        assert_eq!(report.succeeded, vec![String::from("deploy")]);
+        assert_eq!(report.skipped[0].job_id, "build");
     }
 
+    #[cfg(target_family = "unix")]
     #[test]
-    fn test_create_run_order_overlapping_needs() {
+    fn test_run_job_stopped_by_step() {
         let runfile = Runfile {
-            default: String::from("start"),
+            default: String::from("deploy"),
             jobs: HashMap::from([
                 (
-                    "build".into(),
-                    Job {
-                        name: None,
-                        needs: Vec::new(),
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "test".into(),
-                    Job {
-                        name: None,
-                        needs: vec!["build".into()],
-                        steps: Vec::new(),
-                    },
-                ),
-                (
-                    "start".into(),
+                    "gate".into(),
                     Job {
-                        name: None,
-                        needs: vec!["build".into(), "test".into()],
-                        steps: Vec::new(),
+                        steps: vec![
+                            Step {
+                                stop_run: Some(78),
+                                ..step("sh -c 'exit 78'")
+                            },
+                            step("false"),
+                        ],
+                        ..Default::default()
                     },
                 ),
+                ("deploy".into(), job("false", &["gate"])),
             ]),
+            ..Default::default()
         };
 
-        let graph = collect_dependencies(&runfile).unwrap();
+        let report = run_default(&runfile, &RunOptions::default()).unwrap();
 
-        assert_eq!(graph.node_count(), 3, "Incorrect number of nodes in graph");
-        assert_eq!(graph.edge_count(), 3, "Incorrect number of edges in graph");
+        assert_eq!(report.stopped_by.as_deref(), Some("gate"));
+        assert!(report.failures.is_empty());
+        assert_eq!(report.succeeded, vec![String::from("gate")]);
+    }
 
-        let order = create_run_order(runfile.default.as_str(), graph).unwrap();
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_run_job_spawn_failure_stops_persistent_steps() {
+        use nix::{sys::signal, unistd::Pid};
 
-        assert_eq!(order.len(), 3, "Incorrect number of items in run order");
-        assert_eq!(
-            order,
-            vec![
-                String::from("build"),
-                String::from("test"),
-                String::from("start")
-            ]
+        let dir = TempDir::new("spawn");
+        let pid_file = dir.join("pid");
+        let runfile = single_job(vec![
+            Step {
+                persistent: Some(true),
+                startup_delay: Duration::from_millis(200),
+                raw: true,
+                ..step(format!(
+                    "sh -c 'echo $$ > {}; exec sleep 30'",
+                    pid_file.display()
+                ))
+            },
+            step("./no-such-server"),
+        ]);
+
+        let result = run_default(&runfile, &RunOptions::default());
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("command not found: `./no-such-server` (in step start/1)"));
+        let pid = Pid::from_raw(pid.trim().parse().unwrap());
+        assert!(signal::kill(pid, None).is_err());
+    }
+
+    /// Benchmark of the plan cache on a 1000 job chain. Run with `cargo test -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_plan_run_order_cache() {
+        const JOBS: usize = 1000;
+
+        let runfile = Runfile {
+            default: format!("job{}", JOBS - 1),
+            jobs: (0..JOBS)
+                .map(|i| {
+                    (
+                        format!("job{i}"),
+                        Job {
+                            name: None,
+                            needs: i
+                                .checked_sub(1)
+                                .map(|j| Need::from(format!("job{j}")))
+                                .into_iter()
+                                .collect(),
+                            steps: Vec::new(),
+                            ..Default::default()
+                        },
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let dir = TempDir::new("bench");
+        let cache = PlanCache::new(dir.join("plan.bin"), "content");
+
+        let start = std::time::Instant::now();
+        let cold = plan_run_order(
+            &runfile,
+            std::slice::from_ref(&runfile.default),
+            Some(&cache),
+        )
+        .unwrap();
+        let cold_time = start.elapsed();
+
+        let start = std::time::Instant::now();
+        let warm = plan_run_order(
+            &runfile,
+            std::slice::from_ref(&runfile.default),
+            Some(&cache),
         )
+        .unwrap();
+        let warm_time = start.elapsed();
+
+        assert_eq!(cold, warm);
+        println!(
+            "plan for {JOBS} jobs: uncached {cold_time:?}, cached {warm_time:?} ({:.1}x speedup)",
+            cold_time.as_secs_f64() / warm_time.as_secs_f64()
+        );
     }
 }
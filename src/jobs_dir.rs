@@ -0,0 +1,156 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use itertools::Itertools;
+use serde_yaml::{Mapping, Value};
+
+use crate::runfile::{Job, JobId};
+
+/// Directory next to the runfile whose files each define more jobs
+pub const JOBS_DIR: &str = "uni.d";
+
+#[derive(Clone, Debug)]
+pub struct JobsFileError {
+    file: PathBuf,
+    message: String,
+}
+
+impl Error for JobsFileError {}
+
+impl Display for JobsFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid '{}': {}", self.file.display(), self.message)
+    }
+}
+
+/// The `.yaml` and `.yml` files in `dir`, sorted by name so they are merged in the same order
+/// everywhere. A missing directory has no files.
+pub fn files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+        if is_yaml && path.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(files.into_iter().sorted().collect())
+}
+
+/// Add the jobs defined in `content`, read from `file`, to the `jobs` of the runfile `document`.
+/// A jobs file is a mapping of job ids to jobs, laid out like the runfile's `jobs`, and may not
+/// define a job that is already defined.
+pub fn merge(document: &mut Value, file: &Path, content: &str) -> Result<(), JobsFileError> {
+    let error = |message: String| JobsFileError {
+        file: file.to_owned(),
+        message,
+    };
+
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let jobs = match serde_yaml::from_str::<Value>(content).map_err(|e| error(e.to_string()))? {
+        Value::Null => return Ok(()),
+        Value::Mapping(jobs) => jobs,
+        _ => return Err(error(String::from("expected a mapping of job ids to jobs"))),
+    };
+    // Check the jobs here, since errors found once they are merged would point at the runfile
+    serde_path_to_error::deserialize::<_, HashMap<JobId, Job>>(Value::Mapping(jobs.clone()))
+        .map_err(|e| error(e.to_string()))?;
+
+    let Value::Mapping(document) = document else {
+        return Err(error(String::from("the runfile is not a mapping")));
+    };
+    let existing = document
+        .entry(Value::from("jobs"))
+        .or_insert_with(|| Value::Mapping(Mapping::new()));
+    if existing.is_null() {
+        *existing = Value::Mapping(Mapping::new());
+    }
+    let Value::Mapping(existing) = existing else {
+        return Err(error(String::from("the runfile's `jobs` is not a mapping")));
+    };
+
+    for (id, job) in jobs {
+        if existing.contains_key(&id) {
+            let id = id.as_str().unwrap_or_default();
+            return Err(error(format!("job '{id}' is already defined")));
+        }
+        existing.insert(id, job);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_files_sorted() {
+        let dir = TempDir::new("jobs-dir");
+        for name in ["b.yaml", "a.yml", "notes.txt"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = files(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("a.yml"), dir.join("b.yaml")]);
+        assert!(super::files(&dir.join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut document: Value =
+            serde_yaml::from_str("default: test\njobs:\n  build:\n    steps: []\n").unwrap();
+
+        merge(
+            &mut document,
+            Path::new("uni.d/test.yaml"),
+            "test:\n  needs: [build]\n  steps:\n    - run: cargo test\n",
+        )
+        .unwrap();
+
+        let jobs = document["jobs"].as_mapping().unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(document["jobs"]["test"]["needs"][0], "build");
+    }
+
+    #[test]
+    fn test_merge_duplicate() {
+        let mut document: Value =
+            serde_yaml::from_str("default: build\njobs:\n  build:\n    steps: []\n").unwrap();
+
+        let err = merge(&mut document, Path::new("uni.d/build.yaml"), "build: {}\n").unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Invalid 'uni.d/build.yaml': job 'build' is already defined"
+        );
+    }
+
+    #[test]
+    fn test_merge_invalid_job() {
+        let mut document: Value = serde_yaml::from_str("default: build\n").unwrap();
+
+        let err = merge(
+            &mut document,
+            Path::new("uni.d/build.yaml"),
+            "build:\n  steps:\n    - run: make\n      persistant: true\n",
+        )
+        .unwrap_err();
+
+        assert!(err.to_string().contains("build.steps[0]"));
+    }
+}
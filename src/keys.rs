@@ -0,0 +1,117 @@
+use std::{
+    io::IsTerminal,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+};
+
+/// The key that skips the running step
+pub const SKIP_KEY: char = 's';
+
+/// Listens for [`SKIP_KEY`] on an interactive stdin. The terminal reads single keypresses
+/// without echoing them while the listener runs, and gets its settings back once it's dropped.
+pub struct SkipListener {
+    skip: Arc<AtomicBool>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    #[cfg(target_family = "unix")]
+    original: libc::termios,
+}
+
+impl SkipListener {
+    /// Start listening, unless stdin isn't a terminal or its settings can't be changed
+    pub fn start() -> Option<SkipListener> {
+        if !std::io::stdin().is_terminal() {
+            return None;
+        }
+
+        #[cfg(target_family = "unix")]
+        {
+            let original = raw_mode()?;
+            let skip = Arc::new(AtomicBool::new(false));
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread = std::thread::spawn({
+                let (skip, stop) = (Arc::clone(&skip), Arc::clone(&stop));
+                move || listen(&skip, &stop)
+            });
+            Some(SkipListener {
+                skip,
+                stop,
+                thread: Some(thread),
+                original,
+            })
+        }
+        #[cfg(not(target_family = "unix"))]
+        None
+    }
+
+    /// Set once the key is pressed, see [`crate::step::StepContext::skip`]
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.skip)
+    }
+}
+
+impl Drop for SkipListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            _ = thread.join();
+        }
+        // SAFETY: `original` holds the settings read by `tcgetattr` on the same descriptor
+        #[cfg(target_family = "unix")]
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// Switch the terminal to delivering keypresses one at a time without echoing them, returning
+/// its previous settings. Ctrl-C still interrupts the run.
+#[cfg(target_family = "unix")]
+fn raw_mode() -> Option<libc::termios> {
+    let mut original = std::mem::MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr initializes `original` when it succeeds
+    let original = unsafe {
+        if libc::tcgetattr(libc::STDIN_FILENO, original.as_mut_ptr()) != 0 {
+            return None;
+        }
+        original.assume_init()
+    };
+
+    let mut raw = original;
+    raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+    raw.c_cc[libc::VMIN] = 1;
+    raw.c_cc[libc::VTIME] = 0;
+    // SAFETY: `raw` is a valid termios copied from the current settings
+    match unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } {
+        0 => Some(original),
+        _ => None,
+    }
+}
+
+/// Read keypresses until `stop` is set or stdin closes, setting `skip` for each [`SKIP_KEY`]
+#[cfg(target_family = "unix")]
+fn listen(skip: &AtomicBool, stop: &AtomicBool) {
+    let mut poll = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    while !stop.load(Ordering::Relaxed) {
+        // Wake up regularly to notice `stop`, since reads can't be interrupted
+        // SAFETY: `poll` is a single valid pollfd
+        if unsafe { libc::poll(&mut poll, 1, 100) } <= 0 {
+            continue;
+        }
+        let mut key = 0u8;
+        // SAFETY: reads at most one byte into `key`
+        if unsafe { libc::read(libc::STDIN_FILENO, (&mut key as *mut u8).cast(), 1) } != 1 {
+            return;
+        }
+        if char::from(key).eq_ignore_ascii_case(&SKIP_KEY) {
+            skip.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Library behind the `uni` binary. [`plan::plan`] and [`job::RunHandle`] are meant for tools
+//! that schedule or drive runs themselves; the other modules are public for the binary and may
+//! change between releases.
+
+pub mod check;
+pub mod compact;
+pub mod condition;
+pub mod duration;
+pub mod env;
+pub mod events;
+pub mod expand;
+pub mod expression;
+pub mod git;
+pub mod glob;
+pub mod init;
+pub mod inputs;
+pub mod isolate;
+pub mod job;
+pub mod jobs_dir;
+pub mod keys;
+pub mod list;
+pub mod log;
+pub mod output;
+pub mod overrides;
+pub mod plan;
+pub mod profile;
+pub mod report;
+pub mod resume;
+pub mod rng;
+pub mod runfile;
+pub mod secrets;
+pub mod state;
+pub mod stats;
+pub mod step;
+#[cfg(test)]
+mod testing;
+pub mod tree;
+pub mod validate;
+pub mod watch;
@@ -0,0 +1,163 @@
+use std::{error::Error, fmt::Display};
+
+use itertools::Itertools;
+
+use crate::runfile::{Job, Runfile};
+
+#[derive(Clone, Debug)]
+pub struct GroupNotFoundError {
+    group: String,
+}
+
+impl Error for GroupNotFoundError {}
+
+impl Display for GroupNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No jobs in group '{}'", self.group)
+    }
+}
+
+/// Group of a job id following the `group:name` convention
+fn group_of(id: &str) -> Option<&str> {
+    id.split_once(':').map(|(group, _)| group)
+}
+
+pub fn describe(id: &str, job: &Job) -> String {
+    let mut description = id.to_owned();
+    if !job.aliases.is_empty() {
+        description.push_str(&format!(" ({})", job.aliases.join(", ")));
+    }
+    if let Some(name) = &job.name {
+        description.push_str(&format!(" - {name}"));
+    }
+    if job.manual {
+        description.push_str(" (manual)");
+    }
+    description
+}
+
+/// Format the jobs of a runfile for `uni list`. Jobs named `group:name` are listed in a section
+/// per group after the ungrouped jobs, unless `flat` is set. Passing `group` only lists the jobs
+/// in that group.
+pub fn format_jobs(
+    runfile: &Runfile,
+    group: Option<&str>,
+    flat: bool,
+) -> Result<Vec<String>, GroupNotFoundError> {
+    let jobs = runfile
+        .jobs
+        .iter()
+        .filter(|(id, _)| group.is_none() || group_of(id) == group)
+        .sorted_by_key(|(id, _)| id.as_str())
+        .collect::<Vec<_>>();
+
+    if let Some(group) = group.filter(|_| jobs.is_empty()) {
+        return Err(GroupNotFoundError {
+            group: group.to_owned(),
+        });
+    }
+
+    if flat {
+        return Ok(jobs.iter().map(|(id, job)| describe(id, job)).collect());
+    }
+
+    let mut lines = Vec::new();
+    let (ungrouped, grouped): (Vec<_>, Vec<_>) =
+        jobs.into_iter().partition(|(id, _)| group_of(id).is_none());
+
+    lines.extend(ungrouped.iter().map(|(id, job)| describe(id, job)));
+
+    for (group, jobs) in &grouped.into_iter().chunk_by(|(id, _)| group_of(id)) {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(format!("{}:", group.unwrap_or_default()));
+        lines.extend(jobs.map(|(id, job)| format!("  {}", describe(id, job))));
+    }
+
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn runfile() -> Runfile {
+        Runfile {
+            default: String::from("build"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        name: Some("Build".into()),
+                        aliases: vec!["b".into()],
+                        ..Default::default()
+                    },
+                ),
+                ("db:seed".into(), Job::default()),
+                (
+                    "db:reset".into(),
+                    Job {
+                        name: Some("Reset the database".into()),
+                        manual: true,
+                        ..Default::default()
+                    },
+                ),
+                ("test:unit".into(), Job::default()),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_jobs_grouped() {
+        let lines = format_jobs(&runfile(), None, false).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "build (b) - Build",
+                "",
+                "db:",
+                "  db:reset - Reset the database (manual)",
+                "  db:seed",
+                "",
+                "test:",
+                "  test:unit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_jobs_flat() {
+        let lines = format_jobs(&runfile(), None, true).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "build (b) - Build",
+                "db:reset - Reset the database (manual)",
+                "db:seed",
+                "test:unit",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_jobs_single_group() {
+        let lines = format_jobs(&runfile(), Some("db"), false).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "db:",
+                "  db:reset - Reset the database (manual)",
+                "  db:seed"
+            ]
+        );
+
+        assert!(format_jobs(&runfile(), Some("missing"), false).is_err());
+    }
+}
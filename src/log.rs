@@ -0,0 +1,78 @@
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+/// How much of what `uni` does internally is logged to stderr, separately from its usual output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Level {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> LevelFilter {
+        match level {
+            Level::Off => LevelFilter::OFF,
+            Level::Error => LevelFilter::ERROR,
+            Level::Warn => LevelFilter::WARN,
+            Level::Info => LevelFilter::INFO,
+            Level::Debug => LevelFilter::DEBUG,
+            Level::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// Which levels are logged for which modules, from `rust_log` in the `RUST_LOG` format, e.g.
+/// `info,uni::step=trace`, with `level` replacing its default level. Warnings are logged unless
+/// either says otherwise, and invalid directives may be meant for another program, so they're
+/// ignored.
+pub fn filter(rust_log: &str, level: Option<Level>) -> EnvFilter {
+    let directives = rust_log.split(',').map(str::trim).filter(|d| !d.is_empty());
+    let filter = EnvFilter::builder().parse_lossy(["warn"].into_iter().chain(directives).join(","));
+    match level {
+        Some(level) => filter.add_directive(LevelFilter::from(level).into()),
+        None => filter,
+    }
+}
+
+/// Start logging to stderr according to `RUST_LOG` and `level`, see [`filter`]
+pub fn init(level: Option<Level>) {
+    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV).unwrap_or_default();
+    tracing_subscriber::fmt()
+        .with_env_filter(filter(&rust_log, level))
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .without_time()
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(filter("", None).to_string(), "warn");
+        assert_eq!(
+            filter("info, uni::step=trace", None).to_string(),
+            "uni::step=trace,info"
+        );
+        assert_eq!(
+            filter("info,uni::step=trace", Some(Level::Debug)).to_string(),
+            "uni::step=trace,debug"
+        );
+        assert_eq!(filter("", Some(Level::Off)).to_string(), "off");
+    }
+
+    #[test]
+    fn test_filter_invalid() {
+        assert_eq!(filter("uni=loud,debug", None).to_string(), "debug");
+    }
+}
@@ -1,12 +1,44 @@
-use std::{error::Error, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use clap::{Parser, Subcommand};
-use job::run_job;
-use runfile::Runfile;
-
-mod job;
-mod runfile;
-mod step;
+use itertools::Itertools;
+use tracing::{debug, warn};
+use unirun::{
+    check,
+    compact::{self, CompactRenderer},
+    condition::{self, parse_param},
+    duration::parse_duration,
+    env,
+    events::EventSink,
+    expand, init,
+    inputs::{self, InputHashes},
+    job::{
+        find_dependency_path, plan_run_order, resolve_default_job, select_tagged, summarize,
+        RunError, RunHandle, RunOptions,
+    },
+    jobs_dir, list, log, overrides,
+    plan::{self, PlanCache, PlanFormat},
+    profile,
+    report::{ExitCodePolicy, RunReport, SummaryFormat},
+    resume::{self, RunState},
+    runfile::{self, Runfile, TimeoutAction},
+    secrets::{FileSecrets, SecretProvider},
+    state::StateDir,
+    stats, tree,
+    validate::{self, Severity},
+    watch,
+};
 
 #[derive(Debug, Parser)]
 #[command(version, about, long_about = None)]
@@ -14,73 +46,764 @@ struct Args {
     #[command(subcommand)]
     command: Command,
 
-    /// Change to DIRECTORY before doing anything
+    /// Change to DIRECTORY before doing anything. A DIRECTORY referring to params as
+    /// `${{ params.<name> }}` is changed to by `run` and `plan` once the runfile and `--param`s
+    /// are loaded instead, so the runfile is found in the current directory and its jobs run in
+    /// DIRECTORY
     #[arg(short = 'C', long)]
     directory: Option<PathBuf>,
+
+    /// Apply the runfile overlay document named PROFILE
+    #[arg(long, global = true, value_name = "PROFILE")]
+    profile: Option<String>,
+
+    /// Keep state such as the plan cache in DIR. Defaults to $UNIRUN_STATE_DIR, then `.unirun`
+    /// next to the runfile
+    #[arg(long, global = true, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// Log what uni does internally to stderr at LEVEL and below. Defaults to the level of
+    /// $RUST_LOG, which can also set levels per module, e.g. `uni::step=trace`, then `warn`
+    #[arg(long, global = true, value_enum, value_name = "LEVEL")]
+    log_level: Option<log::Level>,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
     /// List available jobs
-    List,
+    List {
+        /// Only list jobs in this group, i.e. jobs named `<group>:<name>`
+        group: Option<String>,
+
+        /// List jobs one per line without grouping them
+        #[arg(long)]
+        flat: bool,
+    },
+
+    /// Write a starter runfile to the current directory
+    Init {
+        /// Overwrite an existing runfile
+        #[arg(long)]
+        force: bool,
+    },
 
     /// Run a job
-    Run(RunArgs),
+    Run(Box<RunArgs>),
+
+    /// Check the runfile for errors and suspicious configuration
+    Validate {
+        /// Treat warnings as errors
+        #[arg(long)]
+        strict: bool,
+    },
+
+    /// Show the dependencies of a job as a tree
+    Tree {
+        /// Job at the root of the tree. Defaults to every job that no other job needs
+        job: Option<String>,
+    },
+
+    /// Show the jobs a run of a target consists of, in the order they run
+    Plan {
+        /// Job to plan. Defaults to $UNIRUN_DEFAULT, then the runfile's `defaults`
+        job_id: Option<String>,
+
+        /// How the plan is printed
+        #[arg(long, value_enum, default_value_t)]
+        format: PlanFormat,
+
+        /// Set the runfile param NAME, which conditions refer to as `${{ params.NAME }}`. Can be
+        /// repeated
+        #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+        params: Vec<(String, String)>,
+    },
+
+    /// Show statistics of the dependency graph, such as its critical path
+    Stats,
+
+    /// Show why a job is needed by a target
+    Why {
+        /// Job whose dependencies are searched
+        target: String,
+
+        /// Job to look for
+        job: String,
+    },
 }
 
 #[derive(Debug, Parser)]
 struct RunArgs {
-    /// Job to run
+    /// Job to run. Defaults to $UNIRUN_DEFAULT, then the runfile's `defaults`
     job_id: Option<String>,
+
+    /// Also run every job with this tag. Can be repeated
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+
+    /// How the exit code is chosen when multiple failures occur
+    #[arg(long, value_enum, default_value_t)]
+    exit_code: ExitCodePolicy,
+
+    /// How the run and the summary at its end are printed
+    #[arg(long, value_enum, default_value_t)]
+    format: SummaryFormat,
+
+    /// Stop at the first failing job. This is the default
+    #[arg(long, overrides_with = "no_fail_fast")]
+    fail_fast: bool,
+
+    /// Keep running jobs that don't depend on a failed job, and exit with an error at the end
+    #[arg(long, overrides_with = "fail_fast")]
+    no_fail_fast: bool,
+
+    /// Run only the given jobs, assuming the jobs they need already ran
+    #[arg(long)]
+    assume_deps_done: bool,
+
+    /// Don't inherit environment variables other than those listed in `pass_env`
+    #[arg(long)]
+    clean_env: bool,
+
+    /// Load environment variables from FILE, defaults to `.env` if it exists. Can be repeated,
+    /// later files override earlier ones and the inherited environment overrides all of them
+    #[arg(long = "env-file", value_name = "FILE")]
+    env_files: Vec<PathBuf>,
+
+    /// Resolve `${{ secret('<path>') }}` in step env from this JSON or YAML file
+    #[arg(long, value_name = "FILE")]
+    secrets_file: Option<PathBuf>,
+
+    /// Always recompute the run order instead of reusing a cached one
+    #[arg(long)]
+    no_plan_cache: bool,
+
+    /// Expose the files changed since this git revision to steps as $UNIRUN_CHANGED_FILES
+    #[arg(long, value_name = "REF")]
+    changed_since: Option<String>,
+
+    /// Print a reminder at this interval while a step keeps running, `0` to disable
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30s")]
+    heartbeat: Duration,
+
+    /// Run the job N times in a row, stopping at the first failing run unless `--no-fail-fast`
+    /// is given
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+
+    /// Write a JSON summary of every job's outcome to FILE once the run ends, even on failure
+    #[arg(long, value_name = "FILE")]
+    write_status: Option<PathBuf>,
+
+    /// Once the run ends, list every skipped job and step with why it was skipped
+    #[arg(long)]
+    explain_skips: bool,
+
+    /// Write a JUnit XML report with a testcase per job to FILE once the run ends
+    #[arg(long, value_name = "FILE")]
+    junit: Option<PathBuf>,
+
+    /// Warn about non-persistent steps still running after DURATION, which may be missing
+    /// `persistent: true`
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    soft_timeout: Option<Duration>,
+
+    /// Fail steps exceeding `--soft-timeout` instead of only warning about them
+    #[arg(long, requires = "soft_timeout")]
+    fail_on_soft_timeout: bool,
+
+    /// How steps still running when they time out are stopped, unless they set `on_timeout`
+    #[arg(long, value_enum, default_value_t)]
+    timeout_action: TimeoutAction,
+
+    /// Skip the jobs that succeeded in the previous run, e.g. to continue from a failure once it's
+    /// fixed. Everything runs if the runfile changed since
+    #[arg(long)]
+    resume: bool,
+
+    /// Only run the jobs whose `inputs` changed since they last succeeded, the jobs without
+    /// `inputs`, and every job needing one of them. The rest are skipped
+    #[arg(long, conflicts_with = "watch")]
+    only_changed_jobs: bool,
+
+    /// Run every job in an empty temporary directory holding only its `inputs`, as if it set
+    /// `isolate: true`
+    #[arg(long)]
+    isolate: bool,
+
+    /// Keep running: reload persistent steps when their job's `watch` paths change, and run the
+    /// target again once it ends and any `watch` path changes. `--watch=PATH` watches PATH too,
+    /// and can be repeated
+    #[arg(
+        long,
+        conflicts_with = "repeat",
+        num_args = 0..=1,
+        require_equals = true,
+        value_name = "PATH"
+    )]
+    watch: Option<Vec<String>>,
+
+    /// With --watch, cancel the run in progress as soon as a `watch` path changes and start
+    /// over, instead of letting it finish first
+    #[arg(long, requires = "watch")]
+    watch_cancel_in_progress: bool,
+
+    /// Check that the program of every step to run can be found, without running anything.
+    /// Programs are looked up in PATH, and relative paths in the project directory
+    #[arg(long, conflicts_with = "watch")]
+    check: bool,
+
+    /// Succeed without running anything when `--tag` or a `for_each` selects no jobs, rather
+    /// than failing
+    #[arg(long)]
+    allow_empty: bool,
+
+    /// Print each step's command, with variables substituted, before running it
+    #[arg(short = 'x', long)]
+    print_command: bool,
+
+    /// Listen for 's' on the terminal to skip the running step. Steps can't read from the
+    /// terminal meanwhile
+    #[arg(long)]
+    skip_key: bool,
+
+    /// Retry every failing non-persistent step up to N times, unless it sets its own `retries`
+    #[arg(long, value_name = "N")]
+    retry_all: Option<u32>,
+
+    /// How long steps with `ignore_signals` may keep running in total after a Ctrl-C or SIGTERM
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, default_value = "30s")]
+    signal_grace: Duration,
+
+    /// Seed for randomizing restart and retry delays (`restart_jitter`, `retry_jitter`), to
+    /// reproduce a run's timing
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Override a runfile value, e.g. `build.0.run="cargo build --verbose"`. Paths are
+    /// `<job>.<field>` or `<job>.<step>.<field>`, where `<step>` is an index or a step `name`
+    #[arg(long = "set", value_name = "PATH=VALUE")]
+    overrides: Vec<String>,
+
+    /// Set the runfile param NAME, which conditions refer to as `${{ params.NAME }}`. Can be
+    /// repeated
+    #[arg(long = "param", value_name = "NAME=VALUE", value_parser = parse_param)]
+    params: Vec<(String, String)>,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    log::init(args.log_level);
 
-    if let Some(directory) = args.directory {
-        if !directory.exists() {
-            eprintln!("{:?} directory does not exist", directory);
-            std::process::exit(-1);
+    // Directories with params can only be resolved once the runfile is loaded
+    let deferred_directory = match args.directory {
+        Some(directory) if directory.to_string_lossy().contains("${{") => Some(directory),
+        Some(directory) => {
+            change_directory(&directory)?;
+            None
         }
+        None => None,
+    };
 
-        if !directory.is_dir() {
-            eprintln!("{:?} is not a directory", directory);
-            std::process::exit(-1);
+    // Commands load the runfile themselves if they need one, so those that don't work anywhere
+    let profile = args.profile;
+    let state_dir = args.state_dir;
+    match args.command {
+        Command::List { group, flat } => {
+            let (runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            for line in list::format_jobs(&runfile, group.as_deref(), flat)? {
+                println!("{line}");
+            }
         }
+        Command::Init { force } => match init::init(Path::new("."), force) {
+            Ok(path) => println!(
+                "Created '{}'",
+                path.file_name().unwrap_or_default().to_string_lossy()
+            ),
+            Err(e) => {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        },
+        Command::Run(args) => {
+            let (mut runfile, content) = load_runfile(profile.as_deref(), &args.overrides)?;
+            runfile.params.extend(args.params.iter().cloned());
+            if let Some(directory) = &deferred_directory {
+                change_directory(&resolve_directory(directory, &runfile.params))?;
+            }
+            let mut targets = Vec::from_iter(
+                args.job_id
+                    .as_deref()
+                    .map(|id| runfile.resolve_alias(id).map(str::to_owned))
+                    .transpose()?,
+            );
+            if !args.tags.is_empty() {
+                match select_tagged(&runfile, &args.tags) {
+                    Ok(selected) => targets.extend(selected),
+                    Err(e) if args.allow_empty => {
+                        if targets.is_empty() {
+                            println!("{e}, nothing to run");
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if targets.is_empty() {
+                targets.push(resolve_default_job(
+                    &runfile,
+                    std::env::var("UNIRUN_DEFAULT").ok().as_deref(),
+                )?);
+            }
 
-        std::env::set_current_dir(directory)?;
-    }
+            let file_env = env::load_files(&args.env_files, profile.as_deref())?;
+            let secrets = match &args.secrets_file {
+                Some(path) => Some(Arc::new(FileSecrets::load(path)?) as Arc<dyn SecretProvider>),
+                None => None,
+            };
+            let discovery_env = env::base(&file_env, args.clean_env, &runfile.pass_env);
+            let instances =
+                expand::expand_for_each(&mut runfile, &targets, &discovery_env, args.allow_empty)?;
 
-    if !PathBuf::from("uni.yaml").exists() {
-        eprintln!("No 'uni.yaml' found in the current directory");
-        std::process::exit(1);
-    }
+            if args.check {
+                let order = plan_run_order(&runfile, &targets, None)?;
+                let (checked, problems) = check::check_steps(
+                    &runfile,
+                    &order,
+                    &discovery_env,
+                    secrets.as_deref(),
+                    &std::env::current_dir()?,
+                );
+                for problem in problems.iter() {
+                    eprintln!("{problem}");
+                }
+                match problems.len() {
+                    0 => println!("All {checked} steps can be started"),
+                    n => {
+                        println!("{n} of {checked} steps can't be started");
+                        std::process::exit(1);
+                    }
+                }
+                return Ok(());
+            }
 
-    let runfile = read_runfile()?;
+            let state = StateDir::resolve(
+                state_dir,
+                std::env::var("UNIRUN_STATE_DIR").ok(),
+                &runfile.dir,
+            );
+            // Overrides, params and for_each instances can change the dependency graph, so they
+            // are part of the key cached state is valid for
+            let params = args
+                .params
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<_>>();
+            let key = [content.as_str(), profile.as_deref().unwrap_or_default()]
+                .into_iter()
+                .chain(args.overrides.iter().map(String::as_str))
+                .chain(params.iter().map(String::as_str))
+                .chain(instances.iter().map(String::as_str))
+                .join("\n");
+            // Hashed before the jobs run, so changes made while they run show up next time
+            let current_hashes = plan_run_order(&runfile, &targets, None)?
+                .into_iter()
+                .map(|id| {
+                    let hash = inputs::hash_inputs(&runfile, &id, &runfile.dir);
+                    (id, hash)
+                })
+                .collect::<HashMap<_, _>>();
+            let run_state = RunState::new(state.last_run(), &key);
+            let mut previously_succeeded = match args.resume {
+                true => run_state.load().unwrap_or_else(|e| {
+                    warn!("{e}, running everything");
+                    HashMap::new()
+                }),
+                false => HashMap::new(),
+            };
 
-    match args.command {
-        Command::List => {
-            for (id, job) in runfile.jobs {
-                match job.name {
-                    Some(name) => println!("{id} - {name}"),
-                    None => println!("{id}"),
+            let cancel = Arc::new(AtomicBool::new(false));
+            let mut options = RunOptions {
+                clean_env: args.clean_env,
+                keep_going: args.no_fail_fast,
+                assume_deps_done: args.assume_deps_done,
+                file_env,
+                soft_timeout: args.soft_timeout,
+                fail_on_soft_timeout: args.fail_on_soft_timeout,
+                timeout_action: args.timeout_action,
+                signal_grace: args.signal_grace,
+                retry_all: args.retry_all,
+                isolate: args.isolate,
+                // Restarting the whole run supersedes reloading its persistent steps
+                watch: args.watch.is_some() && !args.watch_cancel_in_progress,
+                state_dir: state.path().to_path_buf(),
+                heartbeat: (!args.heartbeat.is_zero()).then_some(args.heartbeat),
+                changed_since: args.changed_since,
+                handle_signals: true,
+                seed: args.seed,
+                cancel: Some(Arc::clone(&cancel)),
+                skip_key: args.skip_key,
+                secrets,
+                events: (args.format == SummaryFormat::Compact).then(|| {
+                    Arc::new(CompactRenderer::new(compact::Style::detect())) as Arc<dyn EventSink>
+                }),
+                buffer_output: args.format == SummaryFormat::Compact,
+                print_command: args.print_command,
+                resume: resume::resumable(&previously_succeeded, &current_hashes),
+                unchanged: HashSet::new(),
+                plan_cache: (!args.no_plan_cache)
+                    .then(|| PlanCache::new(state.plan_cache(), key.as_str())),
+            };
+
+            let input_hashes = InputHashes::new(state.input_hashes());
+            if args.only_changed_jobs {
+                let order = plan_run_order(&runfile, &targets, None)?;
+                let changed =
+                    inputs::changed_jobs(&runfile, &order, &current_hashes, &input_hashes.load());
+                options.unchanged = order
+                    .into_iter()
+                    .filter(|id| !changed.contains(id))
+                    .collect();
+            }
+
+            // Watch mode runs the target again whenever a watched file changes
+            let watched = match &args.watch {
+                Some(paths) => plan_run_order(&runfile, &targets, None)?
+                    .iter()
+                    .flat_map(|id| runfile.jobs[id].watch.iter())
+                    .chain(runfile.watch.iter())
+                    .chain(paths.iter())
+                    .cloned()
+                    .unique()
+                    .collect::<Vec<_>>(),
+                None => Vec::new(),
+            };
+            let watched = runfile.watch_set(&watched, state.path());
+            if args.watch.is_some() && watched.is_empty() {
+                warn!("no job to run has `watch` paths, so there is nothing to watch");
+            }
+            let cancel_in_progress = args.watch_cancel_in_progress && !watched.is_empty();
+            if cancel_in_progress {
+                // Each run gets a cancellation flag of its own, so a change can cancel it without
+                // ending the session, and signals are handled here rather than by the run
+                signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&cancel))?;
+                signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&cancel))?;
+                options.handle_signals = false;
+                options.cancel = None;
+            }
+
+            'watch: loop {
+                let snapshot = watch::Snapshot::scan(Path::new("."), &watched);
+                let mut report = RunReport::default();
+                let (mut runs, mut passed) = (0, 0);
+                for run in 1..=args.repeat {
+                    runs = run;
+                    if args.repeat > 1 {
+                        println!("Run {run} of {}", args.repeat);
+                    }
+
+                    let handle =
+                        RunHandle::spawn(runfile.clone(), targets.clone(), options.clone());
+                    let changes = match cancel_in_progress {
+                        true => {
+                            watch::watch_run(Path::new("."), &watched, &snapshot, &cancel, &handle)
+                        }
+                        false => None,
+                    };
+                    let mut errored = false;
+                    let run_report = match handle.join() {
+                        Ok(report) => report,
+                        // Reported along with the other failures, as the job it stopped at
+                        Err(e) => match e.downcast::<RunError>() {
+                            Ok(e) => {
+                                errored = true;
+                                e.report
+                            }
+                            Err(e) => {
+                                eprintln!("{e}");
+                                std::process::exit(1);
+                            }
+                        },
+                    };
+                    if let Some(changes) = changes {
+                        println!("{} changed, starting over", summarize(&changes, 3));
+                        continue 'watch;
+                    }
+                    // Signal handlers stay registered on the shared flag for the following runs
+                    options.handle_signals = false;
+                    // Repetitions run everything again
+                    options.resume.clear();
+                    options.unchanged.clear();
+
+                    let failed = !run_report.failures.is_empty();
+                    passed += u32::from(!failed);
+                    report.merge(run_report);
+                    if (failed && !args.no_fail_fast)
+                        || errored
+                        || cancel.load(Ordering::Relaxed)
+                        || report.stopped_by.is_some()
+                    {
+                        break;
+                    }
+                }
+                if args.repeat > 1 {
+                    println!("Passed {passed} of {runs} runs");
+                }
+                let mut succeeded = std::mem::take(&mut previously_succeeded);
+                for id in report.succeeded.iter() {
+                    succeeded.insert(id.clone(), current_hashes.get(id).copied().flatten());
+                }
+                for failure in report.failures.iter() {
+                    succeeded.remove(&failure.error.job_id);
+                }
+                run_state.store(&succeeded);
+                previously_succeeded = succeeded;
+                if args.only_changed_jobs {
+                    let mut hashes = input_hashes.load();
+                    for id in report.succeeded.iter() {
+                        if let Some(&Some(hash)) = current_hashes.get(id) {
+                            hashes.insert(id.clone(), hash);
+                        }
+                    }
+                    for failure in report.failures.iter() {
+                        hashes.remove(&failure.error.job_id);
+                    }
+                    input_hashes.store(&hashes);
+                }
+
+                if args.format == SummaryFormat::Gantt {
+                    print!("{}", report.format_gantt());
+                }
+
+                if let Some(path) = &args.write_status {
+                    std::fs::write(path, report.to_status_json(args.exit_code))?;
                 }
+                if let Some(path) = &args.junit {
+                    std::fs::write(path, report.to_junit_xml())?;
+                }
+
+                // Steps stopped during shutdown are expected, those that ended on their own aren't
+                for exit in report
+                    .persistent_exits
+                    .iter()
+                    .filter(|e| !e.during_shutdown)
+                {
+                    eprintln!("{exit}");
+                }
+                if let Some(job_id) = &report.stopped_by {
+                    println!("Run stopped by '{job_id}'");
+                }
+                if args.explain_skips {
+                    print!("{}", report.format_skips());
+                } else if args.format != SummaryFormat::Compact {
+                    // The compact format already showed skipped jobs as they were skipped
+                    for skip in report.skipped.iter() {
+                        println!("Job '{}' skipped: {}", skip.job_id, skip.reason);
+                    }
+                }
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                let color = std::io::stderr().is_terminal() && !no_color;
+                for failure in report.failures.iter() {
+                    match &failure.reason {
+                        Some(reason) => eprintln!("{}: {reason}", failure.error),
+                        None => eprintln!("{}", failure.error),
+                    }
+                    match &failure.error.message {
+                        Some(message) if color => eprintln!("\x1b[31m{message}\x1b[0m"),
+                        Some(message) => eprintln!("{message}"),
+                        None => {}
+                    }
+                }
+
+                let code = report.exit_code(args.exit_code);
+                if !watched.is_empty() && !cancel.load(Ordering::Relaxed) {
+                    println!("Watching for changes, press Ctrl-C to stop");
+                    let changes =
+                        watch::wait_for_change(Path::new("."), &watched, snapshot, &cancel);
+                    if let Some(changes) = changes {
+                        println!("{} changed, running again", summarize(&changes, 3));
+                        continue;
+                    }
+                }
+                if code != 0 {
+                    std::process::exit(code);
+                }
+                break;
             }
         }
-        Command::Run(args) => {
-            let job_id = match args.job_id {
-                Some(job_id) => job_id,
-                None => runfile.default.clone(),
+        Command::Validate { strict } => {
+            let (runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            let issues = validate::validate(&runfile);
+            for issue in issues.iter() {
+                eprintln!("{}: {issue}", issue.severity());
+            }
+
+            let failed = issues.iter().any(|issue| match issue.severity() {
+                Severity::Error => true,
+                Severity::Warning => strict,
+            });
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::Tree { job } => {
+            let (runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            for line in tree::format_tree(&runfile, job.as_deref())? {
+                println!("{line}");
+            }
+        }
+        Command::Plan {
+            job_id,
+            format,
+            params,
+        } => {
+            let (mut runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            runfile.params.extend(params);
+            if let Some(directory) = &deferred_directory {
+                change_directory(&resolve_directory(directory, &runfile.params))?;
+            }
+            let target = match job_id {
+                Some(job_id) => runfile.resolve_alias(&job_id)?.to_owned(),
+                None => {
+                    resolve_default_job(&runfile, std::env::var("UNIRUN_DEFAULT").ok().as_deref())?
+                }
             };
-            run_job(&runfile, job_id.as_str())?
+            let targets = [target];
+            let plan = plan::plan(&runfile, &targets)?;
+            match format {
+                PlanFormat::Text => {
+                    for job in plan.jobs {
+                        println!("{}", job.id);
+                    }
+                }
+                PlanFormat::Json => println!(
+                    "{}",
+                    plan::format_plan_json(&runfile, &plan, &std::env::current_dir()?)
+                ),
+            }
+        }
+        Command::Stats => {
+            let (runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            for line in stats::format_stats(&runfile)? {
+                println!("{line}");
+            }
+        }
+        Command::Why { target, job } => {
+            let (runfile, _) = load_runfile(profile.as_deref(), &[])?;
+            let (target, job) = (
+                runfile.resolve_alias(&target)?,
+                runfile.resolve_alias(&job)?,
+            );
+            match find_dependency_path(&runfile, target, job)? {
+                Some(path) => println!("{}", path.join(" -> ")),
+                None => println!("'{job}' is not a dependency of '{target}'"),
+            }
         }
     };
 
     Ok(())
 }
 
-fn read_runfile() -> Result<Runfile, Box<dyn Error>> {
-    let content = std::fs::read_to_string("uni.yaml")?;
+/// Change to the `-C` directory, exiting if it isn't one
+fn change_directory(directory: &Path) -> std::io::Result<()> {
+    if !directory.exists() {
+        eprintln!("{:?} directory does not exist", directory);
+        std::process::exit(-1);
+    }
+
+    if !directory.is_dir() {
+        eprintln!("{:?} is not a directory", directory);
+        std::process::exit(-1);
+    }
+
+    std::env::set_current_dir(directory)
+}
+
+/// The `-C` directory with `params` substituted, exiting if one of them isn't defined
+fn resolve_directory(directory: &Path, params: &HashMap<String, String>) -> PathBuf {
+    let directory = directory.to_string_lossy();
+    match condition::substitute(&directory, params) {
+        Ok(resolved) => PathBuf::from(resolved),
+        Err(e) => {
+            eprintln!("Invalid directory '{directory}': {e}");
+            std::process::exit(-1);
+        }
+    }
+}
+
+/// Find and parse the runfile in the current directory, along with the jobs files in `uni.d/`,
+/// returning it along with their content. Exits if there is none or it's invalid.
+fn load_runfile(
+    profile: Option<&str>,
+    overrides: &[String],
+) -> Result<(Runfile, String), Box<dyn Error>> {
+    let path = match runfile::discover(Path::new(".")) {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let runfile_content = std::fs::read_to_string(&path)?;
+    let dir = path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(jobs_dir::JOBS_DIR);
+    let mut jobs_files = Vec::new();
+    for file in jobs_dir::files(&dir)? {
+        let content = std::fs::read_to_string(&file)?;
+        jobs_files.push((file, content));
+    }
+
+    match parse_runfile(&path, &runfile_content, &jobs_files, profile, overrides) {
+        Ok(mut runfile) => {
+            runfile.dir = path.parent().unwrap_or(Path::new(".")).canonicalize()?;
+            debug!(
+                "loaded '{}' with {} jobs from {} jobs files",
+                path.display(),
+                runfile.jobs.len(),
+                jobs_files.len()
+            );
+            // Jobs files are part of the content, so changing them invalidates cached plans
+            let content = jobs_files
+                .into_iter()
+                .fold(runfile_content, |content, (_, file)| content + &file);
+            Ok((runfile, content))
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_runfile(
+    path: &Path,
+    content: &str,
+    jobs_files: &[(PathBuf, String)],
+    profile: Option<&str>,
+    overrides: &[String],
+) -> Result<Runfile, Box<dyn Error>> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let invalid = |e: &dyn Display| format!("Invalid '{name}': {e}");
+
+    let mut documents = profile::documents(content).map_err(|e| invalid(&e))?;
+    if let Some(base) = documents.first_mut() {
+        for (file, file_content) in jobs_files {
+            let file = file
+                .strip_prefix(path.parent().unwrap_or(Path::new("")))
+                .unwrap_or(file);
+            jobs_dir::merge(base, file, file_content)?;
+        }
+    }
+    let mut document = profile::select(documents, profile)?;
+    for spec in overrides {
+        overrides::apply(&mut document, spec)?;
+    }
 
-    Ok(serde_yaml::from_str(content.as_str())?)
+    // Deserializing from a `Value` loses the location of errors, so track the path to them
+    let mut runfile: Runfile =
+        serde_path_to_error::deserialize(document).map_err(|e| invalid(&e))?;
+    runfile.substitute_vars().map_err(|e| invalid(&e))?;
+    Ok(runfile)
 }
@@ -0,0 +1,233 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    process::Child,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+};
+
+const TRUNCATED_MARKER: &[u8] = b"\n[output truncated]\n";
+
+/// Most stdout passed from a step to the next one, unless the step checks all of it, see
+/// [`LimitedOutput::attach`]
+pub const CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// Most output kept from a step whose output is buffered instead of printed
+pub const BUFFER_LIMIT: usize = 1024 * 1024;
+
+/// Byte budget shared by the stdout and stderr of a single step
+#[derive(Debug)]
+struct Budget {
+    limit: u64,
+    used: AtomicU64,
+    truncated: AtomicBool,
+}
+
+impl Budget {
+    fn new(limit: u64) -> Budget {
+        Budget {
+            limit,
+            used: AtomicU64::new(0),
+            truncated: AtomicBool::new(false),
+        }
+    }
+}
+
+/// Forwards a child's piped output to our own stdout/stderr, cutting it off once the step has
+/// written more than its limit. Output past the limit is still drained so the child never blocks.
+pub struct LimitedOutput {
+    budget: Arc<Budget>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl LimitedOutput {
+    /// Start forwarding the piped stdout and stderr of `child`, to `buffer` instead of our own if
+    /// given. The first `capture_limit` bytes of stdout are also kept in `capture` if given,
+    /// regardless of the limit. Stdout goes to `stdout_file` in full instead if given.
+    pub fn attach(
+        child: &mut Child,
+        limit: u64,
+        capture: Option<Arc<Mutex<Vec<u8>>>>,
+        capture_limit: usize,
+        buffer: Option<Arc<Mutex<Vec<u8>>>>,
+        stdout_file: Option<File>,
+    ) -> LimitedOutput {
+        let budget = Arc::new(Budget::new(limit));
+
+        let mut handles = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            let (budget, buffer) = (Arc::clone(&budget), buffer.clone());
+            handles.push(std::thread::spawn(move || {
+                let capture = capture.as_deref().map(|c| (c, capture_limit));
+                _ = match (stdout_file, &buffer) {
+                    (Some(mut file), _) => {
+                        let unlimited = Budget::new(u64::MAX);
+                        forward(stdout, &mut file, &unlimited, capture)
+                    }
+                    (None, Some(buffer)) => {
+                        forward(stdout, &mut Buffered(buffer), &budget, capture)
+                    }
+                    (None, None) => forward(stdout, &mut std::io::stdout(), &budget, capture),
+                };
+            }));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            let budget = Arc::clone(&budget);
+            handles.push(std::thread::spawn(move || {
+                _ = match &buffer {
+                    Some(buffer) => forward(stderr, &mut Buffered(buffer), &budget, None),
+                    None => forward(stderr, &mut std::io::stderr(), &budget, None),
+                };
+            }));
+        }
+
+        LimitedOutput { budget, handles }
+    }
+
+    /// Wait for all output to be forwarded. Returns whether any output was truncated.
+    pub fn finish(self) -> bool {
+        for handle in self.handles {
+            _ = handle.join();
+        }
+        self.budget.truncated.load(Ordering::Relaxed)
+    }
+}
+
+/// Appends to a shared buffer, keeping at most [`BUFFER_LIMIT`] bytes
+struct Buffered<'a>(&'a Mutex<Vec<u8>>);
+
+impl Write for Buffered<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Ok(mut buffer) = self.0.lock() {
+            let room = BUFFER_LIMIT.saturating_sub(buffer.len()).min(buf.len());
+            buffer.extend_from_slice(&buf[..room]);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn forward(
+    mut src: impl Read,
+    dst: &mut impl Write,
+    budget: &Budget,
+    capture: Option<(&Mutex<Vec<u8>>, usize)>,
+) -> std::io::Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = src.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+
+        if let Some((Ok(mut captured), limit)) = capture.map(|(c, limit)| (c.lock(), limit)) {
+            let room = limit.saturating_sub(captured.len()).min(n);
+            captured.extend_from_slice(&buf[..room]);
+        }
+
+        let used = budget.used.fetch_add(n as u64, Ordering::Relaxed);
+        let allowed = budget.limit.saturating_sub(used).min(n as u64) as usize;
+        dst.write_all(&buf[..allowed])?;
+
+        if allowed < n && !budget.truncated.swap(true, Ordering::Relaxed) {
+            dst.write_all(TRUNCATED_MARKER)?;
+        }
+        dst.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn budget(limit: u64) -> Budget {
+        Budget::new(limit)
+    }
+
+    #[test]
+    fn test_forward_buffered() {
+        let budget = budget(u64::MAX);
+        let buffer = Mutex::new(vec![0; BUFFER_LIMIT - 3]);
+
+        forward(Cursor::new("hello"), &mut Buffered(&buffer), &budget, None).unwrap();
+
+        assert_eq!(&buffer.into_inner().unwrap()[BUFFER_LIMIT - 3..], b"hel");
+    }
+
+    #[test]
+    fn test_forward_under_limit() {
+        let budget = budget(100);
+        let mut dst = Vec::new();
+
+        forward(Cursor::new("hello"), &mut dst, &budget, None).unwrap();
+
+        assert_eq!(dst, b"hello");
+        assert!(!budget.truncated.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_forward_over_limit() {
+        let budget = budget(4);
+        let mut dst = Vec::new();
+
+        forward(Cursor::new("hello world"), &mut dst, &budget, None).unwrap();
+
+        assert_eq!(dst, b"hell\n[output truncated]\n");
+        assert!(budget.truncated.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_forward_limit_shared_between_streams() {
+        let budget = budget(8);
+        let mut stdout = Vec::new();
+        let mut stderr = Vec::new();
+
+        forward(Cursor::new("hello"), &mut stdout, &budget, None).unwrap();
+        forward(Cursor::new("world"), &mut stderr, &budget, None).unwrap();
+
+        assert_eq!(stdout, b"hello");
+        assert_eq!(stderr, b"wor\n[output truncated]\n");
+    }
+
+    #[test]
+    fn test_forward_capture() {
+        let budget = budget(4);
+        let capture = Mutex::new(Vec::new());
+        let mut dst = Vec::new();
+
+        forward(
+            Cursor::new("hello"),
+            &mut dst,
+            &budget,
+            Some((&capture, 64)),
+        )
+        .unwrap();
+
+        assert_eq!(dst, b"hell\n[output truncated]\n");
+        assert_eq!(capture.into_inner().unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_forward_capture_limit() {
+        let budget = budget(u64::MAX);
+        let capture = Mutex::new(Vec::new());
+
+        forward(
+            Cursor::new("hello"),
+            &mut Vec::new(),
+            &budget,
+            Some((&capture, 3)),
+        )
+        .unwrap();
+
+        assert_eq!(capture.into_inner().unwrap(), b"hel");
+    }
+}
@@ -0,0 +1,193 @@
+use std::{error::Error, fmt::Display};
+
+use serde_yaml::{Mapping, Value};
+
+#[derive(Clone, Debug)]
+pub struct OverrideError {
+    path: String,
+    message: String,
+}
+
+impl OverrideError {
+    fn new(path: &str, message: impl Into<String>) -> OverrideError {
+        OverrideError {
+            path: path.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Error for OverrideError {}
+
+impl Display for OverrideError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid override '{}': {}", self.path, self.message)
+    }
+}
+
+/// Apply a `--set <path>=<value>` override to a parsed runfile document.
+///
+/// Paths are dot-separated:
+/// - `<job>.<field>` sets a field of a job, e.g. `build.needs=[]`
+/// - `<job>.<step>.<field>` sets a field of a step, where `<step>` is either the step's index
+///   (starting at 0) or its `name`, e.g. `build.0.run="cargo build --verbose"`
+/// - Further segments address keys of nested maps, e.g. `build.0.env.RUST_LOG=debug`
+///
+/// The value is parsed as YAML, so `true` and `500` become a boolean and a number.
+pub fn apply(document: &mut Value, spec: &str) -> Result<(), OverrideError> {
+    let Some((path, value)) = spec.split_once('=') else {
+        return Err(OverrideError::new(spec, "expected <path>=<value>"));
+    };
+    let value = serde_yaml::from_str::<Value>(value)
+        .map_err(|e| OverrideError::new(path, format!("invalid value: {e}")))?;
+
+    let segments = path.split('.').collect::<Vec<_>>();
+    let (job_id, fields) = match segments.as_slice() {
+        [job_id, rest @ ..] if !rest.is_empty() && segments.iter().all(|s| !s.is_empty()) => {
+            (*job_id, rest)
+        }
+        _ => {
+            return Err(OverrideError::new(
+                path,
+                "expected <job>.<field> or <job>.<step>.<field>",
+            ))
+        }
+    };
+
+    let job = document
+        .get_mut("jobs")
+        .and_then(|jobs| jobs.get_mut(job_id))
+        .ok_or_else(|| OverrideError::new(path, format!("job '{job_id}' not defined")))?;
+
+    let (target, keys) = match fields {
+        [field] => (job, std::slice::from_ref(field)),
+        [step, keys @ ..] => (find_step(job, step, path)?, keys),
+        [] => unreachable!(),
+    };
+
+    set(target, keys, value, path)
+}
+
+fn find_step<'a>(
+    job: &'a mut Value,
+    step: &str,
+    path: &str,
+) -> Result<&'a mut Value, OverrideError> {
+    let steps = job
+        .get_mut("steps")
+        .and_then(Value::as_sequence_mut)
+        .ok_or_else(|| OverrideError::new(path, "job has no steps"))?;
+
+    let found = match step.parse::<usize>() {
+        Ok(index) => steps.get_mut(index),
+        Err(_) => steps
+            .iter_mut()
+            .find(|s| s.get("name").and_then(Value::as_str) == Some(step)),
+    };
+
+    found.ok_or_else(|| OverrideError::new(path, format!("step '{step}' not found")))
+}
+
+fn set(target: &mut Value, keys: &[&str], value: Value, path: &str) -> Result<(), OverrideError> {
+    let mut target = target;
+    for key in &keys[..keys.len() - 1] {
+        let map = target
+            .as_mapping_mut()
+            .ok_or_else(|| OverrideError::new(path, format!("'{key}' is not a map")))?;
+        target = map
+            .entry(Value::from(*key))
+            .or_insert_with(|| Value::Mapping(Mapping::new()));
+    }
+
+    let map = target
+        .as_mapping_mut()
+        .ok_or_else(|| OverrideError::new(path, "target is not a map"))?;
+    map.insert(Value::from(keys[keys.len() - 1]), value);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runfile::Runfile;
+
+    const RUNFILE: &str = r#"
+        default: build
+        jobs:
+          build:
+            steps:
+              - run: cargo build
+              - name: test
+                run: cargo test
+    "#;
+
+    fn document() -> Value {
+        serde_yaml::from_str(RUNFILE).unwrap()
+    }
+
+    #[test]
+    fn test_apply_step_index() {
+        let mut document = document();
+
+        apply(&mut document, "build.0.run=cargo build --verbose").unwrap();
+
+        assert_eq!(
+            document["jobs"]["build"]["steps"][0]["run"],
+            Value::from("cargo build --verbose")
+        );
+    }
+
+    #[test]
+    fn test_apply_step_name() {
+        let mut document = document();
+
+        apply(&mut document, "build.test.persistent=true").unwrap();
+
+        assert_eq!(
+            document["jobs"]["build"]["steps"][1]["persistent"],
+            Value::from(true)
+        );
+
+        let runfile = serde_yaml::from_value::<Runfile>(document).unwrap();
+        let step = &runfile.jobs["build"].steps[1];
+        assert_eq!(step.name.as_deref(), Some("test"));
+        assert_eq!(step.persistent, Some(true));
+    }
+
+    #[test]
+    fn test_apply_nested_key() {
+        let mut document = document();
+
+        apply(&mut document, "build.0.env.RUST_LOG=debug").unwrap();
+
+        assert_eq!(
+            document["jobs"]["build"]["steps"][0]["env"]["RUST_LOG"],
+            Value::from("debug")
+        );
+    }
+
+    #[test]
+    fn test_apply_job_field() {
+        let mut document = document();
+
+        apply(&mut document, "build.name=Build everything").unwrap();
+
+        assert_eq!(
+            document["jobs"]["build"]["name"],
+            Value::from("Build everything")
+        );
+    }
+
+    #[test]
+    fn test_apply_invalid_paths() {
+        let mut document = document();
+
+        assert!(apply(&mut document, "build.0.run").is_err());
+        assert!(apply(&mut document, "build=foo").is_err());
+        assert!(apply(&mut document, "missing.0.run=foo").is_err());
+        assert!(apply(&mut document, "build.5.run=foo").is_err());
+        assert!(apply(&mut document, "build.lint.run=foo").is_err());
+        assert!(apply(&mut document, "build..run=foo").is_err());
+    }
+}
@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{
+    condition::ConditionError,
+    env::Env,
+    job::{plan_run_order, resolve_step},
+    runfile::{JobId, Runfile},
+};
+
+/// Version of the `uni plan --format json` output, bumped whenever a field changes or is removed
+pub const PLAN_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlanFormat {
+    /// Job ids in run order, one per line
+    #[default]
+    Text,
+    /// Jobs in run order with their resolved steps, and the dependency edges between them
+    Json,
+}
+
+/// The run order of some targets as plain data, for schedulers that run the jobs themselves.
+/// Jobs are listed in an order that runs every job after the jobs it needs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    pub targets: Vec<JobId>,
+    pub jobs: Vec<PlannedJob>,
+    pub edges: Vec<Edge>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PlannedJob {
+    pub id: JobId,
+    /// The jobs this job needs, with conditional needs that don't hold left out
+    pub needs: Vec<JobId>,
+}
+
+/// `job` needs `needs`, so it can only run once `needs` succeeded
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub job: JobId,
+    pub needs: JobId,
+}
+
+/// Plan running `targets` and everything they need, see [`Plan`]
+pub fn plan(runfile: &Runfile, targets: &[JobId]) -> Result<Plan, Box<dyn Error>> {
+    let order = plan_run_order(runfile, targets, None)?;
+    Ok(Plan::from_order(runfile, targets, &order)?)
+}
+
+impl Plan {
+    /// The plan for running `order`, the run order of `targets`
+    pub fn from_order(
+        runfile: &Runfile,
+        targets: &[JobId],
+        order: &[JobId],
+    ) -> Result<Plan, ConditionError> {
+        let jobs = order
+            .iter()
+            .map(|id| {
+                let needs = runfile.needs(&runfile.jobs[id])?;
+                Ok(PlannedJob {
+                    id: id.clone(),
+                    needs: needs.into_iter().cloned().collect(),
+                })
+            })
+            .collect::<Result<Vec<PlannedJob>, ConditionError>>()?;
+        let edges = jobs
+            .iter()
+            .flat_map(|job| {
+                job.needs.iter().map(|need| Edge {
+                    job: job.id.clone(),
+                    needs: need.clone(),
+                })
+            })
+            .collect();
+
+        Ok(Plan {
+            targets: targets.to_vec(),
+            jobs,
+            edges,
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct JsonPlan<'a> {
+    schema_version: u32,
+    targets: &'a [JobId],
+    jobs: Vec<JsonJob<'a>>,
+    edges: &'a [Edge],
+}
+
+#[derive(Serialize)]
+struct JsonJob<'a> {
+    id: &'a str,
+    needs: &'a [JobId],
+    steps: Vec<JsonStep>,
+}
+
+#[derive(Serialize)]
+struct JsonStep {
+    name: Option<String>,
+    command: String,
+    persistent: bool,
+    env: BTreeMap<String, String>,
+    workdir: PathBuf,
+}
+
+/// JSON description of `plan`, with every step resolved against the runfile's defaults. Steps
+/// run in `workdir`, or their own `workdir` relative to the runfile.
+pub fn format_plan_json(runfile: &Runfile, plan: &Plan, workdir: &Path) -> String {
+    let jobs = plan
+        .jobs
+        .iter()
+        .map(|job| JsonJob {
+            id: &job.id,
+            needs: &job.needs,
+            steps: runfile.jobs[&job.id]
+                .steps
+                .iter()
+                .map(|step| {
+                    // Env files are left out, since they tend to hold secrets
+                    let step = resolve_step(runfile, step, &Env::new());
+                    JsonStep {
+                        persistent: step.is_persistent(),
+                        name: step.name,
+                        command: step.command,
+                        env: step.env.into_iter().collect(),
+                        workdir: match &step.workdir {
+                            Some(dir) => runfile.dir.join(dir),
+                            None => workdir.to_owned(),
+                        },
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    let plan = JsonPlan {
+        schema_version: PLAN_SCHEMA_VERSION,
+        targets: &plan.targets,
+        jobs,
+        edges: &plan.edges,
+    };
+    serde_json::to_string_pretty(&plan).expect("plan is always serializable")
+}
+
+/// On-disk cache of computed run orders, invalidated whenever the runfile content changes
+#[derive(Clone, Debug)]
+pub struct PlanCache {
+    path: PathBuf,
+    runfile_hash: u64,
+}
+
+impl PlanCache {
+    pub fn new(path: PathBuf, runfile_content: &str) -> PlanCache {
+        let mut hasher = DefaultHasher::new();
+        runfile_content.hash(&mut hasher);
+
+        PlanCache {
+            path,
+            runfile_hash: hasher.finish(),
+        }
+    }
+
+    fn key(&self, targets: &[JobId]) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.runfile_hash.hash(&mut hasher);
+        targets.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Load the cached run order for `targets`. Any missing, stale, or malformed cache is a miss.
+    pub fn load(&self, targets: &[JobId]) -> Option<Vec<JobId>> {
+        let bytes = std::fs::read(&self.path).ok()?;
+        decode(&bytes, self.key(targets))
+    }
+
+    /// Store the run order for `targets`. The cache is best-effort, so failures are ignored.
+    pub fn store(&self, targets: &[JobId], order: &[JobId]) {
+        if let Some(parent) = self.path.parent() {
+            _ = std::fs::create_dir_all(parent);
+        }
+        _ = std::fs::write(&self.path, encode(self.key(targets), order));
+    }
+}
+
+// Layout: key (u64), job count (u32), then each job id as a length (u32) followed by its bytes.
+// All integers are little endian.
+fn encode(key: u64, order: &[JobId]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend(key.to_le_bytes());
+    bytes.extend((order.len() as u32).to_le_bytes());
+    for id in order {
+        bytes.extend((id.len() as u32).to_le_bytes());
+        bytes.extend(id.as_bytes());
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8], expected_key: u64) -> Option<Vec<JobId>> {
+    let (key, rest) = bytes.split_first_chunk::<8>()?;
+    if u64::from_le_bytes(*key) != expected_key {
+        return None;
+    }
+
+    let (count, mut rest) = rest.split_first_chunk::<4>()?;
+    let count = u32::from_le_bytes(*count);
+
+    let mut order = Vec::new();
+    for _ in 0..count {
+        let (len, tail) = rest.split_first_chunk::<4>()?;
+        let len = u32::from_le_bytes(*len) as usize;
+        if tail.len() < len {
+            return None;
+        }
+        let (id, tail) = tail.split_at(len);
+        order.push(String::from_utf8(id.to_vec()).ok()?);
+        rest = tail;
+    }
+
+    rest.is_empty().then_some(order)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::runfile::{Job, Step};
+
+    use super::*;
+
+    #[test]
+    fn test_format_plan_json() {
+        let runfile = Runfile {
+            default: String::from("test"),
+            jobs: HashMap::from([
+                (
+                    "build".into(),
+                    Job {
+                        steps: vec![Step {
+                            command: "cargo build".into(),
+                            workdir: Some("crates/app".into()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "test".into(),
+                    Job {
+                        needs: vec!["build".into()],
+                        steps: vec![Step {
+                            command: "cargo test".into(),
+                            name: Some("unit".into()),
+                            ..Default::default()
+                        }],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            default_env: HashMap::from([("CI".into(), "1".into())]),
+            dir: PathBuf::from("/project"),
+            ..Default::default()
+        };
+        let plan = plan(&runfile, &[String::from("test")]).unwrap();
+
+        let plan: serde_json::Value =
+            serde_json::from_str(&format_plan_json(&runfile, &plan, Path::new("/project")))
+                .unwrap();
+
+        assert_eq!(
+            plan,
+            serde_json::json!({
+                "schema_version": 1,
+                "targets": ["test"],
+                "jobs": [
+                    {
+                        "id": "build",
+                        "needs": [],
+                        "steps": [{ "name": null, "command": "cargo build", "persistent": false, "env": { "CI": "1" }, "workdir": "/project/crates/app" }],
+                    },
+                    {
+                        "id": "test",
+                        "needs": ["build"],
+                        "steps": [{ "name": "unit", "command": "cargo test", "persistent": false, "env": { "CI": "1" }, "workdir": "/project" }],
+                    },
+                ],
+                "edges": [{ "job": "test", "needs": "build" }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan() {
+        let job = |needs: &[&str]| Job {
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("deploy"),
+            jobs: HashMap::from([
+                ("build".into(), job(&[])),
+                ("test".into(), job(&["build"])),
+                ("deploy".into(), job(&["build", "test"])),
+                ("docs".into(), job(&[])),
+            ]),
+            ..Default::default()
+        };
+
+        let plan = plan(&runfile, &[String::from("deploy")]).unwrap();
+
+        let planned = |id: &str, needs: &[&str]| PlannedJob {
+            id: id.into(),
+            needs: needs.iter().map(|&n| n.into()).collect(),
+        };
+        let edge = |job: &str, needs: &str| Edge {
+            job: job.into(),
+            needs: needs.into(),
+        };
+        assert_eq!(
+            plan,
+            Plan {
+                targets: vec![String::from("deploy")],
+                jobs: vec![
+                    planned("build", &[]),
+                    planned("test", &["build"]),
+                    planned("deploy", &["build", "test"]),
+                ],
+                edges: vec![
+                    edge("test", "build"),
+                    edge("deploy", "build"),
+                    edge("deploy", "test"),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_roundtrip() {
+        let order = vec![String::from("build"), String::from("test")];
+
+        let bytes = encode(42, &order);
+
+        assert_eq!(decode(&bytes, 42), Some(order));
+    }
+
+    #[test]
+    fn test_decode_key_mismatch() {
+        let bytes = encode(42, &[String::from("build")]);
+
+        assert_eq!(decode(&bytes, 43), None);
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let bytes = encode(42, &[String::from("build")]);
+
+        assert_eq!(decode(&bytes[..bytes.len() - 1], 42), None);
+    }
+
+    #[test]
+    fn test_key_changes_with_content() {
+        let a = PlanCache::new(PathBuf::new(), "default: a");
+        let b = PlanCache::new(PathBuf::new(), "default: b");
+
+        let target = |id: &str| vec![String::from(id)];
+
+        assert_ne!(a.key(&target("a")), b.key(&target("a")));
+        assert_ne!(a.key(&target("a")), a.key(&target("b")));
+    }
+}
@@ -0,0 +1,211 @@
+use std::{error::Error, fmt::Display};
+
+use itertools::Itertools;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+#[derive(Clone, Debug)]
+pub struct ProfileError {
+    message: String,
+}
+
+impl Error for ProfileError {}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for ProfileError {
+    fn from(message: String) -> ProfileError {
+        ProfileError { message }
+    }
+}
+
+/// Parse the YAML documents of a runfile
+pub fn documents(content: &str) -> Result<Vec<Value>, serde_yaml::Error> {
+    // Editors on Windows may start UTF-8 files with a byte order mark
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    serde_yaml::Deserializer::from_str(content)
+        .map(Value::deserialize)
+        .collect()
+}
+
+/// Build the runfile document from the documents of a runfile, applying the overlay for
+/// `profile`.
+///
+/// The first document is the base runfile. Every following document is an overlay with a
+/// `profile` key naming it, and otherwise the same layout as the base. Overlay mappings are
+/// merged into the base recursively, while any other value, including a list such as a job's
+/// `steps`, replaces the base value. Overlays can only change jobs the base defines.
+pub fn select(documents: Vec<Value>, profile: Option<&str>) -> Result<Value, ProfileError> {
+    let mut documents = documents.into_iter();
+    let mut base = documents.next().unwrap_or_default();
+
+    let mut overlays = Vec::new();
+    for (index, overlay) in documents.enumerate() {
+        let name = overlay
+            .get("profile")
+            .and_then(Value::as_str)
+            .map(str::to_owned);
+        match (name, overlay) {
+            (Some(name), Value::Mapping(mut overlay)) => {
+                overlay.remove("profile");
+                overlays.push((name, overlay));
+            }
+            _ => {
+                return Err(format!(
+                    "Document {} of the runfile is an overlay without a `profile` name",
+                    index + 2
+                )
+                .into())
+            }
+        }
+    }
+
+    let Some(profile) = profile else {
+        return Ok(base);
+    };
+
+    let Some(index) = overlays.iter().position(|(name, _)| name == profile) else {
+        let available = overlays
+            .iter()
+            .map(|(name, _)| format!("'{name}'"))
+            .join(", ");
+        return Err(match available.is_empty() {
+            true => format!("Profile '{profile}' not defined, the runfile defines no profiles"),
+            false => format!("Profile '{profile}' not defined, available profiles are {available}"),
+        }
+        .into());
+    };
+    let (_, overlay) = overlays.swap_remove(index);
+
+    if let Some(jobs) = overlay.get("jobs").and_then(Value::as_mapping) {
+        for id in jobs.keys() {
+            if base
+                .get("jobs")
+                .and_then(|base_jobs| base_jobs.get(id))
+                .is_none()
+            {
+                let id = id.as_str().unwrap_or_default();
+                return Err(format!(
+                    "Profile '{profile}' changes job '{id}', which is not defined"
+                )
+                .into());
+            }
+        }
+    }
+
+    merge(&mut base, Value::Mapping(overlay));
+    Ok(base)
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => merge_mappings(base, overlay),
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn merge_mappings(base: &mut Mapping, overlay: Mapping) {
+    for (key, value) in overlay {
+        match base.get_mut(&key) {
+            Some(existing) => merge(existing, value),
+            None => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUNFILE: &str = r#"
+default: test
+jobs:
+  test:
+    steps:
+      - run: cargo test
+        env:
+          RUST_LOG: info
+---
+profile: ci
+jobs:
+  test:
+    steps:
+      - run: cargo test --locked
+pass_env: [PATH]
+---
+profile: bad
+jobs:
+  deploy:
+    steps: []
+"#;
+
+    fn read(content: &str, profile: Option<&str>) -> Result<Value, ProfileError> {
+        select(documents(content).unwrap(), profile)
+    }
+
+    #[test]
+    fn test_read_base() {
+        let document = read(RUNFILE, None).unwrap();
+
+        assert_eq!(document["jobs"]["test"]["steps"][0]["run"], "cargo test");
+        assert_eq!(document.get("pass_env"), None);
+    }
+
+    #[test]
+    fn test_read_profile() {
+        let document = read(RUNFILE, Some("ci")).unwrap();
+
+        assert_eq!(document["default"], "test");
+        assert_eq!(document["pass_env"][0], "PATH");
+        // Lists are replaced rather than merged
+        assert_eq!(
+            document["jobs"]["test"]["steps"][0]["run"],
+            "cargo test --locked"
+        );
+        assert_eq!(document["jobs"]["test"]["steps"][0].get("env"), None);
+    }
+
+    #[test]
+    fn test_read_bom_crlf() {
+        let content = "\u{feff}default: test\r\njobs:\r\n  test:\r\n    steps:\r\n      - run: |\r\n          cargo test \\\r\n            --locked\r\n";
+
+        let document = read(content, None).unwrap();
+
+        assert_eq!(document["default"], "test");
+        assert!(document["jobs"]["test"]["steps"][0]["run"]
+            .as_str()
+            .unwrap()
+            .starts_with("cargo test"));
+    }
+
+    #[test]
+    fn test_read_profile_unknown_job() {
+        assert!(read(RUNFILE, Some("bad")).is_err());
+    }
+
+    #[test]
+    fn test_read_overlay_without_name() {
+        let err = read("default: test\n---\njobs: {}\n", None).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Document 2 of the runfile is an overlay without a `profile` name"
+        );
+    }
+
+    #[test]
+    fn test_read_profile_not_defined() {
+        let err = read(RUNFILE, Some("prod")).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Profile 'prod' not defined, available profiles are 'ci', 'bad'"
+        );
+    }
+}
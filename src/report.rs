@@ -0,0 +1,777 @@
+use std::{
+    fmt::Display,
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{runfile::JobId, step::StepError};
+
+/// Exit code of a run cancelled before its jobs finished, `128 + SIGINT` as in a shell
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// How the final exit code is derived when more than one failure was recorded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExitCodePolicy {
+    /// Exit code of the first failure
+    #[default]
+    First,
+    /// Exit code of the last failure
+    Last,
+    /// Highest exit code of all failures
+    Max,
+    /// Number of failures
+    Count,
+}
+
+/// How the summary at the end of a run is printed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SummaryFormat {
+    /// Skipped and failed jobs only
+    #[default]
+    Text,
+    /// Also a timeline of when each job ran
+    Gantt,
+    /// A line per job as it runs, showing the output of failed jobs only
+    Compact,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub struct Failure {
+    pub error: StepError,
+    /// Check the step failed, if it didn't fail by its exit code alone
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skip {
+    pub job_id: JobId,
+    /// Index of the step in its job, for a step skipped on its own
+    pub step: Option<usize>,
+    pub reason: String,
+    /// Values the reason was determined from, such as `os: linux`
+    pub inputs: Vec<String>,
+}
+
+impl Display for Skip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.job_id)?;
+        if let Some(step) = self.step {
+            write!(f, "/{step}")?;
+        }
+        write!(f, ": {}", self.reason)?;
+        if !self.inputs.is_empty() {
+            write!(f, " ({})", self.inputs.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// When a job started and finished running its steps
+#[derive(Clone, Debug)]
+pub struct JobTiming {
+    pub job_id: JobId,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+}
+
+/// How a process ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Termination {
+    Exited(i32),
+    Signaled(i32),
+}
+
+impl Termination {
+    pub fn from_status(status: ExitStatus) -> Termination {
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return Termination::Signaled(signal);
+            }
+        }
+
+        Termination::Exited(exit_code(status))
+    }
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Termination::Exited(code) => write!(f, "exited with code {code}"),
+            Termination::Signaled(signal) => {
+                write!(f, "was killed by signal {signal}")?;
+                #[cfg(target_family = "unix")]
+                if let Ok(name) = nix::sys::signal::Signal::try_from(*signal) {
+                    write!(f, " ({name})")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a persistent step ended
+#[derive(Clone, Debug)]
+pub struct PersistentExit {
+    pub job_id: JobId,
+    pub step: usize,
+    pub termination: Termination,
+    /// Whether the step was stopped by us while shutting down, rather than ending on its own
+    pub during_shutdown: bool,
+}
+
+impl Display for PersistentExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Persistent step {}/{} {}",
+            self.job_id, self.step, self.termination
+        )?;
+        if self.during_shutdown {
+            write!(f, " during shutdown")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RunReport {
+    /// Jobs whose steps all completed, or started in the case of persistent steps
+    pub succeeded: Vec<JobId>,
+    pub failures: Vec<Failure>,
+    pub skipped: Vec<Skip>,
+    /// Steps skipped while the rest of their job ran
+    pub skipped_steps: Vec<Skip>,
+    pub persistent_exits: Vec<PersistentExit>,
+    /// Jobs that ran, successfully or not, in the order they finished
+    pub timings: Vec<JobTiming>,
+    /// Job whose step stopped the run early, see [`crate::runfile::Step::stop_run`]
+    pub stopped_by: Option<JobId>,
+    /// Jobs that were running when the run was cancelled
+    pub cancelled: Vec<JobId>,
+    /// Whether the run was cancelled before all of its jobs finished
+    pub interrupted: bool,
+}
+
+impl RunReport {
+    pub fn record_success(&mut self, job_id: &str) {
+        self.succeeded.push(job_id.to_owned());
+    }
+
+    pub fn record_skip(&mut self, job_id: &str, reason: String, inputs: Vec<String>) {
+        self.skipped.push(Skip {
+            job_id: job_id.to_owned(),
+            step: None,
+            reason,
+            inputs,
+        });
+    }
+
+    pub fn record_step_skip(
+        &mut self,
+        job_id: &str,
+        step: usize,
+        reason: String,
+        inputs: Vec<String>,
+    ) {
+        self.skipped_steps.push(Skip {
+            job_id: job_id.to_owned(),
+            step: Some(step),
+            reason,
+            inputs,
+        });
+    }
+
+    pub fn record_persistent_exit(
+        &mut self,
+        job_id: &str,
+        step: usize,
+        status: ExitStatus,
+        during_shutdown: bool,
+    ) {
+        self.persistent_exits.push(PersistentExit {
+            job_id: job_id.to_owned(),
+            step,
+            termination: Termination::from_status(status),
+            during_shutdown,
+        });
+    }
+
+    /// Record that a job which started at `started` has just finished
+    pub fn record_timing(&mut self, job_id: &str, started: SystemTime) {
+        self.timings.push(JobTiming {
+            job_id: job_id.to_owned(),
+            started,
+            finished: SystemTime::now(),
+        });
+    }
+
+    pub fn is_failed(&self, job_id: &str) -> bool {
+        self.failures.iter().any(|f| f.error.job_id == job_id)
+    }
+
+    pub fn is_skipped(&self, job_id: &str) -> bool {
+        self.skipped.iter().any(|s| s.job_id == job_id)
+    }
+
+    /// Record a job that was running when the run was cancelled
+    pub fn record_cancel(&mut self, job_id: &str) {
+        self.cancelled.push(job_id.to_owned());
+        self.interrupted = true;
+    }
+
+    pub fn record_failure(&mut self, error: StepError) {
+        self.push_failure(Failure {
+            error,
+            reason: None,
+        });
+    }
+
+    /// Record a step that failed one of its checks, keeping the exit code it exited with
+    pub fn record_rejection(&mut self, error: StepError, reason: String) {
+        self.push_failure(Failure {
+            error,
+            reason: Some(reason),
+        });
+    }
+
+    /// Add the results of another run, e.g. a repetition of the same job
+    pub fn merge(&mut self, other: RunReport) {
+        self.succeeded.extend(other.succeeded);
+        self.failures.extend(other.failures);
+        self.skipped.extend(other.skipped);
+        self.skipped_steps.extend(other.skipped_steps);
+        self.persistent_exits.extend(other.persistent_exits);
+        self.timings.extend(other.timings);
+        self.stopped_by = self.stopped_by.take().or(other.stopped_by);
+        self.cancelled.extend(other.cancelled);
+        self.interrupted |= other.interrupted;
+    }
+
+    // A persistent step can fail after its job already started successfully
+    fn push_failure(&mut self, failure: Failure) {
+        self.succeeded.retain(|id| *id != failure.error.job_id);
+        self.failures.push(failure);
+    }
+
+    /// JSON summary of the run with the outcome of every job, for consumption by scripts
+    pub fn to_status_json(&self, policy: ExitCodePolicy) -> String {
+        let succeeded = self.succeeded.iter().map(|id| JobStatus {
+            id,
+            outcome: Outcome::Succeeded,
+            exit_code: Some(0),
+            reason: None,
+            message: None,
+        });
+        let failed = self.failures.iter().map(|f| JobStatus {
+            id: &f.error.job_id,
+            outcome: Outcome::Failed,
+            exit_code: Some(f.error.exit_code),
+            reason: f.reason.as_deref(),
+            message: f.error.message.as_deref(),
+        });
+        let skipped = self.skipped.iter().map(|s| JobStatus {
+            id: &s.job_id,
+            outcome: Outcome::Skipped,
+            exit_code: None,
+            reason: Some(&s.reason),
+            message: None,
+        });
+        let cancelled = self.cancelled.iter().map(|id| JobStatus {
+            id,
+            outcome: Outcome::Cancelled,
+            exit_code: None,
+            reason: None,
+            message: None,
+        });
+
+        let persistent = self.persistent_exits.iter().map(|e| PersistentStatus {
+            id: &e.job_id,
+            step: e.step,
+            exit_code: match e.termination {
+                Termination::Exited(code) => Some(code),
+                Termination::Signaled(_) => None,
+            },
+            signal: match e.termination {
+                Termination::Exited(_) => None,
+                Termination::Signaled(signal) => Some(signal),
+            },
+            during_shutdown: e.during_shutdown,
+        });
+
+        let status = Status {
+            exit_code: self.exit_code(policy),
+            stopped_by: self.stopped_by.as_deref(),
+            jobs: succeeded
+                .chain(failed)
+                .chain(skipped)
+                .chain(cancelled)
+                .collect(),
+            persistent: persistent.collect(),
+        };
+        serde_json::to_string_pretty(&status).expect("status is always serializable")
+    }
+
+    /// Every skipped job and step along with why it was skipped, for `--explain-skips`
+    pub fn format_skips(&self) -> String {
+        if self.skipped.is_empty() && self.skipped_steps.is_empty() {
+            return String::from("Nothing was skipped\n");
+        }
+
+        let mut result = String::from("Skipped:\n");
+        for skip in self.skipped.iter().chain(self.skipped_steps.iter()) {
+            result.push_str(&format!("  {skip}\n"));
+        }
+        result
+    }
+
+    /// ASCII timeline with a bar per job spanning the part of the run it was running in
+    pub fn format_gantt(&self) -> String {
+        const WIDTH: usize = 40;
+
+        let Some(start) = self.timings.iter().map(|t| t.started).min() else {
+            return String::new();
+        };
+        let offset = |time: SystemTime| time.duration_since(start).unwrap_or_default();
+        let total = self
+            .timings
+            .iter()
+            .map(|t| offset(t.finished))
+            .max()
+            .unwrap_or_default();
+        let column = |time: SystemTime| match total.is_zero() {
+            true => 0,
+            false => {
+                (offset(time).as_secs_f64() / total.as_secs_f64() * WIDTH as f64).round() as usize
+            }
+        };
+        let name_width = self
+            .timings
+            .iter()
+            .map(|t| t.job_id.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut result = String::new();
+        for timing in self.timings.iter() {
+            let from = column(timing.started).min(WIDTH - 1);
+            let to = column(timing.finished).clamp(from + 1, WIDTH);
+            let elapsed = timing
+                .finished
+                .duration_since(timing.started)
+                .unwrap_or_default();
+            result.push_str(&format!(
+                "{:<name_width$} |{}{}{}| {:?}\n",
+                timing.job_id,
+                " ".repeat(from),
+                "#".repeat(to - from),
+                " ".repeat(WIDTH - to),
+                Duration::from_millis(elapsed.as_millis() as u64),
+            ));
+        }
+        result
+    }
+
+    /// JUnit XML report with a testcase per job, for CI tools that display test results
+    pub fn to_junit_xml(&self) -> String {
+        let time = |job_id: &str| {
+            self.timings
+                .iter()
+                .filter(|t| t.job_id == job_id)
+                .map(|t| t.finished.duration_since(t.started).unwrap_or_default())
+                .sum::<Duration>()
+                .as_secs_f64()
+        };
+        let testcase = |job_id: &str, body: Option<String>| {
+            let open = format!(
+                "    <testcase name=\"{}\" classname=\"uni\" time=\"{:.3}\"",
+                xml_escape(job_id),
+                time(job_id)
+            );
+            match body {
+                Some(body) => format!("{open}>\n      {body}\n    </testcase>\n"),
+                None => format!("{open}/>\n"),
+            }
+        };
+
+        let mut cases = String::new();
+        for job_id in self.succeeded.iter() {
+            cases.push_str(&testcase(job_id, None));
+        }
+        for failure in self.failures.iter() {
+            let message = match &failure.reason {
+                Some(reason) => format!("{}: {reason}", failure.error),
+                None => failure.error.to_string(),
+            };
+            let mut details = format!(
+                "{}\nexit code: {}",
+                failure.error.command, failure.error.exit_code
+            );
+            if let Some(message) = &failure.error.message {
+                details.push_str(&format!("\n{message}"));
+            }
+            let body = format!(
+                "<failure message=\"{}\">{}</failure>",
+                xml_escape(&message),
+                xml_escape(&details)
+            );
+            cases.push_str(&testcase(&failure.error.job_id, Some(body)));
+        }
+        for skip in self.skipped.iter() {
+            let body = format!("<skipped message=\"{}\"/>", xml_escape(&skip.reason));
+            cases.push_str(&testcase(&skip.job_id, Some(body)));
+        }
+
+        let total = self
+            .timings
+            .iter()
+            .map(|t| t.finished.duration_since(t.started).unwrap_or_default())
+            .sum::<Duration>();
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuites>\n  \
+             <testsuite name=\"uni\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n\
+             {cases}  </testsuite>\n\
+             </testsuites>\n",
+            self.succeeded.len() + self.failures.len() + self.skipped.len(),
+            self.failures.len(),
+            self.skipped.len(),
+            total.as_secs_f64(),
+        )
+    }
+
+    /// Exit code of the run: one from its failures, or [`CANCELLED_EXIT_CODE`] for a run that was
+    /// cancelled without failing. Steps that exited with 0 but failed one of their checks count
+    /// as 1, so the run still fails.
+    pub fn exit_code(&self, policy: ExitCodePolicy) -> i32 {
+        if self.failures.is_empty() && self.interrupted {
+            return CANCELLED_EXIT_CODE;
+        }
+        let mut codes = self.failures.iter().map(|f| match f.error.exit_code {
+            0 => 1,
+            code => code,
+        });
+        match policy {
+            ExitCodePolicy::First => codes.next().unwrap_or(0),
+            ExitCodePolicy::Last => codes.next_back().unwrap_or(0),
+            ExitCodePolicy::Max => codes.max().unwrap_or(0),
+            // Exit codes are truncated to 8 bits, so saturate rather than wrap back to success
+            ExitCodePolicy::Count => self.failures.len().min(255) as i32,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Status<'a> {
+    exit_code: i32,
+    stopped_by: Option<&'a str>,
+    jobs: Vec<JobStatus<'a>>,
+    persistent: Vec<PersistentStatus<'a>>,
+}
+
+#[derive(Serialize)]
+struct PersistentStatus<'a> {
+    id: &'a str,
+    step: usize,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    during_shutdown: bool,
+}
+
+#[derive(Serialize)]
+struct JobStatus<'a> {
+    id: &'a str,
+    #[serde(rename = "status")]
+    outcome: Outcome,
+    exit_code: Option<i32>,
+    reason: Option<&'a str>,
+    /// The `on_failure_message` of the step that failed
+    message: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Succeeded,
+    Failed,
+    Skipped,
+    Cancelled,
+}
+
+/// Escape text for use in XML attributes and content
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Convert an exit status to a shell-style exit code. Processes killed by a signal map to
+/// 128 + the signal number.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_error(job_id: &str, exit_code: i32) -> StepError {
+        StepError {
+            job_id: job_id.into(),
+            step: 0,
+            name: None,
+            command: "true".into(),
+            exit_code,
+            signal: None,
+            message: None,
+        }
+    }
+
+    fn report(codes: &[i32]) -> RunReport {
+        RunReport {
+            failures: codes
+                .iter()
+                .map(|&exit_code| Failure {
+                    error: step_error("job", exit_code),
+                    reason: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_exit_code_no_failures() {
+        let report = report(&[]);
+
+        for policy in ExitCodePolicy::value_variants() {
+            assert_eq!(report.exit_code(*policy), 0);
+        }
+    }
+
+    #[test]
+    fn test_exit_code_cancelled() {
+        let mut report = report(&[]);
+        report.record_success("build");
+        report.record_cancel("test");
+
+        for policy in ExitCodePolicy::value_variants() {
+            assert_eq!(report.exit_code(*policy), CANCELLED_EXIT_CODE);
+        }
+        let status: serde_json::Value =
+            serde_json::from_str(&report.to_status_json(ExitCodePolicy::First)).unwrap();
+        assert_eq!(status["exit_code"], 130);
+        assert_eq!(
+            status["jobs"][1],
+            serde_json::json!({ "id": "test", "status": "cancelled", "exit_code": null, "reason": null, "message": null })
+        );
+
+        // Failures still decide the exit code
+        report.record_failure(step_error("lint", 2));
+        assert_eq!(report.exit_code(ExitCodePolicy::First), 2);
+    }
+
+    #[test]
+    fn test_exit_code_policies() {
+        let report = report(&[2, 101, 1]);
+
+        assert_eq!(report.exit_code(ExitCodePolicy::First), 2);
+        assert_eq!(report.exit_code(ExitCodePolicy::Last), 1);
+        assert_eq!(report.exit_code(ExitCodePolicy::Max), 101);
+        assert_eq!(report.exit_code(ExitCodePolicy::Count), 3);
+    }
+
+    #[test]
+    fn test_exit_code_count_saturates() {
+        let report = report(&[1; 300]);
+
+        assert_eq!(report.exit_code(ExitCodePolicy::Count), 255);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut merged = report(&[1]);
+        merged.record_success("build");
+        merged.merge(report(&[2]));
+
+        assert_eq!(merged.succeeded, vec![String::from("build")]);
+        assert_eq!(merged.exit_code(ExitCodePolicy::Last), 2);
+    }
+
+    #[test]
+    fn test_record_rejection_of_successful_step() {
+        let mut report = RunReport::default();
+        report.record_rejection(step_error("build", 0), "output exceeded 10 bytes".into());
+
+        assert_eq!(report.failures[0].error.exit_code, 0);
+        assert_eq!(report.exit_code(ExitCodePolicy::First), 1);
+    }
+
+    #[test]
+    fn test_to_status_json() {
+        let mut report = RunReport::default();
+        report.record_success("build");
+        report.record_success("serve");
+        report.record_failure(StepError {
+            message: Some("Run `uni run lint` for details".into()),
+            ..step_error("serve", 2)
+        });
+        report.record_skip("docs", "no changes since 'main'".into(), Vec::new());
+        report.persistent_exits.push(PersistentExit {
+            job_id: "serve".into(),
+            step: 0,
+            termination: Termination::Exited(2),
+            during_shutdown: false,
+        });
+
+        let status: serde_json::Value =
+            serde_json::from_str(&report.to_status_json(ExitCodePolicy::First)).unwrap();
+
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "exit_code": 2,
+                "stopped_by": null,
+                "jobs": [
+                    { "id": "build", "status": "succeeded", "exit_code": 0, "reason": null, "message": null },
+                    { "id": "serve", "status": "failed", "exit_code": 2, "reason": null, "message": "Run `uni run lint` for details" },
+                    { "id": "docs", "status": "skipped", "exit_code": null, "reason": "no changes since 'main'", "message": null },
+                ],
+                "persistent": [
+                    { "id": "serve", "step": 0, "exit_code": 2, "signal": null, "during_shutdown": false },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_junit_xml() {
+        let start = SystemTime::UNIX_EPOCH;
+        let mut report = RunReport::default();
+        report.record_success("build");
+        report.record_failure(step_error("test", 2));
+        report.record_skip("docs", "no changes since 'main'".into(), Vec::new());
+        report.timings.push(JobTiming {
+            job_id: "build".into(),
+            started: start,
+            finished: start + Duration::from_millis(1500),
+        });
+
+        let xml = report.to_junit_xml();
+
+        assert!(xml
+            .contains(r#"<testsuite name="uni" tests="3" failures="1" skipped="1" time="1.500">"#));
+        assert!(xml.contains(r#"<testcase name="build" classname="uni" time="1.500"/>"#));
+        assert!(xml.contains("failed with exit code 2\">"));
+        assert!(xml.contains(r#"<skipped message="no changes since &apos;main&apos;"/>"#));
+    }
+
+    #[test]
+    fn test_format_skips() {
+        let mut report = RunReport::default();
+        assert_eq!(report.format_skips(), "Nothing was skipped\n");
+
+        report.record_skip("deploy", "dependency 'build' failed".into(), Vec::new());
+        report.record_step_skip(
+            "build",
+            1,
+            "not for this platform".into(),
+            vec!["platforms: windows".into(), "os: linux".into()],
+        );
+
+        assert_eq!(
+            report.format_skips(),
+            "Skipped:\n  \
+             deploy: dependency 'build' failed\n  \
+             build/1: not for this platform (platforms: windows; os: linux)\n"
+        );
+    }
+
+    #[test]
+    fn test_format_gantt() {
+        let start = SystemTime::UNIX_EPOCH;
+        let timing = |job_id: &str, from: u64, to: u64| JobTiming {
+            job_id: job_id.into(),
+            started: start + Duration::from_secs(from),
+            finished: start + Duration::from_secs(to),
+        };
+        let report = RunReport {
+            timings: vec![timing("build", 0, 10), timing("lint", 10, 40)],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            report.format_gantt(),
+            format!(
+                "build |{}{}| 10s\nlint  |{}{}| 30s\n",
+                "#".repeat(10),
+                " ".repeat(30),
+                " ".repeat(10),
+                "#".repeat(30)
+            )
+        );
+        assert_eq!(RunReport::default().format_gantt(), "");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_persistent_exit_display() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut report = RunReport::default();
+        report.record_persistent_exit("dev", 0, ExitStatus::from_raw(9), false);
+        report.record_persistent_exit("dev", 1, ExitStatus::from_raw(15), true);
+        report.record_persistent_exit("db", 0, ExitStatus::from_raw(3 << 8), false);
+
+        let lines = report
+            .persistent_exits
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                "Persistent step dev/0 was killed by signal 9 (SIGKILL)",
+                "Persistent step dev/1 was killed by signal 15 (SIGTERM) during shutdown",
+                "Persistent step db/0 exited with code 3",
+            ]
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_exit_code_signal() {
+        use std::os::unix::process::ExitStatusExt;
+
+        // Raw wait status for a process terminated by SIGKILL
+        let status = ExitStatus::from_raw(9);
+
+        assert_eq!(exit_code(status), 137);
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::runfile::JobId;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResumeError {
+    NoPreviousRun,
+    RunfileChanged,
+}
+
+impl Error for ResumeError {}
+
+impl Display for ResumeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResumeError::NoPreviousRun => write!(f, "there is no previous run to resume"),
+            ResumeError::RunfileChanged => {
+                write!(f, "the runfile changed since the previous run")
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct LastRun {
+    runfile_hash: u64,
+    /// Jobs along with the hash of their inputs when they succeeded, see
+    /// [`crate::inputs::hash_inputs`]
+    succeeded: Vec<(JobId, Option<u64>)>,
+}
+
+/// Jobs that succeeded in previous runs of the same runfile, with the hashes of their inputs,
+/// for `--resume`
+pub struct RunState {
+    path: PathBuf,
+    runfile_hash: u64,
+}
+
+impl RunState {
+    pub fn new(path: PathBuf, runfile_content: &str) -> RunState {
+        let mut hasher = DefaultHasher::new();
+        runfile_content.hash(&mut hasher);
+
+        RunState {
+            path,
+            runfile_hash: hasher.finish(),
+        }
+    }
+
+    /// Jobs that succeeded in the previous run. Missing or malformed state counts as no previous
+    /// run, and the jobs of a runfile that has changed since are no longer known to succeed.
+    pub fn load(&self) -> Result<HashMap<JobId, Option<u64>>, ResumeError> {
+        let last_run = std::fs::read(&self.path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<LastRun>(&bytes).ok())
+            .ok_or(ResumeError::NoPreviousRun)?;
+        if last_run.runfile_hash != self.runfile_hash {
+            return Err(ResumeError::RunfileChanged);
+        }
+        Ok(last_run.succeeded.into_iter().collect())
+    }
+
+    /// Store the jobs that succeeded. The state is best-effort, so failures are ignored.
+    pub fn store(&self, succeeded: &HashMap<JobId, Option<u64>>) {
+        let mut succeeded = succeeded
+            .iter()
+            .map(|(id, hash)| (id.clone(), *hash))
+            .collect::<Vec<_>>();
+        succeeded.sort();
+        let last_run = LastRun {
+            runfile_hash: self.runfile_hash,
+            succeeded,
+        };
+
+        if let Some(parent) = self.path.parent() {
+            _ = std::fs::create_dir_all(parent);
+        }
+        if let Ok(json) = serde_json::to_vec(&last_run) {
+            _ = std::fs::write(&self.path, json);
+        }
+    }
+}
+
+/// The jobs that `--resume` skips: those that `succeeded` with the same `current` hash of their
+/// inputs, or without inputs
+pub fn resumable(
+    succeeded: &HashMap<JobId, Option<u64>>,
+    current: &HashMap<JobId, Option<u64>>,
+) -> HashSet<JobId> {
+    succeeded
+        .iter()
+        .filter(|&(id, hash)| current.get(id) == Some(hash))
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_store_and_load() {
+        let dir = TempDir::new("last-run");
+        let path = dir.join("last-run.json");
+        let state = RunState::new(path.clone(), "default: build");
+
+        assert_eq!(state.load(), Err(ResumeError::NoPreviousRun));
+
+        let succeeded = HashMap::from([("build".into(), Some(1)), ("lint".into(), None)]);
+        state.store(&succeeded);
+        assert_eq!(state.load(), Ok(succeeded));
+
+        let changed = RunState::new(path.clone(), "default: test");
+        assert_eq!(changed.load(), Err(ResumeError::RunfileChanged));
+    }
+
+    #[test]
+    fn test_resumable() {
+        let succeeded = HashMap::from([
+            ("build".into(), Some(1)),
+            ("lint".into(), None),
+            ("test".into(), Some(3)),
+        ]);
+        let current = HashMap::from([
+            ("build".into(), Some(2)),
+            ("lint".into(), None),
+            ("test".into(), Some(3)),
+        ]);
+
+        // Inputs of `build` changed since it succeeded
+        assert_eq!(
+            resumable(&succeeded, &current),
+            HashSet::from(["lint".into(), "test".into()])
+        );
+    }
+}
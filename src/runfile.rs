@@ -1,47 +1,883 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    time::Duration,
+};
 
-use serde::Deserialize;
-use serde_with::{serde_as, DurationMilliSeconds};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::{
+    condition::{self, ConditionError},
+    duration::HumanDuration,
+    expression,
+    watch::WatchSet,
+};
 
 pub type JobId = String;
 
-#[derive(Clone, Debug, Deserialize)]
+/// Values of `std::env::consts::OS` accepted in a step's `platforms`
+pub const KNOWN_PLATFORMS: [&str; 10] = [
+    "linux",
+    "macos",
+    "windows",
+    "ios",
+    "android",
+    "freebsd",
+    "dragonfly",
+    "netbsd",
+    "openbsd",
+    "solaris",
+];
+
+/// File names a runfile is looked up under, in order of precedence
+pub const RUNFILE_NAMES: [&str; 2] = ["uni.yaml", "uni.yml"];
+
+#[derive(Clone, Debug)]
+pub struct RunfileNotFoundError;
+
+impl Error for RunfileNotFoundError {}
+
+impl Display for RunfileNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No runfile found in the current directory, looked for {}",
+            RUNFILE_NAMES
+                .iter()
+                .map(|name| format!("'{name}'"))
+                .join(", ")
+        )
+    }
+}
+
+/// Find the runfile in `dir`. Names are tried in the order of [`RUNFILE_NAMES`], and each name
+/// also matches case-insensitively, e.g. `Uni.yaml`, with an exact match taking precedence.
+pub fn discover(dir: &Path) -> Result<PathBuf, RunfileNotFoundError> {
+    let entries = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .sorted()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    RUNFILE_NAMES
+        .iter()
+        .find_map(|&name| {
+            entries.iter().find(|entry| *entry == name).or_else(|| {
+                entries
+                    .iter()
+                    .find(|entry| entry.eq_ignore_ascii_case(name))
+            })
+        })
+        .map(|name| dir.join(name))
+        .ok_or(RunfileNotFoundError)
+}
+
+/// An alias of more than one job, which can't tell which of them to run
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AmbiguousAliasError {
+    alias: String,
+    jobs: Vec<JobId>,
+}
+
+impl Error for AmbiguousAliasError {}
+
+impl Display for AmbiguousAliasError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' is an alias of several jobs: {}",
+            self.alias,
+            self.jobs.iter().map(|id| format!("'{id}'")).join(", ")
+        )
+    }
+}
+
+/// Why the steps of a job can't be put in an order that satisfies their `needs`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepOrderError {
+    UnknownStep { step: usize, reference: String },
+    Cycle(Vec<usize>),
+}
+
+impl Error for StepOrderError {}
+
+impl Display for StepOrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepOrderError::UnknownStep { step, reference } => {
+                write!(
+                    f,
+                    "step {step} needs step '{reference}', which is not defined"
+                )
+            }
+            StepOrderError::Cycle(steps) => {
+                write!(
+                    f,
+                    "steps {} can't run, since their `needs` form a cycle",
+                    steps.iter().join(", ")
+                )
+            }
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Runfile {
+    /// Job run when none is given, the same as `defaults` with a single job
+    #[serde(default)]
     pub default: JobId,
+
+    /// Jobs run when none is given, of which the first that can run is picked
+    #[serde(default)]
+    pub defaults: Vec<JobId>,
+
     pub jobs: HashMap<JobId, Job>,
+
+    /// Parent environment variables (names or globs) forwarded to steps in clean env mode
+    #[serde(default)]
+    pub pass_env: Vec<String>,
+
+    /// Default output limit for every step, see [`Step::max_output_bytes`]
+    #[serde(default)]
+    pub max_output_bytes: Option<u64>,
+
+    /// Default shell for every step, see [`Step::shell`]
+    #[serde(default)]
+    pub default_shell: Option<String>,
+
+    /// Environment variables of every step, overridden by a step's own `env`
+    #[serde(default)]
+    pub default_env: HashMap<String, String>,
+
+    /// Default working directory for every step, see [`Step::workdir`]
+    #[serde(default)]
+    pub default_workdir: Option<PathBuf>,
+
+    /// Default timeout for every job, see [`Job::timeout`]
+    #[serde_as(as = "Option<HumanDuration>")]
+    #[serde(default)]
+    pub default_timeout: Option<Duration>,
+
+    /// Values conditions refer to as `${{ params.<name> }}`, overridden with `--param`
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+
+    /// Paths watched with `--watch` besides the `watch` paths of the jobs to run. Paths
+    /// starting with `!` are excluded, see [`crate::watch::WatchSet`]
+    #[serde(default)]
+    pub watch: Vec<String>,
+
+    /// Names or paths never watched, replacing the usual version control and build directories
+    /// of [`crate::watch::DEFAULT_IGNORES`]
+    #[serde(default)]
+    pub watch_ignore: Option<Vec<String>>,
+
+    /// Words in commands that `uni validate` takes as a hint that a step keeps running, replacing
+    /// [`crate::validate::LONG_RUNNING_PATTERNS`]
+    #[serde(default)]
+    pub long_running_patterns: Option<Vec<String>>,
+
+    /// Print each step's command before running it, like `uni run --print-command`
+    #[serde(default)]
+    pub print_commands: bool,
+
+    /// Values commands refer to as `${{ vars.<name> }}`, substituted when the runfile is loaded.
+    /// Unlike `default_env`, they aren't passed to the steps' processes
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+
+    /// Directory of the runfile, which its relative paths are resolved against. Set when it's
+    /// loaded, since the working directory can change afterwards with a deferred `-C`
+    #[serde(skip)]
+    pub dir: PathBuf,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[serde_as]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Job {
     #[serde(default)]
     pub name: Option<String>,
 
     #[serde(default)]
-    pub needs: Vec<JobId>,
+    pub needs: Vec<Need>,
 
     #[serde(default)]
     pub steps: Vec<Step>,
+
+    #[serde(default)]
+    pub tags: Vec<String>,
+
+    /// Only run the job when files under one of these paths changed
+    #[serde(default)]
+    pub when_changed: Option<WhenChanged>,
+
+    /// Run one instance of the job per line printed by a command
+    #[serde(default)]
+    pub for_each: Option<ForEach>,
+
+    /// Maximum time for all of the job's steps to finish. Persistent steps are excluded once
+    /// started, since they are meant to keep running.
+    #[serde_as(as = "Option<HumanDuration>")]
+    #[serde(default)]
+    pub timeout: Option<Duration>,
+
+    /// Only run the job when it is named explicitly or needed by another job, never through
+    /// `--tag` or as the default
+    #[serde(default)]
+    pub manual: bool,
+
+    /// Jobs with a higher weight run first among those whose dependencies have all run
+    #[serde(default)]
+    pub weight: u32,
+
+    /// Env file relative to the runfile loaded for the job's steps, overridden by their own
+    /// `env_file` and `env`
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
+    /// Other names the job can be run by, e.g. `t` for `test`
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// Run the job's steps in an empty temporary directory instead of the project directory
+    #[serde(default)]
+    pub isolate: bool,
+
+    /// Stop the run before the job starts if the git working tree has uncommitted changes, e.g.
+    /// for releases
+    #[serde(default)]
+    pub requires_clean_git: bool,
+
+    /// Paths relative to the runfile made available in the temporary directory of an isolated
+    /// job
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Paths relative to the runfile (directories, files, or globs) whose changes reload the
+    /// job's persistent steps, or run the target again, in `uni run --watch`
+    #[serde(default)]
+    pub watch: Vec<String>,
+}
+
+/// A `${{ vars.<name> }}` expression in a command that can't be substituted
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VarError {
+    /// Where the command is, e.g. `job 'build' step 0`
+    location: String,
+    message: String,
+}
+
+impl Error for VarError {}
+
+impl Display for VarError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+impl Runfile {
+    /// Substitute the runfile's `vars` for the `${{ vars.<name> }}` expressions in every job: in
+    /// steps' `run`, `script`, `ready`, `workdir` and `env` values, and in `for_each`. Other
+    /// expressions are left as they are, for conditions and secrets to resolve later.
+    pub fn substitute_vars(&mut self) -> Result<(), VarError> {
+        let vars = &self.vars;
+        for (id, job) in self.jobs.iter_mut().sorted_by(|(a, _), (b, _)| a.cmp(b)) {
+            if let Some(for_each) = &mut job.for_each {
+                for_each.command =
+                    substitute_vars(&for_each.command, vars).map_err(|message| VarError {
+                        location: format!("job '{id}' for_each"),
+                        message,
+                    })?;
+            }
+            for (index, step) in job.steps.iter_mut().enumerate() {
+                let error = |message| VarError {
+                    location: format!("job '{id}' step {index}"),
+                    message,
+                };
+                step.command = substitute_vars(&step.command, vars).map_err(error)?;
+                for text in step.script.iter_mut().chain(step.ready.iter_mut()) {
+                    *text = substitute_vars(text, vars).map_err(error)?;
+                }
+                for value in step.env.values_mut() {
+                    *value = substitute_vars(value, vars).map_err(error)?;
+                }
+                if let Some(workdir) = step.workdir.as_deref().and_then(Path::to_str) {
+                    step.workdir = Some(substitute_vars(workdir, vars).map_err(error)?.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The `defaults`, or `default` if there are none
+    pub fn default_jobs(&self) -> Vec<&str> {
+        match self.defaults.is_empty() {
+            false => self.defaults.iter().map(String::as_str).collect(),
+            true if self.default.is_empty() => Vec::new(),
+            true => vec![self.default.as_str()],
+        }
+    }
+
+    /// Ids of the jobs `job` needs, leaving out those whose condition doesn't hold
+    pub fn needs<'a>(&self, job: &'a Job) -> Result<Vec<&'a JobId>, ConditionError> {
+        let mut needs = Vec::new();
+        for need in job.needs.iter() {
+            let active = match &need.condition {
+                Some(condition) => condition::evaluate(condition, &self.params)?,
+                None => true,
+            };
+            if active {
+                needs.push(&need.job);
+            }
+        }
+        Ok(needs)
+    }
+
+    /// Id of the job `id` is an alias of, or `id` itself if it isn't an alias. Job ids take
+    /// precedence over aliases, and an alias of several jobs is an error rather than whichever
+    /// is found first.
+    pub fn resolve_alias<'a>(&'a self, id: &'a str) -> Result<&'a str, AmbiguousAliasError> {
+        if self.jobs.contains_key(id) {
+            return Ok(id);
+        }
+        let jobs = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.aliases.iter().any(|alias| alias == id))
+            .map(|(job_id, _)| job_id.as_str())
+            .sorted()
+            .collect::<Vec<_>>();
+        match jobs[..] {
+            [] => Ok(id),
+            [job_id] => Ok(job_id),
+            _ => Err(AmbiguousAliasError {
+                alias: id.to_owned(),
+                jobs: jobs.into_iter().map(str::to_owned).collect(),
+            }),
+        }
+    }
+
+    /// What to watch for `paths`, leaving out the runfile's `watch_ignore` and `state_dir`
+    pub fn watch_set(&self, paths: &[String], state_dir: &Path) -> WatchSet {
+        WatchSet::new(
+            paths.iter().cloned(),
+            self.watch_ignore.as_deref(),
+            state_dir,
+        )
+    }
+}
+
+impl Job {
+    /// Index of the step `reference` refers to, either by its index or its `name`
+    pub fn step_index(&self, reference: &str) -> Option<usize> {
+        reference
+            .parse::<usize>()
+            .ok()
+            .filter(|&index| index < self.steps.len())
+            .or_else(|| {
+                self.steps
+                    .iter()
+                    .position(|s| s.name.as_deref() == Some(reference))
+            })
+    }
+
+    /// Indices of the steps in the order they run: one at a time, each as soon as the steps in
+    /// its `needs` have run, and otherwise in the order they are listed
+    pub fn step_order(&self) -> Result<Vec<usize>, StepOrderError> {
+        let mut needs = Vec::with_capacity(self.steps.len());
+        for (index, step) in self.steps.iter().enumerate() {
+            let step_needs = step
+                .needs
+                .iter()
+                .map(|reference| {
+                    self.step_index(reference)
+                        .ok_or_else(|| StepOrderError::UnknownStep {
+                            step: index,
+                            reference: reference.clone(),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            needs.push(step_needs);
+        }
+
+        let mut order = Vec::with_capacity(self.steps.len());
+        let mut done = vec![false; self.steps.len()];
+        // The first ready step goes next, so steps without `needs` keep their order
+        while let Some(next) =
+            (0..self.steps.len()).find(|&i| !done[i] && needs[i].iter().all(|&n| done[n]))
+        {
+            done[next] = true;
+            order.push(next);
+        }
+        match order.len() == self.steps.len() {
+            true => Ok(order),
+            false => Err(StepOrderError::Cycle(
+                (0..self.steps.len()).filter(|&i| !done[i]).collect(),
+            )),
+        }
+    }
+}
+
+/// Job listed in `needs`, either a bare job id or a mapping with the condition it is needed under
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(from = "NeedDef")]
+pub struct Need {
+    pub job: JobId,
+
+    /// Only needed while this holds, see [`condition::evaluate`]
+    pub condition: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expected a job id or a mapping with `job` and `if`"
+)]
+enum NeedDef {
+    Id(JobId),
+    Conditional(ConditionalNeed),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConditionalNeed {
+    job: JobId,
+    #[serde(rename = "if")]
+    condition: Option<String>,
+}
+
+impl From<NeedDef> for Need {
+    fn from(def: NeedDef) -> Self {
+        match def {
+            NeedDef::Id(job) => Need::from(job),
+            NeedDef::Conditional(ConditionalNeed { job, condition }) => Need { job, condition },
+        }
+    }
+}
+
+impl From<JobId> for Need {
+    fn from(job: JobId) -> Self {
+        Need {
+            job,
+            condition: None,
+        }
+    }
+}
+
+impl From<&str> for Need {
+    fn from(job: &str) -> Self {
+        Need::from(job.to_owned())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ForEach {
+    /// Command whose non-empty output lines are the values to run the job with
+    pub command: String,
+
+    /// Environment variable each instance's value is bound to
+    #[serde(rename = "as")]
+    pub var: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WhenChanged {
+    /// Paths (directories, files, or globs) relative to the runfile
+    pub paths: Vec<String>,
+
+    /// Git revision that changes are compared against
+    pub base: String,
+}
+
+/// When a persistent step is restarted after it exits
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    No,
+    OnFailure,
+    Always,
+}
+
+/// How a step still running when it times out is stopped
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimeoutAction {
+    /// Ask the process to exit, killing it if it is still running after a grace period
+    #[default]
+    Terminate,
+    /// Kill the process immediately
+    Kill,
+}
+
+fn default_max_restarts() -> u32 {
+    5
+}
+
+fn default_success_exit_codes() -> Vec<i32> {
+    vec![0]
+}
+
+fn default_restart_delay() -> Duration {
+    Duration::from_secs(1)
+}
+
+fn default_ready_timeout() -> Duration {
+    Duration::from_secs(30)
 }
 
 #[serde_as]
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[cfg_attr(test, derive(PartialEq))]
+#[serde(deny_unknown_fields)]
 pub struct Step {
+    /// Command to run, unless the step has a `script`
     #[serde(rename(deserialize = "run"))]
+    #[serde(default)]
     pub command: String,
 
+    /// Body of a script to run with `interpreter` instead of a command. It's written to a
+    /// temporary file with only `vars` substituted, and the file path passed to the interpreter
+    #[serde(default)]
+    pub script: Option<String>,
+
+    /// Command the `script` is run with, e.g. `python3`
     #[serde(default)]
-    pub persistent: bool,
+    pub interpreter: Option<String>,
+
+    /// Shell the command is passed to after `-c` (`/C` for `cmd`), e.g. `bash -eu`, instead of
+    /// being run directly. The shell then does all substitution
+    #[serde(default)]
+    pub shell: Option<String>,
+
+    /// Human-readable name used in diagnostics and `--set` paths
+    #[serde(default)]
+    pub name: Option<String>,
 
-    #[serde_as(as = "DurationMilliSeconds<u64>")]
+    /// Shown along with the exit code when the step fails, e.g. what to do about it
+    #[serde(default)]
+    pub on_failure_message: Option<String>,
+
+    /// Run the step in the background for the rest of the run. Set to `false` explicitly to
+    /// silence the warning about steps that look long-running
+    #[serde(default)]
+    pub persistent: Option<bool>,
+
+    #[serde_as(as = "HumanDuration")]
     #[serde(default)]
     pub startup_delay: Duration,
+
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Env file relative to the runfile loaded for the step, overridden by its own `env`
+    #[serde(default)]
+    pub env_file: Option<PathBuf>,
+
+    /// Directory the step runs in, relative to the runfile (or the temporary directory of an
+    /// isolated job), e.g. a package of a monorepo
+    #[serde(default)]
+    pub workdir: Option<PathBuf>,
+
+    /// Persistent steps with a higher priority are stopped first during shutdown
+    #[serde(default)]
+    pub shutdown_priority: i32,
+
+    /// How long a persistent step may take to exit once asked to during shutdown before it's
+    /// killed, instead of a short default
+    #[serde_as(as = "Option<HumanDuration>")]
+    #[serde(default)]
+    pub shutdown_timeout: Option<Duration>,
+
+    /// Combined stdout and stderr bytes shown before the rest of the output is discarded
+    #[serde(default)]
+    pub max_output_bytes: Option<u64>,
+
+    /// Fail the step if its output was truncated
+    #[serde(default)]
+    pub fail_on_output_limit: bool,
+
+    /// User (name or uid) to run the command as. Unix only
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// Group (name or gid) to run the command as, defaults to the user's primary group. Unix only
+    #[serde(default)]
+    pub group: Option<String>,
+
+    /// Whether a persistent step is restarted when it exits instead of ending the run
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// Number of restarts after which an exiting step ends the run
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+
+    /// Delay before the first restart, doubled for each subsequent one
+    #[serde_as(as = "HumanDuration")]
+    #[serde(default = "default_restart_delay")]
+    pub restart_delay: Duration,
+
+    /// Fraction between 0 and 1 by which each restart delay is randomly lengthened or
+    /// shortened, so steps restarting together don't all retry at the same moment
+    #[serde(default)]
+    pub restart_jitter: f64,
+
+    /// Discard the command's stdout and stderr. Takes precedence over output limits, but not over
+    /// `stdout_file` and `stderr_file`
+    #[serde(default)]
+    pub silent: bool,
+
+    /// File the command's stdout is written to instead of being printed, relative to the
+    /// directory the step runs in. Missing parent directories are created
+    #[serde(default)]
+    pub stdout_file: Option<PathBuf>,
+
+    /// File the command's stderr is written to instead of being printed, like `stdout_file`
+    #[serde(default)]
+    pub stderr_file: Option<PathBuf>,
+
+    /// Append to `stdout_file` and `stderr_file` instead of truncating them
+    #[serde(default)]
+    pub append: bool,
+
+    /// Unix `nice` value of the command, where higher values lower its scheduling priority.
+    /// Negative values usually need root. Ignored on other platforms
+    #[serde(default)]
+    pub priority: Option<i32>,
+
+    /// Operating systems the step runs on, as named by `std::env::consts::OS`. Empty means all
+    #[serde(default)]
+    pub platforms: Vec<String>,
+
+    /// Run the command as written, without substituting variables or `$(...)` commands
+    #[serde(default)]
+    pub raw: bool,
+
+    /// Command that exits successfully once a persistent step is ready to be used, e.g.
+    /// `pg_isready`. Steps that name this one in `after_ready` wait for it
+    #[serde(default)]
+    pub ready: Option<String>,
+
+    /// How long to wait for the `ready` command to succeed before failing
+    #[serde_as(as = "HumanDuration")]
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout: Duration,
+
+    /// Earlier persistent step of the same job, by index or `name`, whose `ready` command has to
+    /// succeed before this step starts
+    #[serde(default)]
+    pub after_ready: Option<String>,
+
+    /// Steps of the same job, by index or `name`, that have to run before this one, even if
+    /// they are listed after it
+    #[serde(default)]
+    pub needs: Vec<String>,
+
+    /// Exit code with which the step stops the run early without failing it, e.g. when a gate
+    /// finds there is nothing to do
+    #[serde(default)]
+    pub stop_run: Option<i32>,
+
+    /// How the step is stopped when it times out, instead of `--timeout-action`
+    #[serde(default)]
+    pub on_timeout: Option<TimeoutAction>,
+
+    /// Times a non-persistent step is run again after failing before its job fails, instead of
+    /// `--retry-all`
+    #[serde(default)]
+    pub retries: Option<u32>,
+
+    /// Delay before the first retry, doubled for each subsequent one
+    #[serde_as(as = "HumanDuration")]
+    #[serde(default)]
+    pub retry_delay: Duration,
+
+    /// Fraction between 0 and 1 by which each retry delay is randomly lengthened or shortened,
+    /// like `restart_jitter`
+    #[serde(default)]
+    pub retry_jitter: f64,
+
+    /// Let the step finish when the run is cancelled instead of terminating it, and still run it
+    /// if its job was cancelled before reaching it, e.g. for cleanup. Limited by `--signal-grace`
+    #[serde(default)]
+    pub ignore_signals: bool,
+
+    /// Signal sent to the persistent step, e.g. `SIGHUP`, when its job's `watch` paths change,
+    /// instead of restarting it
+    #[serde(default)]
+    pub reload_signal: Option<String>,
+
+    /// Exit codes with which the step succeeds, e.g. `[0, 1]` for `diff`
+    #[serde(default = "default_success_exit_codes")]
+    pub success_exit_codes: Vec<i32>,
+
+    /// Exit code the step has to exit with to succeed, instead of `success_exit_codes`
+    #[serde(default)]
+    pub expect_exit: Option<i32>,
+
+    /// Text the step's stdout has to contain for the step to succeed
+    #[serde(default)]
+    pub expect_stdout_contains: Option<String>,
+
+    /// Regular expression the step's stdout has to match for the step to succeed
+    #[serde(default)]
+    pub expect_stdout_matches: Option<String>,
+
+    /// How long the step may take before it fails, once it has finished. Unlike a job's
+    /// `timeout`, the step isn't stopped when it goes over
+    #[serde_as(as = "Option<HumanDuration>")]
+    #[serde(default)]
+    pub max_duration: Option<Duration>,
+}
+
+impl Step {
+    pub fn is_persistent(&self) -> bool {
+        self.persistent == Some(true)
+    }
+
+    /// Whether the step runs on the operating system `os`
+    pub fn runs_on(&self, os: &str) -> bool {
+        self.platforms.is_empty() || self.platforms.iter().any(|p| p == os)
+    }
+
+    /// Whether the step succeeded by exiting with `status`, see [`Step::success_exit_codes`] and
+    /// [`Step::expect_exit`]
+    pub fn exit_ok(&self, status: ExitStatus) -> bool {
+        let Some(code) = status.code() else {
+            return false;
+        };
+        match self.expect_exit {
+            Some(expected) => code == expected,
+            None => self.success_exit_codes.contains(&code),
+        }
+    }
+
+    /// Whether the step's stdout is checked, see [`Step::expect_stdout_contains`]
+    pub fn expects_stdout(&self) -> bool {
+        self.expect_stdout_contains.is_some() || self.expect_stdout_matches.is_some()
+    }
+}
+
+impl Default for Step {
+    fn default() -> Step {
+        Step {
+            command: String::new(),
+            script: None,
+            interpreter: None,
+            shell: None,
+            name: None,
+            on_failure_message: None,
+            persistent: None,
+            startup_delay: Duration::ZERO,
+            env: HashMap::new(),
+            env_file: None,
+            workdir: None,
+            shutdown_priority: 0,
+            shutdown_timeout: None,
+            max_output_bytes: None,
+            fail_on_output_limit: false,
+            user: None,
+            group: None,
+            restart: RestartPolicy::default(),
+            max_restarts: default_max_restarts(),
+            restart_delay: default_restart_delay(),
+            restart_jitter: 0.0,
+            silent: false,
+            stdout_file: None,
+            stderr_file: None,
+            append: false,
+            priority: None,
+            platforms: Vec::new(),
+            raw: false,
+            stop_run: None,
+            on_timeout: None,
+            retries: None,
+            retry_delay: Duration::ZERO,
+            retry_jitter: 0.0,
+            ignore_signals: false,
+            reload_signal: None,
+            success_exit_codes: default_success_exit_codes(),
+            expect_exit: None,
+            expect_stdout_contains: None,
+            expect_stdout_matches: None,
+            max_duration: None,
+            ready: None,
+            ready_timeout: default_ready_timeout(),
+            after_ready: None,
+            needs: Vec::new(),
+        }
+    }
+}
+
+/// Substitute `vars` for the `${{ vars.<name> }}` expressions in `text`
+fn substitute_vars(text: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    expression::substitute(text, |expression| match expression.strip_prefix("vars.") {
+        Some(name) => match vars.get(name) {
+            Some(value) => Ok(Some(value.clone())),
+            None => Err(format!("var '{name}' is not defined")),
+        },
+        None => Ok(None),
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::testing::TempDir;
+
     use super::*;
 
+    #[test]
+    fn test_step_order() {
+        let step = |name: &str, needs: &[&str]| Step {
+            name: Some(name.into()),
+            needs: needs.iter().map(|&n| n.to_owned()).collect(),
+            ..Default::default()
+        };
+        let job = |steps: Vec<Step>| Job {
+            steps,
+            ..Default::default()
+        };
+
+        let sequential = job(vec![step("a", &[]), step("b", &[]), step("c", &[])]);
+        assert_eq!(sequential.step_order(), Ok(vec![0, 1, 2]));
+        let reordered = job(vec![
+            step("test", &["build"]),
+            step("build", &["fetch"]),
+            step("lint", &[]),
+            step("fetch", &[]),
+        ]);
+        assert_eq!(reordered.step_order(), Ok(vec![2, 3, 1, 0]));
+        let cyclic = job(vec![step("a", &["b"]), step("b", &["a"]), step("c", &[])]);
+        assert_eq!(
+            cyclic.step_order().unwrap_err().to_string(),
+            "steps 0, 1 can't run, since their `needs` form a cycle"
+        );
+        let unknown = job(vec![step("a", &["missing"])]);
+        assert_eq!(
+            unknown.step_order(),
+            Err(StepOrderError::UnknownStep {
+                step: 0,
+                reference: "missing".into()
+            })
+        );
+    }
+
     #[test]
     fn test_deserialize_startup_delay() {
         let toml = r#"
@@ -51,8 +887,9 @@ mod tests {
 
         let expected = Step {
             command: String::from("foo"),
-            persistent: false,
+            persistent: None,
             startup_delay: Duration::from_millis(20),
+            ..Default::default()
         };
 
         let step: Step = serde_yaml::from_str(toml).unwrap();
@@ -60,6 +897,18 @@ mod tests {
         assert_eq!(step, expected);
     }
 
+    #[test]
+    fn test_deserialize_startup_delay_human() {
+        let yaml = r#"
+            run: foo
+            startup_delay: 1m30s
+        "#;
+
+        let step: Step = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(step.startup_delay, Duration::from_secs(90));
+    }
+
     #[test]
     fn test_deserialize_startup_delay_default() {
         let toml = r#"
@@ -68,12 +917,270 @@ mod tests {
 
         let expected = Step {
             command: String::from("foo"),
-            persistent: false,
+            persistent: None,
             startup_delay: Duration::from_millis(0),
+            ..Default::default()
         };
 
         let step: Step = serde_yaml::from_str(toml).unwrap();
 
         assert_eq!(step, expected);
     }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_success_exit_codes() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let yaml = r#"
+            run: diff a b
+            success_exit_codes: [0, 1]
+        "#;
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+
+        let step: Step = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(step.success_exit_codes, vec![0, 1]);
+        assert!(step.exit_ok(exited(0)));
+        assert!(step.exit_ok(exited(1)));
+        assert!(!step.exit_ok(exited(2)));
+        // Killed by SIGTERM
+        assert!(!step.exit_ok(ExitStatus::from_raw(15)));
+
+        let default = Step::default();
+        assert!(default.exit_ok(exited(0)));
+        assert!(!default.exit_ok(exited(1)));
+
+        let expecting = Step {
+            expect_exit: Some(2),
+            ..step
+        };
+        assert!(expecting.exit_ok(exited(2)));
+        assert!(!expecting.exit_ok(exited(1)));
+    }
+
+    #[test]
+    fn test_deserialize_restart() {
+        let yaml = r#"
+            run: foo
+            persistent: true
+            restart: on-failure
+            restart_delay: 500ms
+        "#;
+
+        let step: Step = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(step.restart, RestartPolicy::OnFailure);
+        assert_eq!(step.max_restarts, 5);
+        assert_eq!(step.restart_delay, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_deserialize_env() {
+        let yaml = r#"
+            run: foo
+            env:
+              FOO: bar
+        "#;
+
+        let expected = Step {
+            command: String::from("foo"),
+            env: HashMap::from([("FOO".into(), "bar".into())]),
+            ..Default::default()
+        };
+
+        let step: Step = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(step, expected);
+    }
+
+    #[test]
+    fn test_deserialize_on_timeout() {
+        let yaml = r#"
+            run: ./server
+            on_timeout: kill
+        "#;
+
+        let step: Step = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(step.on_timeout, Some(TimeoutAction::Kill));
+    }
+
+    #[test]
+    fn test_resolve_alias() {
+        let yaml = r#"
+            default: test
+            jobs:
+              test:
+                aliases: [t]
+              build:
+                aliases: [b]
+              bench:
+                aliases: [b]
+        "#;
+
+        let runfile: Runfile = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(runfile.resolve_alias("t"), Ok("test"));
+        assert_eq!(runfile.resolve_alias("test"), Ok("test"));
+        assert_eq!(runfile.resolve_alias("missing"), Ok("missing"));
+        assert_eq!(
+            runfile.resolve_alias("b").unwrap_err().to_string(),
+            "'b' is an alias of several jobs: 'bench', 'build'"
+        );
+    }
+
+    #[test]
+    fn test_needs_conditional() {
+        let yaml = r#"
+            default: deploy
+            params:
+              skip_tests: "false"
+            jobs:
+              build: {}
+              test: {}
+              deploy:
+                needs:
+                  - build
+                  - job: test
+                    if: ${{ params.skip_tests }} != true
+        "#;
+
+        let mut runfile: Runfile = serde_yaml::from_str(yaml).unwrap();
+        let deploy = runfile.jobs["deploy"].clone();
+
+        assert_eq!(deploy.needs[0], Need::from("build"));
+        assert_eq!(
+            deploy.needs[1].condition.as_deref(),
+            Some("${{ params.skip_tests }} != true")
+        );
+        assert_eq!(runfile.needs(&deploy).unwrap(), vec!["build", "test"]);
+        runfile.params.insert("skip_tests".into(), "true".into());
+        assert_eq!(runfile.needs(&deploy).unwrap(), vec!["build"]);
+        runfile.params.clear();
+        assert!(runfile.needs(&deploy).is_err());
+    }
+
+    #[test]
+    fn test_deserialize_job_timeout() {
+        let yaml = r#"
+            timeout: 10m
+            steps:
+              - run: foo
+        "#;
+
+        let job: Job = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(job.timeout, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn test_substitute_vars() {
+        let yaml = r#"
+            vars:
+              image: myrepo/app
+            jobs:
+              build:
+                for_each:
+                  command: ls ${{ vars.image }}
+                  as: dir
+                steps:
+                  - run: docker build -t ${{ vars.image }}:latest .
+                    ready: docker image inspect ${{vars.image}}
+                    workdir: ${{ vars.image }}
+                    env:
+                      TOKEN: ${{ secret('token') }}
+                      IMAGE: ${{ vars.image }}
+                  - run: echo ${{ params.target }}
+                  - script: docker push ${{ vars.image }}
+                    interpreter: sh
+        "#;
+        let mut runfile: Runfile = serde_yaml::from_str(yaml).unwrap();
+
+        runfile.substitute_vars().unwrap();
+
+        let build = &runfile.jobs["build"];
+        assert_eq!(build.for_each.as_ref().unwrap().command, "ls myrepo/app");
+        assert_eq!(
+            build.steps[0].command,
+            "docker build -t myrepo/app:latest ."
+        );
+        assert_eq!(
+            build.steps[0].ready.as_deref(),
+            Some("docker image inspect myrepo/app")
+        );
+        assert_eq!(build.steps[0].workdir, Some(PathBuf::from("myrepo/app")));
+        assert_eq!(build.steps[0].env["TOKEN"], "${{ secret('token') }}");
+        assert_eq!(build.steps[0].env["IMAGE"], "myrepo/app");
+        assert_eq!(build.steps[1].command, "echo ${{ params.target }}");
+        assert_eq!(
+            build.steps[2].script.as_deref(),
+            Some("docker push myrepo/app")
+        );
+
+        runfile.jobs.get_mut("build").unwrap().steps[1].command = "echo ${{ vars.tag }}".into();
+        assert_eq!(
+            runfile.substitute_vars().unwrap_err().to_string(),
+            "job 'build' step 1: var 'tag' is not defined"
+        );
+    }
+
+    #[test]
+    fn test_deserialize_runfile_defaults() {
+        let yaml = r#"
+            default: build
+            default_shell: bash -eu
+            default_env:
+              CI: "1"
+            default_timeout: 30m
+            jobs: {}
+        "#;
+
+        let runfile: Runfile = serde_yaml::from_str(yaml).unwrap();
+
+        assert_eq!(runfile.default_shell.as_deref(), Some("bash -eu"));
+        assert_eq!(runfile.default_env["CI"], "1");
+        assert_eq!(runfile.default_timeout, Some(Duration::from_secs(1800)));
+    }
+
+    #[test]
+    fn test_step_runs_on() {
+        let step = Step {
+            platforms: vec!["linux".into(), "macos".into()],
+            ..Default::default()
+        };
+
+        assert!(step.runs_on("linux"));
+        assert!(!step.runs_on("windows"));
+        assert!(Step::default().runs_on("windows"));
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = TempDir::new("discover");
+
+        assert!(discover(&dir).is_err());
+
+        std::fs::write(dir.join("Uni.yml"), "").unwrap();
+        assert_eq!(discover(&dir).unwrap(), dir.join("Uni.yml"));
+
+        std::fs::write(dir.join("uni.yaml"), "").unwrap();
+        assert_eq!(discover(&dir).unwrap(), dir.join("uni.yaml"));
+    }
+
+    #[test]
+    fn test_deserialize_unknown_field() {
+        let yaml = r#"
+            default: build
+            jobs:
+              build:
+                steps:
+                  - run: foo
+                    persistant: true
+        "#;
+
+        let err = serde_yaml::from_str::<Runfile>(yaml).unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `persistant`"));
+    }
 }
@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+/// Directory state is kept in unless configured otherwise, relative to the runfile
+pub const DEFAULT_STATE_DIR: &str = ".unirun";
+
+/// Directory everything unirun keeps between runs lives in. Paths of files in it are only built
+/// here, so they all honor `--state-dir` and `UNIRUN_STATE_DIR`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateDir {
+    path: PathBuf,
+}
+
+impl StateDir {
+    /// The directory given by `--state-dir`, or else `UNIRUN_STATE_DIR`, or else `.unirun` in
+    /// `runfile_dir`. Empty values are ignored.
+    pub fn resolve(flag: Option<PathBuf>, env: Option<String>, runfile_dir: &Path) -> StateDir {
+        let path = flag
+            .filter(|path| !path.as_os_str().is_empty())
+            .or_else(|| env.filter(|path| !path.is_empty()).map(PathBuf::from))
+            .unwrap_or_else(|| runfile_dir.join(DEFAULT_STATE_DIR));
+        StateDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Cached run orders, see [`crate::plan::PlanCache`]
+    pub fn plan_cache(&self) -> PathBuf {
+        self.path.join("plan.bin")
+    }
+
+    /// Jobs that succeeded in previous runs, see [`crate::resume::RunState`]
+    pub fn last_run(&self) -> PathBuf {
+        self.path.join("last-run.json")
+    }
+
+    /// Input hashes of jobs that succeeded, see [`crate::inputs::InputHashes`]
+    pub fn input_hashes(&self) -> PathBuf {
+        self.path.join("inputs.json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = Path::new("project");
+
+        assert_eq!(
+            StateDir::resolve(None, None, dir).plan_cache(),
+            PathBuf::from("project/.unirun/plan.bin")
+        );
+        assert_eq!(
+            StateDir::resolve(None, Some("/tmp/state".into()), dir).plan_cache(),
+            PathBuf::from("/tmp/state/plan.bin")
+        );
+        assert_eq!(
+            StateDir::resolve(Some("cli".into()), Some("/tmp/state".into()), dir).plan_cache(),
+            PathBuf::from("cli/plan.bin")
+        );
+        assert_eq!(
+            StateDir::resolve(Some(PathBuf::new()), Some(String::new()), dir),
+            StateDir::resolve(None, None, dir)
+        );
+    }
+}
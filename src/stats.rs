@@ -0,0 +1,171 @@
+use std::error::Error;
+
+use itertools::Itertools;
+use petgraph::{
+    algo::toposort,
+    graph::{DiGraph, NodeIndex},
+    Direction,
+};
+
+use crate::{job::collect_dependencies, runfile::Runfile};
+
+/// How many jobs are listed for the highest fan-in and fan-out
+const TOP_DEGREES: usize = 5;
+
+/// Format structural statistics of the dependency graph of `runfile` for `uni stats`: its size,
+/// its roots (jobs nothing needs) and leaves (jobs that need nothing), the critical path and the
+/// jobs most needed and needing the most.
+pub fn format_stats(runfile: &Runfile) -> Result<Vec<String>, Box<dyn Error>> {
+    let deps = collect_dependencies(runfile)?;
+    let graph = deps.inner();
+
+    let count = |direction| {
+        graph
+            .node_indices()
+            .filter(|&n| graph.neighbors_directed(n, direction).next().is_none())
+            .count()
+    };
+    let path = critical_path(graph);
+    let path = match path.is_empty() {
+        true => String::from("Critical path: 0 jobs"),
+        false => format!(
+            "Critical path: {} jobs ({})",
+            path.len(),
+            path.iter().map(|&n| &graph[n]).join(" -> ")
+        ),
+    };
+
+    let mut lines = vec![
+        format!("Jobs: {}", graph.node_count()),
+        format!("Edges: {}", graph.edge_count()),
+        format!("Roots: {}", count(Direction::Incoming)),
+        format!("Leaves: {}", count(Direction::Outgoing)),
+        path,
+    ];
+    for (label, direction) in [
+        ("Highest fan-in", Direction::Incoming),
+        ("Highest fan-out", Direction::Outgoing),
+    ] {
+        let top = top_degrees(graph, direction);
+        if !top.is_empty() {
+            let jobs = top.iter().map(|(id, degree)| format!("{id} ({degree})"));
+            lines.push(format!("{label}: {}", jobs.format(", ")));
+        }
+    }
+
+    Ok(lines)
+}
+
+/// The longest chain of jobs each needing the one before it, in the order they run. Ties between
+/// chains equally long are broken by job id, so the same chain is picked every time.
+fn critical_path(graph: &DiGraph<String, ()>) -> Vec<NodeIndex> {
+    // Edges point from a job to its needs, so needs come after the jobs needing them
+    let order = toposort(graph, None).expect("dependency graph is acyclic");
+
+    // Length of the longest chain ending at each job, and the need it continues from
+    let mut longest = vec![(1, None); graph.node_count()];
+    for &node in order.iter().rev() {
+        let best = graph
+            .neighbors(node)
+            .max_by(|&a, &b| {
+                longest[a.index()]
+                    .0
+                    .cmp(&longest[b.index()].0)
+                    .then_with(|| graph[b].cmp(&graph[a]))
+            })
+            .map(|need| (longest[need.index()].0 + 1, Some(need)));
+        if let Some(best) = best {
+            longest[node.index()] = best;
+        }
+    }
+
+    let Some(mut node) = graph.node_indices().max_by(|&a, &b| {
+        longest[a.index()]
+            .0
+            .cmp(&longest[b.index()].0)
+            .then_with(|| graph[b].cmp(&graph[a]))
+    }) else {
+        return Vec::new();
+    };
+    let mut path = vec![node];
+    while let Some(need) = longest[node.index()].1 {
+        path.push(need);
+        node = need;
+    }
+    path.reverse();
+    path
+}
+
+/// Jobs with more than one edge in `direction`, the most first
+fn top_degrees(graph: &DiGraph<String, ()>, direction: Direction) -> Vec<(&str, usize)> {
+    graph
+        .node_indices()
+        .map(|n| {
+            (
+                graph[n].as_str(),
+                graph.neighbors_directed(n, direction).count(),
+            )
+        })
+        .filter(|&(_, degree)| degree > 1)
+        .sorted_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)))
+        .take(TOP_DEGREES)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::runfile::Job;
+
+    use super::*;
+
+    #[test]
+    fn test_format_stats() {
+        let job = |needs: &[&str]| Job {
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("deploy"),
+            jobs: HashMap::from([
+                ("build".into(), job(&[])),
+                ("lint".into(), job(&[])),
+                ("test".into(), job(&["build"])),
+                ("package".into(), job(&["build", "test"])),
+                ("deploy".into(), job(&["package", "lint"])),
+                ("docs".into(), job(&["build"])),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            format_stats(&runfile).unwrap(),
+            vec![
+                "Jobs: 6",
+                "Edges: 6",
+                "Roots: 2",
+                "Leaves: 2",
+                "Critical path: 4 jobs (build -> test -> package -> deploy)",
+                "Highest fan-in: build (3)",
+                "Highest fan-out: deploy (2), package (2)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_stats_empty() {
+        let runfile = Runfile::default();
+
+        assert_eq!(
+            format_stats(&runfile).unwrap(),
+            vec![
+                "Jobs: 0",
+                "Edges: 0",
+                "Roots: 0",
+                "Leaves: 0",
+                "Critical path: 0 jobs",
+            ]
+        );
+    }
+}
@@ -1,26 +1,956 @@
-use std::process::{Child, Command};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
 
+use regex::{Regex, RegexBuilder};
 use shlex::Shlex;
+use tracing::{debug, debug_span};
 
-use crate::runfile::Step;
+use crate::{
+    env::Env,
+    output::{LimitedOutput, CAPTURE_LIMIT},
+    report::{exit_code, Termination},
+    runfile::{JobId, Step, TimeoutAction},
+};
+
+pub enum Process {
+    /// A persistent step that is still running in the background, along with the script file it
+    /// runs, which is removed once it's dropped
+    Persistent {
+        proc: Child,
+        script: Option<ScriptFile>,
+    },
+    /// A step that ran to completion
+    Finished(ExitStatus),
+    /// A step that ran to completion but failed one of the checks configured on it
+    Rejected { status: ExitStatus, reason: String },
+}
+
+/// How often a running step is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a timed-out step has to exit after being asked to before it is killed
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// How long persistent steps without a `shutdown_timeout` may take to exit during shutdown
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Everything about the surrounding run that a step needs to know to execute
+#[derive(Clone, Debug, Default)]
+pub struct StepContext {
+    /// Complete environment of the child process
+    pub env: Env,
+    /// Human-readable `<job>/<step>` identifier used in diagnostics
+    pub label: String,
+    /// Interval at which a reminder is printed while a non-persistent step is still running
+    pub heartbeat: Option<Duration>,
+    /// Set when the run is cancelled, at which point a running non-persistent step is terminated
+    pub cancel: Arc<AtomicBool>,
+    /// When the step's job times out. A non-persistent step still running by then is terminated.
+    pub deadline: Option<Instant>,
+    /// Time after which a non-persistent step is suspected of never exiting
+    pub soft_timeout: Option<Duration>,
+    /// Terminate and fail a step once it exceeds the soft timeout, rather than just warning
+    pub fail_on_soft_timeout: bool,
+    /// How the step is stopped once it times out
+    pub timeout_action: TimeoutAction,
+    /// How long after the run is cancelled the step may keep running, if it ignores signals
+    pub signal_grace: Option<Duration>,
+    /// When the run was cancelled, shared between steps so they use up a single grace period
+    pub cancelled_at: Arc<OnceLock<Instant>>,
+    /// Directory the step runs in, instead of the process working directory
+    pub workdir: Option<PathBuf>,
+    /// Where the stdout of a non-persistent step is kept in addition to being printed, for the
+    /// next step's `$PREV_STDOUT`
+    pub capture_stdout: Option<Arc<Mutex<Vec<u8>>>>,
+    /// Set when the user asks to skip the running step, which terminates and fails a
+    /// non-persistent step. It's cleared whenever a step starts. Keys pressed while it's given
+    /// are meant for `uni`, so the step doesn't read from the terminal.
+    pub skip: Option<Arc<AtomicBool>>,
+    /// Where the stdout and stderr of a non-persistent step go instead of being printed, e.g. to
+    /// only show them if it fails
+    pub buffer_output: Option<Arc<Mutex<Vec<u8>>>>,
+    /// Print each command before running it, like `set -x`
+    pub print_command: bool,
+    /// Values of secrets in the step's env, masked in printed commands
+    pub secrets: Vec<String>,
+}
 
 pub trait Run {
-    fn run(&self) -> std::io::Result<Option<Child>>;
+    fn run(&self, ctx: &StepContext) -> Result<Process, Box<dyn Error>>;
 }
 
 impl Run for Step {
-    fn run(&self) -> std::io::Result<Option<Child>> {
-        let cmd_args = CmdArgs::from(self.command.as_str());
+    fn run(&self, ctx: &StepContext) -> Result<Process, Box<dyn Error>> {
+        let workdir = match &ctx.workdir {
+            Some(workdir) if !workdir.is_dir() => {
+                return Err(
+                    format!("working directory '{}' does not exist", workdir.display()).into(),
+                );
+            }
+            Some(workdir) => workdir.clone(),
+            None => std::env::current_dir()?,
+        };
+
+        #[cfg(target_family = "unix")]
+        let (uid, gid) = {
+            let user = self.user.as_deref().map(resolve_user).transpose()?;
+            let group = match &self.group {
+                Some(group) => Some(resolve_group(group)?),
+                None => user.and_then(|(_, primary_gid)| primary_gid),
+            };
+            (user.map(|(uid, _)| uid), group)
+        };
+        #[cfg(not(target_family = "unix"))]
+        if self.user.is_some() || self.group.is_some() {
+            return Err("the `user` and `group` step options are only supported on Unix".into());
+        }
+        #[cfg(not(target_family = "unix"))]
+        let (uid, gid) = (None, None);
+
+        // Commands in the step's command run where and as whom the step does
+        let subshell = Subshell {
+            workdir: ctx.workdir.as_ref().map(|_| workdir.clone()),
+            uid,
+            gid,
+        };
+        let substitution = match self.raw {
+            true => Substitution::None,
+            false => Substitution::All(&subshell),
+        };
+        let (cmd_args, script) = match &self.script {
+            Some(script) => {
+                if !self.command.trim().is_empty() {
+                    return Err("`run` and `script` can't both be set".into());
+                }
+                let interpreter = self
+                    .interpreter
+                    .as_deref()
+                    .ok_or("a `script` step needs an `interpreter`")?;
+                let mut cmd_args = CmdArgs::new(interpreter, &ctx.env, substitution)?;
+                let file = ScriptFile::create(script)?;
+                cmd_args.args.push(file.path.to_string_lossy().into_owned());
+                (cmd_args, Some(file))
+            }
+            None => match &self.shell {
+                Some(shell) => {
+                    let mut cmd_args = CmdArgs::new(shell, &ctx.env, substitution)?;
+                    if self.command.trim().is_empty() {
+                        return Err(CommandParseError::Empty.into());
+                    }
+                    cmd_args.args.push(shell_flag(&cmd_args.cmd).to_owned());
+                    cmd_args.args.push(self.command.clone());
+                    (cmd_args, None)
+                }
+                None => (
+                    CmdArgs::new(self.command.as_str(), &ctx.env, substitution)?,
+                    None,
+                ),
+            },
+        };
+
+        if ctx.print_command {
+            let words = std::iter::once(cmd_args.cmd.as_str())
+                .chain(cmd_args.args.iter().map(String::as_str))
+                .collect::<Vec<_>>();
+            // Script files are temporary, so their path says nothing
+            let (words, suffix) = match script {
+                Some(_) => (&words[..words.len() - 1], " <script>"),
+                None => (&words[..], ""),
+            };
+            let shown = shlex::try_join(words.iter().copied()).unwrap_or_else(|_| words.join(" "));
+            print_command(ctx, &format!("{shown}{suffix}"));
+        }
+
+        let mut command = Command::new(resolve_program(&cmd_args.cmd, &workdir));
+        command.args(cmd_args.args).env_clear().envs(&ctx.env);
+        subshell.apply(&mut command);
+        let expected_match = self
+            .expect_stdout_matches
+            .as_deref()
+            .map(|re| RegexBuilder::new(re).multi_line(true).build())
+            .transpose()
+            .map_err(|e| format!("invalid `expect_stdout_matches`: {e}"))?;
+        // Stdout expectations need the output even if the next step doesn't
+        let capture = ctx
+            .capture_stdout
+            .clone()
+            .or_else(|| self.expects_stdout().then(Default::default))
+            .filter(|_| !self.is_persistent());
+        let buffer = ctx.buffer_output.clone().filter(|_| !self.is_persistent());
+        if ctx.skip.is_some() {
+            command.stdin(Stdio::null());
+        }
+        if self.silent {
+            command.stdout(Stdio::null()).stderr(Stdio::null());
+        } else if self.max_output_bytes.is_some() || buffer.is_some() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        } else if capture.is_some() {
+            command.stdout(Stdio::piped());
+        }
+        let (stdout_file, stderr_file) = self.output_files(&workdir)?;
+        // Stdout still passes through when it's captured, to go to both
+        let stdout_file = match stdout_file {
+            Some(file) if capture.is_some() => {
+                command.stdout(Stdio::piped());
+                Some(file)
+            }
+            Some(file) => {
+                command.stdout(file);
+                None
+            }
+            None => None,
+        };
+        if let Some(file) = stderr_file {
+            command.stderr(file);
+        }
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::CommandExt;
 
-        let mut proc = Command::new(cmd_args.cmd).args(cmd_args.args).spawn()?;
+            // Keep Ctrl-C in the terminal from reaching the step along with the rest of the
+            // foreground process group
+            if self.ignore_signals {
+                command.process_group(0);
+            }
+
+            if let Some(priority) = self.priority {
+                // SAFETY: setpriority is async-signal-safe and only changes the child process
+                unsafe {
+                    command.pre_exec(move || {
+                        if libc::setpriority(libc::PRIO_PROCESS, 0, priority) == -1 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    });
+                }
+            }
+        }
+        #[cfg(not(target_family = "unix"))]
+        if self.priority.is_some() {
+            tracing::warn!(
+                "{}: the `priority` step option is only supported on Unix",
+                ctx.label
+            );
+        }
+
+        // Keys pressed before the step started are meant for an earlier step
+        if let Some(skip) = &ctx.skip {
+            skip.store(false, Ordering::Relaxed);
+        }
+        let started = Instant::now();
+        let span = debug_span!("spawn", step = %ctx.label).entered();
+        let mut proc = command.spawn().map_err(|e| -> Box<dyn Error> {
+            match e.kind() {
+                std::io::ErrorKind::NotFound => Box::new(CommandNotFoundError {
+                    program: cmd_args.cmd.clone(),
+                    label: ctx.label.clone(),
+                }),
+                _ => e.into(),
+            }
+        })?;
+        debug!("started `{}` as pid {}", cmd_args.cmd, proc.id());
+        drop(span);
+        let output = (proc.stdout.is_some() || proc.stderr.is_some()).then(|| {
+            let limit = self.max_output_bytes.unwrap_or(u64::MAX);
+            // Expectations are checked against all of stdout
+            let capture_limit = match self.expects_stdout() {
+                true => usize::MAX,
+                false => CAPTURE_LIMIT,
+            };
+            LimitedOutput::attach(
+                &mut proc,
+                limit,
+                capture.clone(),
+                capture_limit,
+                buffer,
+                stdout_file,
+            )
+        });
 
         std::thread::sleep(self.startup_delay);
 
-        if self.persistent {
-            Ok(Some(proc))
+        if self.is_persistent() {
+            return Ok(Process::Persistent { proc, script });
+        }
+
+        let _span = debug_span!("wait", step = %ctx.label).entered();
+        let (status, stopped) = wait(&mut proc, ctx, started)?;
+        let elapsed = started.elapsed();
+        debug!("{} after {elapsed:?}", Termination::from_status(status));
+        let truncated = output.is_some_and(|o| o.finish());
+        let stdout = capture.filter(|_| self.expects_stdout()).and_then(|c| {
+            c.lock()
+                .ok()
+                .map(|c| String::from_utf8_lossy(&c).into_owned())
+        });
+
+        if let Some(reason) = stopped {
+            Ok(Process::Rejected { status, reason })
+        } else if truncated && self.fail_on_output_limit {
+            Ok(Process::Rejected {
+                status,
+                reason: format!(
+                    "output exceeded {} bytes",
+                    self.max_output_bytes.unwrap_or_default()
+                ),
+            })
+        } else if let Some(reason) = self.unmet_expectation(status, stdout, expected_match) {
+            Ok(Process::Rejected { status, reason })
+        } else if let Some(max) = self
+            .max_duration
+            .filter(|&max| elapsed > max && self.exit_ok(status))
+        {
+            Ok(Process::Rejected {
+                status,
+                reason: format!(
+                    "exceeded max_duration ({:?} > {max:?})",
+                    Duration::from_millis(elapsed.as_millis() as u64)
+                ),
+            })
         } else {
-            proc.wait()?;
-            Ok(None)
+            Ok(Process::Finished(status))
+        }
+    }
+}
+
+impl Step {
+    /// Open the step's `stdout_file` and `stderr_file` relative to `workdir`, creating their
+    /// directories. The same path for both is opened once, so the streams don't overwrite each
+    /// other.
+    fn output_files(&self, workdir: &Path) -> Result<(Option<File>, Option<File>), Box<dyn Error>> {
+        let open = |path: &Path| -> Result<File, Box<dyn Error>> {
+            let path = workdir.join(path);
+            let error = |e: std::io::Error| format!("Unable to open '{}': {e}", path.display());
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(error)?;
+            }
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .append(self.append)
+                .truncate(!self.append)
+                .open(&path)
+                .map_err(error)?;
+            Ok(file)
+        };
+
+        let stdout = self.stdout_file.as_deref().map(open).transpose()?;
+        let stderr = match (&stdout, &self.stderr_file) {
+            (Some(stdout), Some(path)) if self.stdout_file.as_ref() == Some(path) => {
+                Some(stdout.try_clone()?)
+            }
+            (_, Some(path)) => Some(open(path)?),
+            (_, None) => None,
+        };
+        Ok((stdout, stderr))
+    }
+
+    /// The program the step starts: its interpreter, its shell or the first word of its command,
+    /// with variables substituted but without running `$(...)` commands
+    pub fn program(&self, env: &Env) -> Result<String, Box<dyn Error>> {
+        let substitution = match self.raw {
+            true => Substitution::None,
+            false => Substitution::Variables,
+        };
+        let program = match (&self.script, &self.interpreter, &self.shell) {
+            (Some(_), Some(interpreter), _) => interpreter,
+            (Some(_), None, _) => return Err("a `script` step needs an `interpreter`".into()),
+            (None, _, Some(shell)) => shell,
+            (None, _, None) => &self.command,
+        };
+        Ok(CmdArgs::new(program, env, substitution)?.cmd)
+    }
+
+    /// Why the output of a step that exited with `status` doesn't meet its expectations, if it
+    /// doesn't. Exit codes are checked by the caller, unless the step expects a specific one.
+    fn unmet_expectation(
+        &self,
+        status: ExitStatus,
+        stdout: Option<String>,
+        expected_match: Option<Regex>,
+    ) -> Option<String> {
+        if let Some(code) = self.expect_exit.filter(|_| !self.exit_ok(status)) {
+            return Some(format!(
+                "{}, expected exit code {code}",
+                Termination::from_status(status)
+            ));
+        }
+        if !self.exit_ok(status) {
+            return None;
+        }
+
+        let stdout = stdout.unwrap_or_default();
+        let unmet = match (&self.expect_stdout_contains, expected_match) {
+            (Some(text), _) if !stdout.contains(text.as_str()) => {
+                format!("expected stdout to contain {text:?}")
+            }
+            (_, Some(regex)) if !regex.is_match(&stdout) => {
+                format!("expected stdout to match /{regex}/")
+            }
+            _ => return None,
+        };
+        Some(format!("{unmet}, got {}", snippet(&stdout)))
+    }
+}
+
+/// Print `command` along with the step's label, with the values of secrets masked. Commands of
+/// steps whose output is kept go along with it, so they show up with the output of failed jobs.
+fn print_command(ctx: &StepContext, command: &str) {
+    let command = ctx
+        .secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(command.to_owned(), |command, secret| {
+            command.replace(secret.as_str(), "***")
+        });
+    let line = format!("+ {}: {command}", ctx.label);
+
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    match &ctx.buffer_output {
+        Some(buffer) => {
+            if let Ok(mut buffer) = buffer.lock() {
+                buffer.extend_from_slice(line.as_bytes());
+                buffer.push(b'\n');
+            }
+        }
+        None if std::io::stderr().is_terminal() && !no_color => {
+            eprintln!("\x1b[2m{line}\x1b[0m")
+        }
+        None => eprintln!("{line}"),
+    }
+}
+
+/// How much of a step's output is shown when it doesn't meet an expectation
+const SNIPPET_LEN: usize = 200;
+
+/// The start of `output`, quoted, or a note that there was none
+fn snippet(output: &str) -> String {
+    let output = output.trim();
+    if output.is_empty() {
+        return String::from("no output");
+    }
+    match output.char_indices().nth(SNIPPET_LEN) {
+        Some((end, _)) => format!("{:?}...", &output[..end]),
+        None => format!("{output:?}"),
+    }
+}
+
+/// Wait for a non-persistent step to exit, printing a heartbeat while it keeps running. The step
+/// is terminated if the run is cancelled (once its signal grace period is over, if it has one),
+/// its job times out, or it exceeds a failing soft timeout. The reason is returned along with the
+/// exit status unless it was terminated right away on cancellation.
+fn wait(
+    proc: &mut Child,
+    ctx: &StepContext,
+    started: Instant,
+) -> std::io::Result<(ExitStatus, Option<String>)> {
+    let mut next_heartbeat = ctx.heartbeat;
+    let mut hinted = false;
+    let mut grace_ends = None;
+
+    loop {
+        if let Some(status) = proc.try_wait()? {
+            return Ok((status, None));
+        }
+        if ctx.cancel.load(Ordering::Relaxed) {
+            let Some(grace) = ctx.signal_grace else {
+                terminate(proc);
+                return Ok((proc.wait()?, None));
+            };
+            let ends = *grace_ends.get_or_insert_with(|| {
+                eprintln!("{}: run cancelled, letting the step finish", ctx.label);
+                *ctx.cancelled_at.get_or_init(Instant::now) + grace
+            });
+            if Instant::now() >= ends {
+                let reason = format!("still running {grace:?} after the run was cancelled");
+                return Ok((stop(proc, ctx.timeout_action)?, Some(reason)));
+            }
+        }
+        if ctx
+            .skip
+            .as_ref()
+            .is_some_and(|skip| skip.swap(false, Ordering::Relaxed))
+        {
+            let status = stop(proc, TimeoutAction::Terminate)?;
+            return Ok((status, Some(String::from("skipped from the keyboard"))));
+        }
+        if ctx
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            let status = stop(proc, ctx.timeout_action)?;
+            return Ok((status, Some(String::from("job timed out"))));
+        }
+
+        let elapsed = started.elapsed();
+        if let Some(soft_timeout) = ctx.soft_timeout.filter(|&t| !hinted && elapsed >= t) {
+            eprintln!(
+                "{}: step still running after {soft_timeout:?}, did you mean `persistent: true`?",
+                ctx.label
+            );
+            hinted = true;
+
+            if ctx.fail_on_soft_timeout {
+                let reason = format!("still running after {soft_timeout:?}");
+                return Ok((stop(proc, ctx.timeout_action)?, Some(reason)));
+            }
+        }
+
+        if let Some(heartbeat) = next_heartbeat.filter(|&h| elapsed >= h) {
+            eprintln!(
+                "still running: {} ({}s elapsed)",
+                ctx.label,
+                elapsed.as_secs()
+            );
+            next_heartbeat = ctx.heartbeat.map(|interval| heartbeat + interval);
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Resolve a program given as a `./` or `../` relative path against the step's working directory,
+/// rather than leaving it to the platform. Absolute paths and bare names looked up in `PATH` are
+/// returned unchanged.
+fn resolve_program(program: &str, workdir: &Path) -> PathBuf {
+    let is_relative = ["./", "../"]
+        .iter()
+        .any(|prefix| program.starts_with(prefix))
+        || (cfg!(target_family = "windows")
+            && [".\\", "..\\"]
+                .iter()
+                .any(|prefix| program.starts_with(prefix)));
+
+    if is_relative {
+        workdir.join(program)
+    } else {
+        PathBuf::from(program)
+    }
+}
+
+/// Find the executable `program` would start in `workdir`: a path relative to `workdir`, or a
+/// bare name looked up in the `PATH` of `env`
+pub fn find_program(program: &str, workdir: &Path, env: &Env) -> Option<PathBuf> {
+    let is_executable = |path: &Path| {
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            path.metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        }
+        #[cfg(not(target_family = "unix"))]
+        path.is_file()
+    };
+    // Windows finds `cargo` as `cargo.exe`
+    let candidates = |path: PathBuf| {
+        let extensions: &[&str] = match cfg!(target_family = "windows") {
+            true if path.extension().is_none() => &["exe", "cmd", "bat", "com"],
+            _ => &[],
+        };
+        std::iter::once(path.clone()).chain(extensions.iter().map(move |e| path.with_extension(e)))
+    };
+
+    let separators: &[char] = match cfg!(target_family = "windows") {
+        true => &['/', '\\'],
+        false => &['/'],
+    };
+    if program.contains(separators) {
+        return candidates(workdir.join(program)).find(|p| is_executable(p));
+    }
+    let path = env.get("PATH")?;
+    std::env::split_paths(path)
+        .flat_map(|dir| candidates(workdir.join(dir).join(program)))
+        .find(|p| is_executable(p))
+}
+
+/// Resolve a user name or numeric id to its uid and, if known, its primary gid
+#[cfg(target_family = "unix")]
+fn resolve_user(user: &str) -> std::io::Result<(u32, Option<u32>)> {
+    use nix::unistd::{Uid, User};
+
+    let numeric_uid = user.parse::<u32>().ok();
+    let entry = match numeric_uid {
+        Some(uid) => User::from_uid(Uid::from_raw(uid))?,
+        None => User::from_name(user)?,
+    };
+
+    match (entry, numeric_uid) {
+        (Some(entry), _) => Ok((entry.uid.as_raw(), Some(entry.gid.as_raw()))),
+        (None, Some(uid)) => Ok((uid, None)),
+        (None, None) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("user '{user}' does not exist"),
+        )),
+    }
+}
+
+/// Resolve a group name or numeric id to its gid
+#[cfg(target_family = "unix")]
+fn resolve_group(group: &str) -> std::io::Result<u32> {
+    use nix::unistd::Group;
+
+    if let Ok(gid) = group.parse::<u32>() {
+        return Ok(gid);
+    }
+
+    match Group::from_name(group)? {
+        Some(entry) => Ok(entry.gid.as_raw()),
+        None => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("group '{group}' does not exist"),
+        )),
+    }
+}
+
+/// Flag that makes `shell` run the command given as its next argument
+fn shell_flag(shell: &str) -> &'static str {
+    let name = Path::new(shell)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    match name.eq_ignore_ascii_case("cmd") {
+        true => "/C",
+        false => "-c",
+    }
+}
+
+/// Temporary file holding the body of a `script` step, removed when dropped
+#[derive(Debug)]
+pub struct ScriptFile {
+    path: PathBuf,
+}
+
+impl ScriptFile {
+    fn create(script: &str) -> std::io::Result<ScriptFile> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        // Restarted persistent steps get a new file, as the previous one is removed when the
+        // previous process is replaced
+        let path = std::env::temp_dir().join(format!(
+            "uni-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        file.write_all(script.as_bytes())?;
+
+        Ok(ScriptFile { path })
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Ask a running process to stop, gracefully where the platform allows it
+pub fn terminate(proc: &mut Child) {
+    #[cfg(target_family = "unix")]
+    {
+        use nix::sys::signal::{self, Signal};
+        use nix::unistd::Pid;
+        // We don't really care if we failed to kill a process, and exiting on error would
+        // prevent subsequent processes from being stopped.
+        debug!("sending SIGTERM to pid {}", proc.id());
+        _ = signal::kill(Pid::from_raw(proc.id() as i32), Signal::SIGTERM);
+    };
+    #[cfg(target_family = "windows")]
+    {
+        _ = proc.kill();
+    }
+}
+
+/// The signal named `name`, with or without its `SIG` prefix, e.g. `SIGHUP` or `HUP`
+#[cfg(target_family = "unix")]
+pub fn signal_from_name(name: &str) -> Option<nix::sys::signal::Signal> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = match name.starts_with("SIG") {
+        true => name,
+        false => format!("SIG{name}"),
+    };
+    name.parse().ok()
+}
+
+/// Send the signal named `name` to a process, see [`signal_from_name`]
+#[cfg(target_family = "unix")]
+pub fn send_signal(proc: &Child, name: &str) -> Result<(), String> {
+    use nix::{sys::signal, unistd::Pid};
+
+    let signal = signal_from_name(name).ok_or_else(|| format!("unknown signal '{name}'"))?;
+    debug!("sending {signal} to pid {}", proc.id());
+    signal::kill(Pid::from_raw(proc.id() as i32), signal).map_err(|e| e.to_string())
+}
+
+/// Stop a timed-out process as `action` says and wait for it to exit
+pub fn stop(proc: &mut Child, action: TimeoutAction) -> std::io::Result<ExitStatus> {
+    match action {
+        TimeoutAction::Kill => _ = proc.kill(),
+        TimeoutAction::Terminate => return terminate_within(proc, TIMEOUT_GRACE),
+    }
+    proc.wait()
+}
+
+/// Terminate a process and wait for it to exit, killing it if it's still running after `grace`
+pub fn terminate_within(proc: &mut Child, grace: Duration) -> std::io::Result<ExitStatus> {
+    terminate(proc);
+    let grace_ends = Instant::now() + grace;
+    while proc.try_wait()?.is_none() {
+        if Instant::now() >= grace_ends {
+            _ = proc.kill();
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    proc.wait()
+}
+
+/// A step whose program doesn't exist
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandNotFoundError {
+    pub program: String,
+    /// `<job>/<step>` identifier of the step
+    pub label: String,
+}
+
+impl Error for CommandNotFoundError {}
+
+impl Display for CommandNotFoundError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command not found: `{}` (in step {}) - ",
+            self.program, self.label
+        )?;
+        // A program given as a path isn't looked up in PATH
+        match self.program.contains(['/', '\\']) {
+            true => write!(f, "does the file exist?"),
+            false => write!(f, "is it installed and on PATH?"),
+        }
+    }
+}
+
+/// A step that exited unsuccessfully
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StepError {
+    pub job_id: JobId,
+    /// Index of the step in its job
+    pub step: usize,
+    pub name: Option<String>,
+    /// Command after environment variable substitution
+    pub command: String,
+    /// Shell-style exit code, see [`exit_code`]
+    pub exit_code: i32,
+    /// Signal the step was killed by, if any
+    pub signal: Option<i32>,
+    /// The step's `on_failure_message`
+    pub message: Option<String>,
+}
+
+impl StepError {
+    pub fn new(
+        job_id: &str,
+        index: usize,
+        step: &Step,
+        env: &Env,
+        status: ExitStatus,
+    ) -> StepError {
+        #[cfg(target_family = "unix")]
+        let signal = {
+            use std::os::unix::process::ExitStatusExt;
+            status.signal()
+        };
+        #[cfg(not(target_family = "unix"))]
+        let signal = None;
+
+        // Command substitutions are shown as written rather than run a second time
+        let substitution = match step.raw {
+            true => Substitution::None,
+            false => Substitution::Variables,
+        };
+        let display = |value: &str| {
+            CmdArgs::new(value, env, substitution)
+                .ok()
+                .and_then(|a| {
+                    shlex::try_join(
+                        std::iter::once(a.cmd.as_str()).chain(a.args.iter().map(String::as_str)),
+                    )
+                    .ok()
+                })
+                .unwrap_or_else(|| value.trim().to_owned())
+        };
+        let command = match (&step.script, &step.interpreter) {
+            (Some(_), Some(interpreter)) => format!("{} <script>", display(interpreter)),
+            // The shell sees the command as written
+            _ if step.shell.is_some() => step.command.trim().to_owned(),
+            _ => display(&step.command),
+        };
+
+        StepError {
+            job_id: job_id.to_owned(),
+            step: index,
+            name: step.name.clone(),
+            command,
+            exit_code: exit_code(status),
+            signal,
+            message: step.on_failure_message.clone(),
+        }
+    }
+}
+
+impl Error for StepError {}
+
+impl Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "job '{}' step {}", self.job_id, self.step)?;
+        if let Some(name) = &self.name {
+            write!(f, " '{name}'")?;
+        }
+        write!(f, " ('{}')", self.command)?;
+
+        match self.signal {
+            Some(signal) => write!(f, " was killed by signal {signal}"),
+            // Exited successfully, but failed one of its checks
+            None if self.exit_code == 0 => write!(f, " failed"),
+            None => write!(f, " failed with exit code {}", self.exit_code),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandParseError {
+    Empty,
+    UnterminatedQuote,
+    /// A `${NAME:?message}` reference to a variable that is unset or empty
+    UnsetVariable {
+        name: String,
+        message: String,
+    },
+    UnterminatedSubstitution,
+    /// A `$(...)` command substitution that couldn't be evaluated
+    SubstitutionFailed {
+        command: String,
+        message: String,
+    },
+}
+
+impl Error for CommandParseError {}
+
+impl Display for CommandParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandParseError::Empty => write!(f, "step command is empty after parsing"),
+            CommandParseError::UnterminatedQuote => {
+                write!(f, "step command has an unterminated quote or escape")
+            }
+            CommandParseError::UnsetVariable { name, message } if message.is_empty() => {
+                write!(f, "variable '{name}' is not set")
+            }
+            CommandParseError::UnsetVariable { name, message } => write!(f, "{name}: {message}"),
+            CommandParseError::UnterminatedSubstitution => {
+                write!(
+                    f,
+                    "step command has an unterminated `$(` command substitution"
+                )
+            }
+            CommandParseError::SubstitutionFailed { command, message } => {
+                write!(f, "command substitution `$({command})` {message}")
+            }
+        }
+    }
+}
+
+/// Run a command to completion and capture its stdout. Stderr is passed through.
+pub fn capture_stdout(command: &str, env: &Env) -> Result<(ExitStatus, String), Box<dyn Error>> {
+    let cmd_args = CmdArgs::new(command, env, Substitution::Variables)?;
+
+    let output = Command::new(cmd_args.cmd)
+        .args(cmd_args.args)
+        .env_clear()
+        .envs(env)
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    Ok((
+        output.status,
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+    ))
+}
+
+/// Run a command to completion with its output discarded, returning whether it succeeded
+pub fn probe(command: &str, env: &Env) -> bool {
+    let Ok(cmd_args) = CmdArgs::new(command, env, Substitution::Variables) else {
+        return false;
+    };
+
+    Command::new(cmd_args.cmd)
+        .args(cmd_args.args)
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Check that a step command can be parsed, without substituting environment variables
+pub fn validate_command(command: &str) -> Result<(), CommandParseError> {
+    CmdArgs::parse(command).map(|_| ())
+}
+
+/// How much of a command is substituted before it is split into arguments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Substitution<'a> {
+    None,
+    /// Environment variables only
+    Variables,
+    /// Environment variables and `$(...)` commands, run by `Subshell`
+    All(&'a Subshell),
+}
+
+/// Where and as whom a process runs, the step's own process or a `$(...)` command in it
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Subshell {
+    /// Left unset for the process working directory, since changing into it could fail once
+    /// running as another user
+    workdir: Option<PathBuf>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Subshell {
+    fn apply(&self, command: &mut Command) {
+        if let Some(workdir) = &self.workdir {
+            command.current_dir(workdir);
+        }
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::process::CommandExt;
+
+            // The child sets its group before its user, since it can no longer change groups
+            // once it has dropped to an unprivileged user
+            if let Some(gid) = self.gid {
+                command.gid(gid);
+            }
+            if let Some(uid) = self.uid {
+                command.uid(uid);
+            }
         }
     }
 }
@@ -32,25 +962,270 @@ struct CmdArgs {
     args: Vec<String>,
 }
 
-impl From<&str> for CmdArgs {
-    fn from(value: &str) -> CmdArgs {
-        let mut value = value.to_string();
-        for (key, val) in std::env::vars() {
-            value = value.replace(format!("${key}").as_str(), val.as_str());
+impl CmdArgs {
+    fn new(
+        value: &str,
+        env: &Env,
+        substitution: Substitution,
+    ) -> Result<CmdArgs, CommandParseError> {
+        match substitution {
+            Substitution::None => CmdArgs::parse(value),
+            Substitution::Variables => CmdArgs::parse(&substitute(value, env, None)?),
+            Substitution::All(subshell) => CmdArgs::parse(&substitute(value, env, Some(subshell))?),
         }
-        value = value.replace(" \\\n", " ");
+    }
+
+    fn parse(value: &str) -> Result<CmdArgs, CommandParseError> {
+        // Files saved on Windows may end lines with CRLF
+        let value = value.replace("\r\n", "\n").replace(" \\\n", " ");
 
         let mut shlex = Shlex::new(value.as_str());
+        let mut tokens = shlex.by_ref().collect::<Vec<_>>().into_iter();
+        if shlex.had_error {
+            return Err(CommandParseError::UnterminatedQuote);
+        }
+
+        let cmd = tokens.next().ok_or(CommandParseError::Empty)?;
+        let args = tokens.collect();
+
+        Ok(CmdArgs { cmd, args })
+    }
+}
+
+/// Substitute environment variables in a command. Unlike in a POSIX shell, variables are also
+/// substituted within single quotes, though commands aren't:
+/// - `$NAME` and `${NAME}` are replaced by the variable's value
+/// - `${NAME:-default}` falls back to `default` when the variable is unset or empty
+/// - `${NAME:?message}` fails with `message` when the variable is unset or empty
+///
+/// - `$(command)` is replaced by the trimmed stdout of `command` run by the shell in `commands`,
+///   if given and it isn't within single quotes
+///
+/// References to unset variables without a fallback are left as they are, and a `$` escaped
+/// with a backslash outside single quotes is kept literally. Substituted values are escaped for
+/// the quotes they appear in, so each stays within a single argument once the command is split,
+/// spaces and quotes included.
+fn substitute(
+    value: &str,
+    env: &Env,
+    commands: Option<&Subshell>,
+) -> Result<String, CommandParseError> {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let mut result = String::with_capacity(value.len());
+    let mut quoting = Quoting::None;
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        let escaped;
+        (quoting, escaped) = quoting.after(&rest[..start]);
+        result.push_str(&rest[..start]);
+        let reference = &rest[start..];
+        if escaped {
+            result.push('$');
+            rest = &reference[1..];
+            continue;
+        }
+
+        // Running commands nobody expects to run is worse than not substituting variables
+        let commands = commands.filter(|_| quoting != Quoting::Single);
+        if let Some((inner, subshell)) = reference.strip_prefix("$(").zip(commands) {
+            let end = closing_paren(inner).ok_or(CommandParseError::UnterminatedSubstitution)?;
+            let output = substitute_command(&inner[..end], env, subshell, 1)?;
+            result.push_str(&quoting.escape(&output));
+            rest = &inner[end + 1..];
+        } else if let Some(braced) = reference.strip_prefix("${") {
+            let Some(end) = braced.find('}') else {
+                result.push_str(reference);
+                return Ok(result);
+            };
+            let expression = &braced[..end];
+            rest = &braced[end + 1..];
+
+            let (name, operator) = match [":-", ":?"]
+                .iter()
+                .filter_map(|op| expression.find(op))
+                .min()
+            {
+                Some(i) => (
+                    &expression[..i],
+                    Some((&expression[i..i + 2], &expression[i + 2..])),
+                ),
+                None => (expression, None),
+            };
+            let value = env.get(name).filter(|v| !v.is_empty());
+
+            match (value, operator) {
+                (Some(value), Some(_)) => result.push_str(&quoting.escape(value)),
+                (None, Some((":-", default))) => result.push_str(&quoting.escape(default)),
+                (None, Some((_, message))) => {
+                    return Err(CommandParseError::UnsetVariable {
+                        name: name.to_owned(),
+                        message: message.to_owned(),
+                    })
+                }
+                (_, None) => match env.get(name) {
+                    Some(value) => result.push_str(&quoting.escape(value)),
+                    None => result.push_str(&reference[..end + 3]),
+                },
+            }
+        } else {
+            let name_len = reference[1..]
+                .find(|c: char| !is_name_char(c))
+                .unwrap_or(reference.len() - 1);
+            let name = &reference[1..1 + name_len];
+            match env.get(name).filter(|_| !name.is_empty()) {
+                Some(value) => result.push_str(&quoting.escape(value)),
+                None => result.push_str(&reference[..1 + name_len]),
+            }
+            rest = &reference[1 + name_len..];
+        }
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+/// Quotes in effect at some point of a command, as the command is split into arguments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+impl Quoting {
+    /// The quotes in effect after `text`, and whether its last character is an unquoted or
+    /// double-quoted backslash escaping whatever follows
+    fn after(self, text: &str) -> (Quoting, bool) {
+        let mut quoting = self;
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            quoting = match (quoting, c) {
+                (Quoting::None | Quoting::Double, '\\') => {
+                    if chars.next().is_none() {
+                        return (quoting, true);
+                    }
+                    quoting
+                }
+                (Quoting::None, '\'') => Quoting::Single,
+                (Quoting::Single, '\'') => Quoting::None,
+                (Quoting::None, '"') => Quoting::Double,
+                (Quoting::Double, '"') => Quoting::None,
+                (quoting, _) => quoting,
+            };
+        }
+        (quoting, false)
+    }
 
-        let cmd = shlex.next().unwrap();
-        let args = shlex.collect();
+    /// `value` escaped to be taken literally where these quotes are in effect. An empty value
+    /// outside of quotes stays empty, so it doesn't become an argument of its own.
+    fn escape(self, value: &str) -> String {
+        match self {
+            Quoting::None if value.is_empty() => String::new(),
+            Quoting::None => shlex::try_quote(value)
+                .map_or_else(|_| value.to_owned(), |quoted| quoted.into_owned()),
+            Quoting::Single => value.replace('\'', "'\\''"),
+            Quoting::Double => value.chars().fold(String::new(), |mut escaped, c| {
+                if matches!(c, '"' | '\\' | '$' | '`') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+                escaped
+            }),
+        }
+    }
+}
+
+/// How deeply `$(...)` command substitutions can be nested
+const MAX_SUBSTITUTION_DEPTH: usize = 4;
+
+/// Run the command of a `$(...)` substitution in `subshell`, after evaluating the substitutions
+/// nested in it
+fn substitute_command(
+    command: &str,
+    env: &Env,
+    subshell: &Subshell,
+    depth: usize,
+) -> Result<String, CommandParseError> {
+    let failed = |message: String| CommandParseError::SubstitutionFailed {
+        command: command.to_owned(),
+        message,
+    };
+    if depth > MAX_SUBSTITUTION_DEPTH {
+        return Err(failed(format!(
+            "is nested more than {MAX_SUBSTITUTION_DEPTH} levels deep"
+        )));
+    }
+
+    let mut expanded = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(start) = rest.find("$(") {
+        expanded.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
+        let end = closing_paren(inner).ok_or(CommandParseError::UnterminatedSubstitution)?;
+        expanded.push_str(&substitute_command(
+            &inner[..end],
+            env,
+            subshell,
+            depth + 1,
+        )?);
+        rest = &inner[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    #[cfg(target_family = "unix")]
+    let mut shell = Command::new("sh");
+    #[cfg(target_family = "unix")]
+    shell.arg("-c");
+    #[cfg(not(target_family = "unix"))]
+    let mut shell = Command::new("cmd");
+    #[cfg(not(target_family = "unix"))]
+    shell.arg("/C");
+
+    subshell.apply(&mut shell);
+    let output = shell
+        .arg(&expanded)
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| failed(format!("couldn't be started: {e}")))?;
+    if !output.status.success() {
+        return Err(failed(Termination::from_status(output.status).to_string()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Index of the parenthesis closing a group whose opening parenthesis precedes `text`
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 1;
+    for (index, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => continue,
+        }
+        if depth == 0 {
+            return Some(index);
+        }
+    }
+    None
+}
 
-        CmdArgs { cmd, args }
+impl TryFrom<&str> for CmdArgs {
+    type Error = CommandParseError;
+
+    fn try_from(value: &str) -> Result<CmdArgs, CommandParseError> {
+        CmdArgs::new(value, &std::env::vars().collect(), Substitution::Variables)
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::testing::TempDir;
+
     use super::*;
 
     #[test]
@@ -61,7 +1236,7 @@ mod tests {
             cmd: "cargo".into(),
             args: ["run", "--help"].into_iter().map(String::from).collect(),
         };
-        let actual = CmdArgs::from(value);
+        let actual = CmdArgs::try_from(value).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -80,8 +1255,780 @@ mod tests {
                 .map(String::from)
                 .collect(),
         };
-        let actual = CmdArgs::from(value);
+        let actual = CmdArgs::try_from(value).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    pub fn test_cmd_args_from_str_multiline_crlf() {
+        let value = "cargo run -- \\\r\n    --testing foo\r\n";
+
+        let expected = CmdArgs {
+            cmd: "cargo".into(),
+            args: ["run", "--", "--testing", "foo"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        };
+        let actual = CmdArgs::try_from(value).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    pub fn test_cmd_args_new_substitutes_env() {
+        let value = "echo $GREETING";
+        let env = Env::from([("GREETING".into(), "hello".into())]);
+
+        let expected = CmdArgs {
+            cmd: "echo".into(),
+            args: vec!["hello".into()],
+        };
+        let actual = CmdArgs::new(value, &env, Substitution::Variables).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    pub fn test_cmd_args_new_keeps_values_in_one_argument() {
+        let env = Env::from([
+            ("MESSAGE".into(), "hello world".into()),
+            ("QUOTED".into(), r#"say "hi" to 'them' \o/ $HOME"#.into()),
+            ("EMPTY".into(), "".into()),
+        ]);
+        let args = |value: &str| {
+            CmdArgs::new(value, &env, Substitution::Variables)
+                .unwrap()
+                .args
+        };
+
+        assert_eq!(args(r#"echo "$MESSAGE""#), vec!["hello world"]);
+        assert_eq!(args("echo $MESSAGE"), vec!["hello world"]);
+        assert_eq!(args("echo '$MESSAGE'"), vec!["hello world"]);
+        assert_eq!(
+            args("echo pre-${MESSAGE}-post"),
+            vec!["pre-hello world-post"]
+        );
+        assert_eq!(args("echo ${UNSET:-a b}"), vec!["a b"]);
+        for value in ["echo $QUOTED", r#"echo "$QUOTED""#, "echo '$QUOTED'"] {
+            assert_eq!(args(value), vec![r#"say "hi" to 'them' \o/ $HOME"#]);
+        }
+        assert_eq!(args(r#"echo "a $EMPTY" $EMPTY b"#), vec!["a ", "b"]);
+        assert_eq!(args(r"echo \$MESSAGE"), vec!["$MESSAGE"]);
+    }
+
+    #[test]
+    pub fn test_substitute() {
+        let env = Env::from([
+            ("HOST".into(), "example.com".into()),
+            ("HOSTNAME".into(), "web1".into()),
+            ("EMPTY".into(), "".into()),
+        ]);
+
+        assert_eq!(
+            substitute("$HOST $HOSTNAME", &env, None).unwrap(),
+            "example.com web1"
+        );
+        assert_eq!(
+            substitute("${HOST}:80", &env, None).unwrap(),
+            "example.com:80"
+        );
+        assert_eq!(
+            substitute("$MISSING ${MISSING}", &env, None).unwrap(),
+            "$MISSING ${MISSING}"
+        );
+        assert_eq!(substitute("cost: $5", &env, None).unwrap(), "cost: $5");
+        assert_eq!(substitute("trailing $", &env, None).unwrap(), "trailing $");
+    }
+
+    #[test]
+    pub fn test_substitute_default() {
+        let env = Env::from([("PORT".into(), "8080".into()), ("EMPTY".into(), "".into())]);
+
+        assert_eq!(substitute("${PORT:-3000}", &env, None).unwrap(), "8080");
+        assert_eq!(substitute("${UNSET:-3000}", &env, None).unwrap(), "3000");
+        assert_eq!(substitute("${EMPTY:-3000}", &env, None).unwrap(), "3000");
+        assert_eq!(substitute("${UNSET:-}", &env, None).unwrap(), "");
+    }
+
+    #[test]
+    pub fn test_substitute_required() {
+        let env = Env::from([
+            ("TOKEN".into(), "secret".into()),
+            ("EMPTY".into(), "".into()),
+        ]);
+
+        assert_eq!(
+            substitute("${TOKEN:?set TOKEN}", &env, None).unwrap(),
+            "secret"
+        );
+        assert_eq!(
+            substitute("${UNSET:?set UNSET first}", &env, None),
+            Err(CommandParseError::UnsetVariable {
+                name: "UNSET".into(),
+                message: "set UNSET first".into()
+            })
+        );
+        assert_eq!(
+            substitute("${EMPTY:?}", &env, None)
+                .unwrap_err()
+                .to_string(),
+            "variable 'EMPTY' is not set"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_substitute_command() {
+        let env = Env::from([("NAME".into(), "app".into())]);
+
+        assert_eq!(
+            substitute(
+                "build -t $NAME:$(echo ' v1 ')",
+                &env,
+                Some(&Subshell::default())
+            )
+            .unwrap(),
+            "build -t app:v1"
+        );
+        assert_eq!(
+            substitute(
+                "echo $(echo $(echo $NAME))",
+                &env,
+                Some(&Subshell::default())
+            )
+            .unwrap(),
+            "echo app"
+        );
+        assert_eq!(
+            substitute("echo $(echo hi)", &env, None).unwrap(),
+            "echo $(echo hi)"
+        );
+        assert_eq!(
+            substitute("echo $(echo a b)", &env, Some(&Subshell::default())).unwrap(),
+            "echo 'a b'"
+        );
+        assert_eq!(
+            substitute(
+                "echo '$(echo injected) $NAME'",
+                &env,
+                Some(&Subshell::default())
+            )
+            .unwrap(),
+            "echo '$(echo injected) app'"
+        );
+        assert_eq!(
+            substitute("echo $(exit 3)", &env, Some(&Subshell::default()))
+                .unwrap_err()
+                .to_string(),
+            "command substitution `$(exit 3)` exited with code 3"
+        );
+        assert_eq!(
+            substitute("echo $(echo (hi)", &env, Some(&Subshell::default())),
+            Err(CommandParseError::UnterminatedSubstitution)
+        );
+        assert!(matches!(
+            substitute("$($($($($(echo hi)))))", &env, Some(&Subshell::default())),
+            Err(CommandParseError::SubstitutionFailed { .. })
+        ));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_substitute_command_in_workdir() {
+        let dir = TempDir::new("substitute-workdir");
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            workdir: Some(dir.to_path_buf()),
+            ..Default::default()
+        };
+        let step = Step {
+            command: format!("test $(pwd -P) = {}", dir.canonicalize().unwrap().display()),
+            ..Default::default()
+        };
+
+        assert!(matches!(step.run(&ctx).unwrap(), Process::Finished(status) if status.success()));
+    }
+
+    #[test]
+    pub fn test_cmd_args_new_raw() {
+        let env = Env::from([("NAME".into(), "app".into())]);
+
+        let expected = CmdArgs {
+            cmd: "echo".into(),
+            args: vec!["$NAME".into(), "$(id)".into()],
+        };
+        let actual = CmdArgs::new("echo $NAME '$(id)'", &env, Substitution::None).unwrap();
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    pub fn test_validate_command_skips_substitution() {
+        assert_eq!(validate_command("deploy ${TOKEN:?set TOKEN}"), Ok(()));
+    }
+
+    #[test]
+    pub fn test_cmd_args_from_str_whitespace_only() {
+        let actual = CmdArgs::try_from("  \n\t ");
+
+        assert_eq!(actual, Err(CommandParseError::Empty));
+    }
+
+    #[test]
+    pub fn test_cmd_args_from_str_comment_only() {
+        let actual = CmdArgs::try_from("# cargo build");
+
+        assert_eq!(actual, Err(CommandParseError::Empty));
+    }
+
+    #[test]
+    pub fn test_cmd_args_from_str_unterminated_quote() {
+        let actual = CmdArgs::try_from("echo \"hello");
+
+        assert_eq!(actual, Err(CommandParseError::UnterminatedQuote));
+    }
+
+    #[test]
+    pub fn test_resolve_program_relative() {
+        let workdir = Path::new("/project/sub");
+
+        assert_eq!(
+            resolve_program("./build.sh", workdir),
+            workdir.join("./build.sh")
+        );
+        assert_eq!(
+            resolve_program("../tools/lint", workdir),
+            workdir.join("../tools/lint")
+        );
+    }
+
+    #[test]
+    pub fn test_resolve_program_absolute() {
+        assert_eq!(
+            resolve_program("/usr/bin/env", Path::new("/project")),
+            PathBuf::from("/usr/bin/env")
+        );
+    }
+
+    #[test]
+    pub fn test_resolve_program_path_lookup() {
+        assert_eq!(
+            resolve_program("cargo", Path::new("/project")),
+            PathBuf::from("cargo")
+        );
+        // Only an explicit `./` prefix makes a path relative to the working directory
+        assert_eq!(
+            resolve_program("bin/tool", Path::new("/project")),
+            PathBuf::from("bin/tool")
+        );
+    }
+
+    #[test]
+    pub fn test_run_command_not_found() {
+        let step = Step {
+            command: "unirun-missing-program --flag".into(),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            label: "build/2".into(),
+            ..Default::default()
+        };
+
+        let Err(err) = step.run(&ctx) else {
+            panic!("missing program ran");
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "command not found: `unirun-missing-program` (in step build/2) - is it installed and on PATH?"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_past_deadline() {
+        let step = Step {
+            command: "sleep 10".into(),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let Process::Rejected { reason, .. } = step.run(&ctx).unwrap() else {
+            panic!("step was not rejected");
+        };
+
+        assert_eq!(reason, "job timed out");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_expectations() {
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+        let step = |expect: fn(&mut Step)| {
+            let mut step = Step {
+                command: "sh -c 'echo status: OK; exit 3'".into(),
+                expect_exit: Some(3),
+                ..Default::default()
+            };
+            expect(&mut step);
+            step.run(&ctx).unwrap()
+        };
+
+        let met = step(|s| s.expect_stdout_contains = Some("OK".into()));
+        assert!(matches!(met, Process::Finished(s) if s.code() == Some(3)));
+        let met = step(|s| s.expect_stdout_matches = Some("^status: (OK|DONE)$".into()));
+        assert!(matches!(met, Process::Finished(_)));
+
+        let Process::Rejected { reason, .. } =
+            step(|s| s.expect_stdout_contains = Some("FAILED".into()))
+        else {
+            panic!("step was not rejected");
+        };
+        assert_eq!(
+            reason,
+            r#"expected stdout to contain "FAILED", got "status: OK""#
+        );
+        let Process::Rejected { reason, .. } = step(|s| s.expect_exit = Some(0)) else {
+            panic!("step was not rejected");
+        };
+        assert_eq!(reason, "exited with code 3, expected exit code 0");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_expect_stdout_past_capture_limit() {
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            buffer_output: Some(Default::default()),
+            ..Default::default()
+        };
+        let lines = CAPTURE_LIMIT; // Two bytes each
+        let step = Step {
+            command: format!("sh -c 'yes | head -n {lines}; echo DONE'"),
+            expect_stdout_contains: Some("DONE".into()),
+            ..Default::default()
+        };
+
+        assert!(matches!(step.run(&ctx).unwrap(), Process::Finished(_)));
+    }
+
+    #[test]
+    pub fn test_snippet() {
+        assert_eq!(snippet("  \n"), "no output");
+        assert_eq!(snippet("ok\n"), r#""ok""#);
+        assert_eq!(
+            snippet(&"x".repeat(300)),
+            format!("{:?}...", "x".repeat(200))
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_skipped() {
+        let step = Step {
+            command: "sleep 10".into(),
+            ..Default::default()
+        };
+        let skip = Arc::new(AtomicBool::new(true));
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            skip: Some(Arc::clone(&skip)),
+            ..Default::default()
+        };
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            skip.store(true, Ordering::Relaxed);
+        });
+
+        let started = Instant::now();
+        let Process::Rejected { reason, .. } = step.run(&ctx).unwrap() else {
+            panic!("step was not rejected");
+        };
+
+        assert_eq!(reason, "skipped from the keyboard");
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_past_deadline_kill() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let step = Step {
+            command: "sh -c 'trap \"\" TERM; sleep 10'".into(),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+            timeout_action: TimeoutAction::Kill,
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let Process::Rejected { status, .. } = step.run(&ctx).unwrap() else {
+            panic!("step was not rejected");
+        };
+
+        assert_eq!(status.signal(), Some(9));
+        assert!(started.elapsed() < TIMEOUT_GRACE);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_step_error_display() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let step = Step {
+            command: "cargo test $ARGS".into(),
+            ..Default::default()
+        };
+        let env = Env::from([("ARGS".into(), "--workspace".into())]);
+
+        let error = StepError::new("test", 1, &step, &env, ExitStatus::from_raw(101 << 8));
+        assert_eq!(
+            error.to_string(),
+            "job 'test' step 1 ('cargo test --workspace') failed with exit code 101"
+        );
+
+        let step = Step {
+            name: Some("server".into()),
+            ..step
+        };
+        let error = StepError::new("dev", 0, &step, &env, ExitStatus::from_raw(9));
+        assert_eq!(
+            error.to_string(),
+            "job 'dev' step 0 'server' ('cargo test --workspace') was killed by signal 9"
+        );
+        assert_eq!(error.exit_code, 137);
+
+        // Succeeded by its exit code, but failed one of its checks
+        let error = StepError::new("dev", 0, &step, &env, ExitStatus::from_raw(0));
+        assert_eq!(
+            error.to_string(),
+            "job 'dev' step 0 'server' ('cargo test --workspace') failed"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_script() {
+        let dir = TempDir::new("script");
+        let record = dir.join("record");
+        let step = Step {
+            script: Some(format!("echo \"$0\" > {}\nexit 3\n", record.display())),
+            interpreter: Some("sh -e".into()),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+
+        let Process::Finished(status) = step.run(&ctx).unwrap() else {
+            panic!("step did not run to completion");
+        };
+        let script_path = std::fs::read_to_string(&record).unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        assert!(!Path::new(script_path.trim()).exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_with_shell() {
+        let step = Step {
+            command: "[ \"$(echo $GREETING)\" = hello ] && true".into(),
+            shell: Some("sh -e".into()),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: Env::from([
+                ("GREETING".into(), "hello".into()),
+                ("PATH".into(), std::env::var("PATH").unwrap()),
+            ]),
+            ..Default::default()
+        };
+
+        assert!(matches!(step.run(&ctx).unwrap(), Process::Finished(status) if status.success()));
+    }
+
+    #[test]
+    pub fn test_shell_flag() {
+        assert_eq!(shell_flag("bash"), "-c");
+        assert_eq!(shell_flag("/usr/bin/zsh"), "-c");
+        assert_eq!(shell_flag("cmd"), "/C");
+        assert_eq!(shell_flag("CMD.EXE"), "/C");
+    }
+
+    #[test]
+    pub fn test_step_error_display_script() {
+        let step = Step {
+            script: Some("print('hi')".into()),
+            interpreter: Some("python3 -u".into()),
+            ..Default::default()
+        };
+
+        let error = StepError::new("gen", 0, &step, &Env::new(), ExitStatus::default());
+        assert_eq!(error.command, "python3 -u <script>");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_past_soft_timeout() {
+        let step = Step {
+            command: "sleep 10".into(),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            soft_timeout: Some(Duration::from_millis(100)),
+            fail_on_soft_timeout: true,
+            ..Default::default()
+        };
+
+        let Process::Rejected { reason, .. } = step.run(&ctx).unwrap() else {
+            panic!("step was not rejected");
+        };
+
+        assert_eq!(reason, "still running after 100ms");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_output_files() {
+        let dir = TempDir::new("output-files");
+        let capture = Arc::new(Mutex::new(Vec::new()));
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            workdir: Some(dir.to_path_buf()),
+            capture_stdout: Some(Arc::clone(&capture)),
+            ..Default::default()
+        };
+        let step = Step {
+            command: "sh -c 'echo out; echo err >&2'".into(),
+            stdout_file: Some(PathBuf::from("reports/out.txt")),
+            stderr_file: Some(PathBuf::from("reports/err.txt")),
+            silent: true,
+            ..Default::default()
+        };
+
+        step.run(&ctx).unwrap();
+        let appended = Step {
+            command: "echo again".into(),
+            stderr_file: None,
+            append: true,
+            ..step.clone()
+        };
+        appended.run(&ctx).unwrap();
+        let combined = Step {
+            command: "sh -c 'echo out; echo err >&2'".into(),
+            stdout_file: Some(PathBuf::from("combined.txt")),
+            stderr_file: Some(PathBuf::from("combined.txt")),
+            ..Default::default()
+        };
+        combined
+            .run(&StepContext {
+                capture_stdout: None,
+                ..ctx.clone()
+            })
+            .unwrap();
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap();
+        let files = [
+            read("reports/out.txt"),
+            read("reports/err.txt"),
+            read("combined.txt"),
+        ];
+
+        assert_eq!(files, ["out\nagain\n", "err\n", "out\nerr\n"]);
+        assert_eq!(capture.lock().unwrap().as_slice(), b"out\nagain\n");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_print_command() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let ctx = StepContext {
+            env: std::env::vars()
+                .chain([(String::from("TOKEN"), String::from("s3cr3t"))])
+                .collect(),
+            label: String::from("deploy/0"),
+            buffer_output: Some(Arc::clone(&buffer)),
+            print_command: true,
+            secrets: vec![String::from("s3cr3t")],
+            ..Default::default()
+        };
+        let step = Step {
+            command: "echo 'token: $TOKEN'".into(),
+            raw: true,
+            ..Default::default()
+        };
+
+        step.run(&ctx).unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&buffer.lock().unwrap()),
+            "+ deploy/0: echo 'token: $TOKEN'\ntoken: $TOKEN\n"
+        );
+        let step = Step {
+            command: "echo $TOKEN".into(),
+            ..Default::default()
+        };
+        buffer.lock().unwrap().clear();
+        step.run(&ctx).unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&buffer.lock().unwrap()),
+            "+ deploy/0: echo ***\ns3cr3t\n"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_past_max_duration() {
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+        let step = |command: &str| Step {
+            command: command.into(),
+            max_duration: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+
+        let Process::Rejected { status, reason } = step("sleep 0.3").run(&ctx).unwrap() else {
+            panic!("step was not rejected");
+        };
+        assert!(status.success());
+        assert!(reason.starts_with("exceeded max_duration ("));
+        assert!(reason.ends_with(" > 100ms)"));
+        assert!(matches!(
+            step("true").run(&ctx).unwrap(),
+            Process::Finished(_)
+        ));
+        let failed = step("sh -c 'sleep 0.3; exit 1'").run(&ctx).unwrap();
+        assert!(matches!(failed, Process::Finished(s) if s.code() == Some(1)));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_capture_stdout() {
+        let step = Step {
+            command: "echo hello".into(),
+            ..Default::default()
+        };
+        let capture = Arc::new(Mutex::new(Vec::new()));
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            capture_stdout: Some(Arc::clone(&capture)),
+            ..Default::default()
+        };
+
+        assert!(matches!(step.run(&ctx).unwrap(), Process::Finished(status) if status.success()));
+        assert_eq!(*capture.lock().unwrap(), b"hello\n");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_silent_ignores_output_limit() {
+        let step = Step {
+            command: "echo hello world".into(),
+            silent: true,
+            max_output_bytes: Some(1),
+            fail_on_output_limit: true,
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+
+        assert!(matches!(step.run(&ctx).unwrap(), Process::Finished(status) if status.success()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    pub fn test_run_with_priority() {
+        let step = Step {
+            command: "sh -c '[ \"$(nice)\" = 5 ]'".into(),
+            priority: Some(5),
+            ..Default::default()
+        };
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+
+        let Process::Finished(status) = step.run(&ctx).unwrap() else {
+            panic!("step did not run to completion");
+        };
+
+        assert!(status.success());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_resolve_user() {
+        assert_eq!(resolve_user("root").unwrap(), (0, Some(0)));
+        assert_eq!(resolve_user("0").unwrap(), (0, Some(0)));
+        assert_eq!(resolve_user("4000000").unwrap(), (4000000, None));
+        assert!(resolve_user("no-such-user-unirun").is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_resolve_group() {
+        assert_eq!(resolve_group("0").unwrap(), 0);
+        assert!(resolve_group("no-such-group-unirun").is_err());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    pub fn test_run_as_user() {
+        use nix::unistd::{Uid, User};
+
+        // Dropping privileges needs root and a known unprivileged account
+        let Ok(Some(nobody)) = User::from_name("nobody") else {
+            return;
+        };
+        if !Uid::effective().is_root() {
+            return;
+        }
+
+        let ctx = StepContext {
+            env: std::env::vars().collect(),
+            ..Default::default()
+        };
+        let run = |command: String| {
+            let step = Step {
+                command,
+                user: Some("nobody".into()),
+                ..Default::default()
+            };
+            let Process::Finished(status) = step.run(&ctx).unwrap() else {
+                panic!("step did not run to completion");
+            };
+            status
+        };
+
+        let (uid, gid) = (nobody.uid, nobody.gid);
+        assert!(run(format!(
+            "sh -c '[ \"$(id -u)\" = {uid} ] && [ \"$(id -g)\" = {gid} ]'"
+        ))
+        .success());
+        // Command substitutions run as the user too
+        assert!(run(format!("test $(id -u):$(id -g) = {uid}:{gid}")).success());
+    }
 }
@@ -0,0 +1,39 @@
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+/// Empty directory for a test, removed when dropped, so it's cleaned up even if the test panics
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create the directory, unique to `name` and the test process
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("unirun-{name}-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{collections::HashSet, error::Error};
+
+use itertools::Itertools;
+use petgraph::{
+    graph::{DiGraph, NodeIndex},
+    Direction,
+};
+
+use crate::{
+    job::{collect_dependencies, find_node},
+    list::describe,
+    runfile::Runfile,
+};
+
+/// Format the dependencies of `root`, or of every job nothing else needs, as an indented tree
+/// for `uni tree`. A job with dependencies of its own that already appeared earlier in the same
+/// tree is marked with `(*)` instead of being expanded again.
+pub fn format_tree(runfile: &Runfile, root: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    let deps = collect_dependencies(runfile)?;
+    let graph = deps.inner();
+
+    let roots = match root {
+        Some(root) => vec![find_node(&deps, root)?],
+        None => graph
+            .node_indices()
+            .filter(|&n| {
+                graph
+                    .neighbors_directed(n, Direction::Incoming)
+                    .next()
+                    .is_none()
+            })
+            .sorted_by_key(|&n| &graph[n])
+            .collect(),
+    };
+
+    let mut lines = Vec::new();
+    for root in roots {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(describe(&graph[root], &runfile.jobs[&graph[root]]));
+        format_children(
+            runfile,
+            graph,
+            root,
+            "",
+            &mut HashSet::from([root]),
+            &mut lines,
+        );
+    }
+
+    Ok(lines)
+}
+
+fn format_children(
+    runfile: &Runfile,
+    graph: &DiGraph<String, ()>,
+    node: NodeIndex,
+    prefix: &str,
+    seen: &mut HashSet<NodeIndex>,
+    lines: &mut Vec<String>,
+) {
+    let children = graph
+        .neighbors(node)
+        .sorted_by_key(|&n| &graph[n])
+        .collect::<Vec<_>>();
+
+    for (i, &child) in children.iter().enumerate() {
+        let last = i == children.len() - 1;
+        let (branch, indent) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+
+        let label = describe(&graph[child], &runfile.jobs[&graph[child]]);
+        if !seen.insert(child) && graph.neighbors(child).next().is_some() {
+            lines.push(format!("{prefix}{branch}{label} (*)"));
+            continue;
+        }
+
+        lines.push(format!("{prefix}{branch}{label}"));
+        format_children(
+            runfile,
+            graph,
+            child,
+            &format!("{prefix}{indent}"),
+            seen,
+            lines,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::runfile::Job;
+
+    use super::*;
+
+    fn job(needs: &[&str]) -> Job {
+        Job {
+            needs: needs.iter().map(|&n| n.into()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn runfile() -> Runfile {
+        Runfile {
+            default: String::from("dev"),
+            jobs: HashMap::from([
+                ("dev".into(), job(&["server", "worker"])),
+                ("server".into(), job(&["db"])),
+                ("worker".into(), job(&["db"])),
+                (
+                    "db".into(),
+                    Job {
+                        name: Some(String::from("Database")),
+                        ..job(&["docker"])
+                    },
+                ),
+                ("docker".into(), job(&[])),
+                ("lint".into(), job(&[])),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_tree_root() {
+        let lines = format_tree(&runfile(), Some("dev")).unwrap();
+
+        assert_eq!(
+            lines,
+            vec![
+                "dev",
+                "├── server",
+                "│   └── db - Database",
+                "│       └── docker",
+                "└── worker",
+                "    └── db - Database (*)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_tree_all_roots() {
+        let lines = format_tree(&runfile(), None).unwrap();
+
+        assert_eq!(lines[0], "dev");
+        assert_eq!(lines[lines.len() - 2..], ["", "lint"]);
+    }
+
+    #[test]
+    fn test_format_tree_unknown_root() {
+        assert!(format_tree(&runfile(), Some("missing")).is_err());
+    }
+}
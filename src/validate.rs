@@ -0,0 +1,1201 @@
+use std::{collections::HashMap, fmt::Display};
+
+use petgraph::{acyclic::Acyclic, data::Build, graph::DiGraph};
+use regex::Regex;
+
+use crate::{
+    condition::{self, ConditionError},
+    runfile::{JobId, Need, RestartPolicy, Runfile, StepOrderError, KNOWN_PLATFORMS},
+    step::{validate_command, CommandParseError},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Words, or runs of words, in commands that usually keep running until stopped, such as dev
+/// servers and file watchers, unless the runfile sets `long_running_patterns`
+pub const LONG_RUNNING_PATTERNS: &[&str] = &[
+    "serve",
+    "server",
+    "runserver",
+    "dev",
+    "watch",
+    "--watch",
+    "up",
+    "tail -f",
+    "nodemon",
+    "webpack-dev-server",
+    "http-server",
+    "live-server",
+];
+
+/// A problem found in a runfile
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Issue {
+    DefaultNotDefined(JobId),
+    ManualDefault(JobId),
+    DefaultAndDefaults,
+    NoDefault,
+    UnknownNeed {
+        job: JobId,
+        need: JobId,
+    },
+    DependencyCycle {
+        job: JobId,
+        need: JobId,
+    },
+    InvalidCondition {
+        job: JobId,
+        need: JobId,
+        error: ConditionError,
+    },
+    InvalidCommand {
+        job: JobId,
+        step: usize,
+        error: CommandParseError,
+    },
+    RunAndScript {
+        job: JobId,
+        step: usize,
+    },
+    ScriptWithoutInterpreter {
+        job: JobId,
+        step: usize,
+    },
+    InvalidRestartJitter {
+        job: JobId,
+        step: usize,
+    },
+    InvalidRetryJitter {
+        job: JobId,
+        step: usize,
+    },
+    InvalidAfterReady {
+        job: JobId,
+        step: usize,
+        reference: String,
+    },
+    DuplicateAlias {
+        alias: String,
+        job: JobId,
+        other: JobId,
+    },
+    AliasIsJobId {
+        alias: String,
+        job: JobId,
+    },
+    EmptyJob(JobId),
+    RestartNotPersistent {
+        job: JobId,
+        step: usize,
+    },
+    ShutdownTimeoutNotPersistent {
+        job: JobId,
+        step: usize,
+    },
+    OutputLimitNotSet {
+        job: JobId,
+        step: usize,
+    },
+    UnknownPlatform {
+        job: JobId,
+        step: usize,
+        platform: String,
+    },
+    InterpreterWithoutScript {
+        job: JobId,
+        step: usize,
+    },
+    ReadyNotPersistent {
+        job: JobId,
+        step: usize,
+    },
+    IgnoreSignalsPersistent {
+        job: JobId,
+        step: usize,
+    },
+    UnknownSignal {
+        job: JobId,
+        step: usize,
+        signal: String,
+    },
+    ReloadSignalNotPersistent {
+        job: JobId,
+        step: usize,
+    },
+    InvalidStdoutPattern {
+        job: JobId,
+        step: usize,
+        error: String,
+    },
+    ExpectationNotChecked {
+        job: JobId,
+        step: usize,
+        reason: &'static str,
+    },
+    SuccessExitCodesIgnored {
+        job: JobId,
+        step: usize,
+    },
+    LooksPersistent {
+        job: JobId,
+        step: usize,
+        pattern: String,
+    },
+    InvalidStepOrder {
+        job: JobId,
+        error: StepOrderError,
+    },
+}
+
+impl Issue {
+    /// Whether the issue prevents the runfile from working (errors) or is merely suspicious
+    /// (warnings). This is the only place issues are classified.
+    pub fn severity(&self) -> Severity {
+        match self {
+            Issue::DefaultNotDefined(_)
+            | Issue::ManualDefault(_)
+            | Issue::DefaultAndDefaults
+            | Issue::UnknownNeed { .. }
+            | Issue::DependencyCycle { .. }
+            | Issue::InvalidCondition { .. }
+            | Issue::InvalidCommand { .. }
+            | Issue::RunAndScript { .. }
+            | Issue::ScriptWithoutInterpreter { .. }
+            | Issue::InvalidRestartJitter { .. }
+            | Issue::InvalidRetryJitter { .. }
+            | Issue::InvalidAfterReady { .. }
+            | Issue::DuplicateAlias { .. }
+            | Issue::AliasIsJobId { .. }
+            | Issue::UnknownSignal { .. }
+            | Issue::InvalidStdoutPattern { .. }
+            | Issue::InvalidStepOrder { .. } => Severity::Error,
+            Issue::NoDefault
+            | Issue::EmptyJob(_)
+            | Issue::RestartNotPersistent { .. }
+            | Issue::ShutdownTimeoutNotPersistent { .. }
+            | Issue::OutputLimitNotSet { .. }
+            | Issue::UnknownPlatform { .. }
+            | Issue::InterpreterWithoutScript { .. }
+            | Issue::ReadyNotPersistent { .. }
+            | Issue::IgnoreSignalsPersistent { .. }
+            | Issue::ReloadSignalNotPersistent { .. }
+            | Issue::ExpectationNotChecked { .. }
+            | Issue::LooksPersistent { .. }
+            | Issue::SuccessExitCodesIgnored { .. } => Severity::Warning,
+        }
+    }
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::DefaultNotDefined(id) => write!(f, "default job '{id}' not defined"),
+            Issue::ManualDefault(id) => write!(f, "default job '{id}' is manual"),
+            Issue::DefaultAndDefaults => {
+                write!(f, "`default` and `defaults` can't both be set")
+            }
+            Issue::NoDefault => write!(
+                f,
+                "no `default` or `defaults` job, so `uni run` always needs a job"
+            ),
+            Issue::UnknownNeed { job, need } => {
+                write!(f, "job '{job}' needs '{need}', which is not defined")
+            }
+            Issue::DependencyCycle { job, need } => {
+                write!(f, "job '{job}' needing '{need}' creates a dependency cycle")
+            }
+            Issue::InvalidCondition { job, need, error } => {
+                write!(f, "job '{job}' needing '{need}': {error}")
+            }
+            Issue::InvalidCommand { job, step, error } => {
+                write!(f, "job '{job}' step {step}: {error}")
+            }
+            Issue::DuplicateAlias { alias, job, other } => {
+                write!(f, "job '{job}' alias '{alias}' is also an alias of '{other}'")
+            }
+            Issue::AliasIsJobId { alias, job } => {
+                write!(f, "job '{job}' alias '{alias}' is the id of another job")
+            }
+            Issue::RunAndScript { job, step } => {
+                write!(f, "job '{job}' step {step}: `run` and `script` can't both be set")
+            }
+            Issue::ScriptWithoutInterpreter { job, step } => {
+                write!(f, "job '{job}' step {step}: `script` needs an `interpreter`")
+            }
+            Issue::InvalidRestartJitter { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `restart_jitter` must be between 0 and 1"
+            ),
+            Issue::InvalidRetryJitter { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `retry_jitter` must be between 0 and 1"
+            ),
+            Issue::InvalidAfterReady {
+                job,
+                step,
+                reference,
+            } => write!(
+                f,
+                "job '{job}' step {step}: `after_ready` step '{reference}' is not an earlier persistent step with a `ready` check"
+            ),
+            Issue::EmptyJob(id) => write!(f, "job '{id}' has no steps and no needs"),
+            Issue::RestartNotPersistent { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `restart` has no effect on a non-persistent step"
+            ),
+            Issue::ShutdownTimeoutNotPersistent { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `shutdown_timeout` has no effect on a non-persistent step"
+            ),
+            Issue::OutputLimitNotSet { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `fail_on_output_limit` has no effect without `max_output_bytes`"
+            ),
+            Issue::UnknownPlatform {
+                job,
+                step,
+                platform,
+            } => write!(
+                f,
+                "job '{job}' step {step}: unknown platform '{platform}', the step never runs there"
+            ),
+            Issue::InterpreterWithoutScript { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `interpreter` has no effect without `script`"
+            ),
+            Issue::ReadyNotPersistent { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `ready` has no effect on a non-persistent step"
+            ),
+            Issue::IgnoreSignalsPersistent { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `ignore_signals` has no effect on a persistent step"
+            ),
+            Issue::UnknownSignal { job, step, signal } => {
+                write!(f, "job '{job}' step {step}: unknown `reload_signal` '{signal}'")
+            }
+            Issue::ReloadSignalNotPersistent { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `reload_signal` has no effect on a non-persistent step"
+            ),
+            Issue::InvalidStdoutPattern { job, step, error } => {
+                write!(f, "job '{job}' step {step}: invalid `expect_stdout_matches`: {error}")
+            }
+            Issue::SuccessExitCodesIgnored { job, step } => write!(
+                f,
+                "job '{job}' step {step}: `success_exit_codes` has no effect with `expect_exit`"
+            ),
+            Issue::ExpectationNotChecked { job, step, reason } => {
+                write!(f, "job '{job}' step {step}: expectations are never checked on {reason}")
+            }
+            Issue::InvalidStepOrder { job, error } => write!(f, "job '{job}': {error}"),
+            Issue::LooksPersistent { job, step, pattern } => write!(
+                f,
+                "job '{job}' step {step}: command contains `{pattern}`, so it may keep running and \
+                 block the run; set `persistent: true`, or `persistent: false` if it exits"
+            ),
+        }
+    }
+}
+
+/// Check a runfile for problems, sorted with errors first
+pub fn validate(runfile: &Runfile) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if !runfile.default.is_empty() && !runfile.defaults.is_empty() {
+        issues.push(Issue::DefaultAndDefaults);
+    }
+    let defaults = runfile.default_jobs();
+    if defaults.is_empty() {
+        issues.push(Issue::NoDefault);
+    }
+    for id in defaults {
+        // An ambiguous alias is reported along with the other aliases
+        match runfile.resolve_alias(id).map(|id| runfile.jobs.get(id)) {
+            Ok(None) => issues.push(Issue::DefaultNotDefined(id.to_owned())),
+            Ok(Some(job)) if job.manual => issues.push(Issue::ManualDefault(id.to_owned())),
+            Ok(Some(_)) | Err(_) => {}
+        }
+    }
+
+    let mut graph: Acyclic<DiGraph<&str, ()>> = Acyclic::new();
+    let nodes = runfile
+        .jobs
+        .keys()
+        .map(|id| (id.as_str(), graph.add_node(id.as_str())))
+        .collect::<HashMap<_, _>>();
+
+    let mut ids = runfile.jobs.keys().collect::<Vec<_>>();
+    ids.sort();
+
+    let mut aliases = HashMap::new();
+    for id in ids {
+        let job = &runfile.jobs[id];
+
+        for alias in job.aliases.iter() {
+            if runfile.jobs.contains_key(alias) {
+                issues.push(Issue::AliasIsJobId {
+                    alias: alias.clone(),
+                    job: id.clone(),
+                });
+            } else if let Some(other) = aliases.insert(alias.as_str(), id) {
+                issues.push(Issue::DuplicateAlias {
+                    alias: alias.clone(),
+                    job: id.clone(),
+                    other: other.clone(),
+                });
+            }
+        }
+
+        for Need {
+            job: need,
+            condition,
+        } in job.needs.iter()
+        {
+            let Some(&need_node) = nodes.get(need.as_str()) else {
+                issues.push(Issue::UnknownNeed {
+                    job: id.clone(),
+                    need: need.clone(),
+                });
+                continue;
+            };
+            // Only needs whose condition holds are part of the graph
+            let active = condition
+                .as_deref()
+                .map_or(Ok(true), |c| condition::evaluate(c, &runfile.params));
+            match active {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(error) => {
+                    issues.push(Issue::InvalidCondition {
+                        job: id.clone(),
+                        need: need.clone(),
+                        error,
+                    });
+                    continue;
+                }
+            }
+
+            if graph
+                .try_add_edge(nodes[id.as_str()], need_node, ())
+                .is_err()
+            {
+                issues.push(Issue::DependencyCycle {
+                    job: id.clone(),
+                    need: need.clone(),
+                });
+            }
+        }
+
+        if job.steps.is_empty() && job.needs.is_empty() {
+            issues.push(Issue::EmptyJob(id.clone()));
+        }
+
+        let step_order = job.step_order();
+        if let Err(error) = &step_order {
+            issues.push(Issue::InvalidStepOrder {
+                job: id.clone(),
+                error: error.clone(),
+            });
+        }
+        // Where a step runs, going by its index if the steps can't be ordered
+        let position = |index: usize| match &step_order {
+            Ok(order) => order.iter().position(|&i| i == index).unwrap_or(index),
+            Err(_) => index,
+        };
+        for (index, step) in job.steps.iter().enumerate() {
+            let command = match (&step.script, &step.interpreter) {
+                (Some(_), _) if !step.command.trim().is_empty() => {
+                    issues.push(Issue::RunAndScript {
+                        job: id.clone(),
+                        step: index,
+                    });
+                    None
+                }
+                (Some(_), None) => {
+                    issues.push(Issue::ScriptWithoutInterpreter {
+                        job: id.clone(),
+                        step: index,
+                    });
+                    None
+                }
+                (Some(_), Some(interpreter)) => Some(interpreter.as_str()),
+                (None, interpreter) => {
+                    if interpreter.is_some() {
+                        issues.push(Issue::InterpreterWithoutScript {
+                            job: id.clone(),
+                            step: index,
+                        });
+                    }
+                    Some(step.command.as_str())
+                }
+            };
+            if let Some(Err(error)) = command.map(validate_command) {
+                issues.push(Issue::InvalidCommand {
+                    job: id.clone(),
+                    step: index,
+                    error,
+                });
+            }
+            let pattern = match (&step.persistent, &runfile.long_running_patterns) {
+                (Some(_), _) => None,
+                (None, Some(patterns)) => long_running_pattern(&step.command, patterns),
+                (None, None) => long_running_pattern(&step.command, LONG_RUNNING_PATTERNS),
+            };
+            if let Some(pattern) = pattern {
+                issues.push(Issue::LooksPersistent {
+                    job: id.clone(),
+                    step: index,
+                    pattern,
+                });
+            }
+            if !step.is_persistent() && step.restart != RestartPolicy::No {
+                issues.push(Issue::RestartNotPersistent {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if !step.is_persistent() && step.shutdown_timeout.is_some() {
+                issues.push(Issue::ShutdownTimeoutNotPersistent {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if !(0.0..=1.0).contains(&step.restart_jitter) {
+                issues.push(Issue::InvalidRestartJitter {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if !(0.0..=1.0).contains(&step.retry_jitter) {
+                issues.push(Issue::InvalidRetryJitter {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if let Some(reference) = &step.after_ready {
+                let waited_on = job
+                    .step_index(reference)
+                    .filter(|&i| position(i) < position(index))
+                    .map(|i| &job.steps[i]);
+                if !waited_on.is_some_and(|s| s.is_persistent() && s.ready.is_some()) {
+                    issues.push(Issue::InvalidAfterReady {
+                        job: id.clone(),
+                        step: index,
+                        reference: reference.clone(),
+                    });
+                }
+            }
+            if !step.is_persistent() && step.ready.is_some() {
+                issues.push(Issue::ReadyNotPersistent {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if step.is_persistent() && step.ignore_signals {
+                issues.push(Issue::IgnoreSignalsPersistent {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            if let Some(signal) = &step.reload_signal {
+                // Other platforms restart the step instead, whatever the signal
+                #[cfg(target_family = "unix")]
+                if crate::step::signal_from_name(signal).is_none() {
+                    issues.push(Issue::UnknownSignal {
+                        job: id.clone(),
+                        step: index,
+                        signal: signal.clone(),
+                    });
+                }
+                if !step.is_persistent() {
+                    issues.push(Issue::ReloadSignalNotPersistent {
+                        job: id.clone(),
+                        step: index,
+                    });
+                }
+            }
+            if let Some(Err(error)) = step.expect_stdout_matches.as_deref().map(Regex::new) {
+                issues.push(Issue::InvalidStdoutPattern {
+                    job: id.clone(),
+                    step: index,
+                    error: error.to_string(),
+                });
+            }
+            if step.expect_exit.is_some() && step.success_exit_codes != [0] {
+                issues.push(Issue::SuccessExitCodesIgnored {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            let expects =
+                step.expect_exit.is_some() || step.expects_stdout() || step.max_duration.is_some();
+            let unchecked = if expects && step.is_persistent() {
+                Some("a persistent step")
+            } else if step.expects_stdout() && step.silent && step.stdout_file.is_none() {
+                Some("the output of a silent step")
+            } else {
+                None
+            };
+            if let Some(reason) = unchecked {
+                issues.push(Issue::ExpectationNotChecked {
+                    job: id.clone(),
+                    step: index,
+                    reason,
+                });
+            }
+            if step.fail_on_output_limit
+                && step.max_output_bytes.or(runfile.max_output_bytes).is_none()
+            {
+                issues.push(Issue::OutputLimitNotSet {
+                    job: id.clone(),
+                    step: index,
+                });
+            }
+            for platform in step.platforms.iter() {
+                if !KNOWN_PLATFORMS.contains(&platform.as_str()) {
+                    issues.push(Issue::UnknownPlatform {
+                        job: id.clone(),
+                        step: index,
+                        platform: platform.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity()));
+    issues
+}
+
+/// The first of `patterns` whose words appear in `command` in a row. The program counts by its
+/// file name, so `./bin/server` contains `server`.
+fn long_running_pattern(command: &str, patterns: &[impl AsRef<str>]) -> Option<String> {
+    let words = command
+        .split_whitespace()
+        .enumerate()
+        .map(|(i, word)| match i {
+            0 => word.rsplit(['/', '\\']).next().unwrap_or(word),
+            _ => word,
+        })
+        .collect::<Vec<_>>();
+    patterns
+        .iter()
+        .map(AsRef::as_ref)
+        .find(|pattern| {
+            let pattern = pattern.split_whitespace().collect::<Vec<_>>();
+            !pattern.is_empty() && words.windows(pattern.len()).any(|w| w == pattern)
+        })
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::runfile::{Job, Step};
+
+    use super::*;
+
+    fn step(command: &str) -> Step {
+        Step {
+            command: command.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let runfile = Runfile {
+            default: String::from("build"),
+            jobs: HashMap::from([(
+                "build".into(),
+                Job {
+                    steps: vec![step("cargo build")],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(validate(&runfile), Vec::new());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let runfile = Runfile {
+            default: String::from("missing"),
+            jobs: HashMap::from([
+                (
+                    "a".into(),
+                    Job {
+                        needs: vec!["b".into(), "nope".into()],
+                        steps: vec![step("  ")],
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "b".into(),
+                    Job {
+                        needs: vec!["a".into()],
+                        steps: vec![Step {
+                            restart_jitter: 1.5,
+                            retry_jitter: -0.5,
+                            ..step("true")
+                        }],
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile);
+
+        assert!(issues.iter().all(|i| i.severity() == Severity::Error));
+        assert!(issues.contains(&Issue::DefaultNotDefined("missing".into())));
+        assert!(issues.contains(&Issue::UnknownNeed {
+            job: "a".into(),
+            need: "nope".into()
+        }));
+        assert!(issues.contains(&Issue::DependencyCycle {
+            job: "b".into(),
+            need: "a".into()
+        }));
+        assert!(issues.contains(&Issue::InvalidCommand {
+            job: "a".into(),
+            step: 0,
+            error: CommandParseError::Empty
+        }));
+        assert!(issues.contains(&Issue::InvalidRestartJitter {
+            job: "b".into(),
+            step: 0
+        }));
+        assert!(issues.contains(&Issue::InvalidRetryJitter {
+            job: "b".into(),
+            step: 0
+        }));
+    }
+
+    #[test]
+    fn test_validate_defaults() {
+        let job = Job {
+            steps: vec![step("true")],
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("build"),
+            defaults: vec!["build".into(), "dev".into()],
+            jobs: HashMap::from([("build".into(), job)]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![
+                Issue::DefaultAndDefaults,
+                Issue::DefaultNotDefined("dev".into())
+            ]
+        );
+
+        let runfile = Runfile {
+            default: String::new(),
+            defaults: Vec::new(),
+            ..runfile
+        };
+        assert_eq!(validate(&runfile), vec![Issue::NoDefault]);
+    }
+
+    #[test]
+    fn test_validate_manual_default() {
+        let runfile = Runfile {
+            default: String::from("db:reset"),
+            jobs: HashMap::from([(
+                "db:reset".into(),
+                Job {
+                    steps: vec![step("./reset.sh")],
+                    manual: true,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![Issue::ManualDefault("db:reset".into())]
+        );
+    }
+
+    #[test]
+    fn test_validate_script() {
+        let script = Step {
+            script: Some("print('hi')".into()),
+            interpreter: Some("python3".into()),
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("a"),
+            jobs: HashMap::from([(
+                "a".into(),
+                Job {
+                    steps: vec![
+                        script.clone(),
+                        Step {
+                            command: "true".into(),
+                            ..script.clone()
+                        },
+                        Step {
+                            interpreter: None,
+                            ..script
+                        },
+                        Step {
+                            interpreter: Some("python3".into()),
+                            ..step("true")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![
+                Issue::RunAndScript {
+                    job: "a".into(),
+                    step: 1
+                },
+                Issue::ScriptWithoutInterpreter {
+                    job: "a".into(),
+                    step: 2
+                },
+                Issue::InterpreterWithoutScript {
+                    job: "a".into(),
+                    step: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_aliases() {
+        let job = |aliases: &[&str]| Job {
+            aliases: aliases.iter().map(|&a| a.into()).collect(),
+            steps: vec![step("true")],
+            ..Default::default()
+        };
+        let runfile = Runfile {
+            default: String::from("t"),
+            jobs: HashMap::from([
+                ("test".into(), job(&["t"])),
+                ("tidy".into(), job(&["t", "test"])),
+            ]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![
+                Issue::DuplicateAlias {
+                    alias: "t".into(),
+                    job: "tidy".into(),
+                    other: "test".into()
+                },
+                Issue::AliasIsJobId {
+                    alias: "test".into(),
+                    job: "tidy".into()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_conditional_needs() {
+        let job = |need: Need| Job {
+            needs: vec![need],
+            steps: vec![step("true")],
+            ..Default::default()
+        };
+        let conditional = |job: &str, condition: &str| Need {
+            job: job.into(),
+            condition: Some(condition.into()),
+        };
+        let runfile = Runfile {
+            default: String::from("a"),
+            jobs: HashMap::from([
+                ("a".into(), job("b".into())),
+                // Only a cycle while the condition holds
+                ("b".into(), job(conditional("a", "${{ params.cycle }}"))),
+                ("c".into(), job(conditional("a", "${{ params.missing }}"))),
+            ]),
+            params: HashMap::from([("cycle".into(), "false".into())]),
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile);
+
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(
+            &issues[0],
+            Issue::InvalidCondition { job, need, .. } if job == "c" && need == "a"
+        ));
+    }
+
+    #[test]
+    fn test_validate_after_ready() {
+        let db = Step {
+            name: Some("db".into()),
+            persistent: Some(true),
+            ready: Some("pg_isready".into()),
+            ..step("postgres")
+        };
+        let after = |reference: &str| Step {
+            after_ready: Some(reference.into()),
+            ..step("./migrate")
+        };
+        let runfile = Runfile {
+            default: String::from("dev"),
+            jobs: HashMap::from([(
+                "dev".into(),
+                Job {
+                    steps: vec![
+                        db,
+                        after("db"),
+                        after("0"),
+                        after("3"),
+                        after("missing"),
+                        Step {
+                            ready: Some("true".into()),
+                            ..step("make")
+                        },
+                        Step {
+                            persistent: Some(true),
+                            ignore_signals: true,
+                            ..step("./server")
+                        },
+                        Step {
+                            reload_signal: Some("SIGHUP".into()),
+                            ..step("make")
+                        },
+                        Step {
+                            shutdown_timeout: Some(Duration::from_secs(5)),
+                            ..step("make")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![
+                Issue::InvalidAfterReady {
+                    job: "dev".into(),
+                    step: 3,
+                    reference: "3".into()
+                },
+                Issue::InvalidAfterReady {
+                    job: "dev".into(),
+                    step: 4,
+                    reference: "missing".into()
+                },
+                Issue::ReadyNotPersistent {
+                    job: "dev".into(),
+                    step: 5
+                },
+                Issue::IgnoreSignalsPersistent {
+                    job: "dev".into(),
+                    step: 6
+                },
+                Issue::ReloadSignalNotPersistent {
+                    job: "dev".into(),
+                    step: 7
+                },
+                Issue::ShutdownTimeoutNotPersistent {
+                    job: "dev".into(),
+                    step: 8
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_step_needs() {
+        let runfile = Runfile {
+            default: String::from("dev"),
+            jobs: HashMap::from([(
+                "dev".into(),
+                Job {
+                    steps: vec![
+                        Step {
+                            needs: vec!["db".into()],
+                            after_ready: Some("db".into()),
+                            ..step("./migrate")
+                        },
+                        Step {
+                            name: Some("db".into()),
+                            persistent: Some(true),
+                            ready: Some("pg_isready".into()),
+                            ..step("postgres")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(validate(&runfile), Vec::new());
+
+        let mut cyclic = runfile.clone();
+        cyclic.jobs.get_mut("dev").unwrap().steps[1].needs = vec!["0".into()];
+        let issues = validate(&cyclic);
+        assert!(matches!(
+            &issues[..],
+            [
+                Issue::InvalidStepOrder { .. },
+                Issue::InvalidAfterReady { .. }
+            ]
+        ));
+        assert_eq!(
+            issues[0].to_string(),
+            "job 'dev': steps 0, 1 can't run, since their `needs` form a cycle"
+        );
+    }
+
+    #[test]
+    fn test_validate_looks_persistent() {
+        let runfile = |patterns: Option<Vec<String>>| Runfile {
+            default: String::from("dev"),
+            jobs: HashMap::from([(
+                "dev".into(),
+                Job {
+                    steps: vec![
+                        step("npm run dev"),
+                        step("tail -f log/dev.log"),
+                        step("./bin/server --port 8080"),
+                        step("tail -n 20 log/dev.log"),
+                        Step {
+                            persistent: Some(false),
+                            ..step("docker compose up --wait")
+                        },
+                        Step {
+                            persistent: Some(true),
+                            ..step("cargo watch")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            long_running_patterns: patterns,
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile(None));
+        assert_eq!(
+            issues,
+            vec![
+                Issue::LooksPersistent {
+                    job: "dev".into(),
+                    step: 0,
+                    pattern: "dev".into()
+                },
+                Issue::LooksPersistent {
+                    job: "dev".into(),
+                    step: 1,
+                    pattern: "tail -f".into()
+                },
+                Issue::LooksPersistent {
+                    job: "dev".into(),
+                    step: 2,
+                    pattern: "server".into()
+                },
+            ]
+        );
+        assert_eq!(
+            issues[1].to_string(),
+            "job 'dev' step 1: command contains `tail -f`, so it may keep running and block the \
+             run; set `persistent: true`, or `persistent: false` if it exits"
+        );
+        assert!(matches!(
+            &validate(&runfile(Some(vec!["log/dev.log".into()])))[..],
+            [
+                Issue::LooksPersistent { step: 1, .. },
+                Issue::LooksPersistent { step: 3, .. },
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_validate_expectations() {
+        let runfile = Runfile {
+            default: String::from("check"),
+            jobs: HashMap::from([(
+                "check".into(),
+                Job {
+                    steps: vec![
+                        Step {
+                            expect_exit: Some(1),
+                            expect_stdout_matches: Some("^ok$".into()),
+                            ..step("./check")
+                        },
+                        Step {
+                            expect_stdout_matches: Some("(".into()),
+                            ..step("./check")
+                        },
+                        Step {
+                            persistent: Some(true),
+                            expect_exit: Some(0),
+                            ..step("./server")
+                        },
+                        Step {
+                            silent: true,
+                            expect_stdout_contains: Some("ok".into()),
+                            ..step("./check")
+                        },
+                        Step {
+                            persistent: Some(true),
+                            max_duration: Some(Duration::from_secs(1)),
+                            ..step("./server")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile);
+        assert!(matches!(
+            &issues[..],
+            [
+                Issue::InvalidStdoutPattern { step: 1, .. },
+                Issue::ExpectationNotChecked { step: 2, .. },
+                Issue::ExpectationNotChecked { step: 3, .. },
+                Issue::ExpectationNotChecked { step: 4, .. },
+            ]
+        ));
+        assert_eq!(
+            issues[2].to_string(),
+            "job 'check' step 3: expectations are never checked on the output of a silent step"
+        );
+    }
+
+    #[test]
+    fn test_validate_success_exit_codes_ignored() {
+        let runfile = Runfile {
+            default: String::from("diff"),
+            jobs: HashMap::from([(
+                "diff".into(),
+                Job {
+                    steps: vec![
+                        Step {
+                            success_exit_codes: vec![0, 1],
+                            ..step("diff a b")
+                        },
+                        Step {
+                            expect_exit: Some(1),
+                            success_exit_codes: vec![0, 1],
+                            ..step("diff a b")
+                        },
+                    ],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile);
+        assert!(matches!(
+            &issues[..],
+            [Issue::SuccessExitCodesIgnored { step: 1, .. }]
+        ));
+        assert_eq!(
+            issues[0].to_string(),
+            "job 'diff' step 1: `success_exit_codes` has no effect with `expect_exit`"
+        );
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_validate_reload_signal() {
+        let server = |signal: &str| Step {
+            persistent: Some(true),
+            reload_signal: Some(signal.into()),
+            ..step("./server")
+        };
+        let runfile = Runfile {
+            default: String::from("dev"),
+            jobs: HashMap::from([(
+                "dev".into(),
+                Job {
+                    steps: vec![server("SIGHUP"), server("usr1"), server("SIGRELOAD")],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            validate(&runfile),
+            vec![Issue::UnknownSignal {
+                job: "dev".into(),
+                step: 2,
+                signal: "SIGRELOAD".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_validate_warnings_sorted_after_errors() {
+        let runfile = Runfile {
+            default: String::from("missing"),
+            jobs: HashMap::from([(
+                "a".into(),
+                Job {
+                    steps: vec![Step {
+                        restart: RestartPolicy::Always,
+                        fail_on_output_limit: true,
+                        platforms: vec!["linux".into(), "mac".into()],
+                        ..step("true")
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let issues = validate(&runfile);
+
+        assert_eq!(
+            issues.iter().map(|i| i.severity()).collect::<Vec<_>>(),
+            vec![
+                Severity::Error,
+                Severity::Warning,
+                Severity::Warning,
+                Severity::Warning
+            ]
+        );
+        assert!(issues.contains(&Issue::UnknownPlatform {
+            job: "a".into(),
+            step: 0,
+            platform: "mac".into()
+        }));
+    }
+}
@@ -0,0 +1,384 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{git::path_matches, glob::glob_match, job::RunHandle};
+
+/// How often watched files are checked for changes
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long watched files have to stay unchanged before a change is reported, so a burst of
+/// changes, e.g. from saving several files or checking out a branch, is reported once
+pub const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Names of version control and build directories that are never watched, unless the runfile
+/// sets `watch_ignore`. The state directory is never watched either way, since every run
+/// writes to it, see [`WatchSet::new`].
+pub const DEFAULT_IGNORES: &[&str] = &[
+    ".git",
+    ".hg",
+    ".svn",
+    "target",
+    "node_modules",
+    "build",
+    "dist",
+    "__pycache__",
+    ".venv",
+];
+
+/// Watched paths, along with the paths excluded from them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WatchSet {
+    paths: Vec<String>,
+    excludes: Vec<String>,
+    state_dir: Option<PathBuf>,
+}
+
+impl WatchSet {
+    /// Watch `paths` (directories, files, or globs), except those starting with `!`, which are
+    /// excluded instead. `ignore` replaces [`DEFAULT_IGNORES`] if given. Exclusions without a
+    /// `/` exclude files and directories of that name anywhere, others are relative to the root.
+    /// `state_dir`, relative to the working directory like any `--state-dir`, is always excluded.
+    pub fn new(
+        paths: impl IntoIterator<Item = String>,
+        ignore: Option<&[String]>,
+        state_dir: &Path,
+    ) -> WatchSet {
+        let (excludes, paths): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .map(|path| match path.strip_prefix("./") {
+                Some(rest) if !rest.is_empty() => rest.to_owned(),
+                _ => path,
+            })
+            .partition(|path| path.starts_with('!'));
+        let ignore = match ignore {
+            Some(ignore) => ignore.to_vec(),
+            None => DEFAULT_IGNORES
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect(),
+        };
+        let excludes = excludes
+            .iter()
+            .map(|path| path[1..].to_owned())
+            .chain(ignore)
+            .collect();
+        WatchSet {
+            paths,
+            excludes,
+            state_dir: std::path::absolute(state_dir).ok(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Whether `path` is the state directory, with `path` being absolute
+    fn is_state_dir(&self, path: &Path) -> bool {
+        self.state_dir.as_deref() == Some(path)
+    }
+
+    fn excludes(&self, relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.excludes
+            .iter()
+            .any(|exclude| match exclude.contains('/') {
+                true => path_matches(exclude.trim_end_matches("/**"), relative),
+                false => glob_match(exclude, name),
+            })
+    }
+}
+
+/// The files matching some watched paths along with when they were last modified, to tell which
+/// of them changed between two scans
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    files: BTreeMap<String, SystemTime>,
+}
+
+impl Snapshot {
+    /// Scan the files under `root` matched by the paths of `set` (directories, files, or globs
+    /// relative to `root`, see [`path_matches`])
+    pub fn scan(root: &Path, set: &WatchSet) -> Snapshot {
+        let root = &std::path::absolute(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut snapshot = Snapshot::default();
+        for path in set.paths.iter() {
+            if matches!(path.as_str(), "." | "./") {
+                snapshot.walk(root, "", set, &|_| true);
+                continue;
+            }
+            // Only the directory before the first glob character can contain matches
+            let literal = path.split(['*', '?']).next().unwrap_or_default();
+            let start = match literal.rfind('/') {
+                Some(end) if literal.len() < path.len() => &literal[..end],
+                None if literal.len() < path.len() => "",
+                _ => path.trim_end_matches('/'),
+            };
+            snapshot.walk(root, start, set, &|file| path_matches(path, file));
+        }
+        snapshot
+    }
+
+    fn walk(
+        &mut self,
+        root: &Path,
+        relative: &str,
+        set: &WatchSet,
+        matches: &dyn Fn(&str) -> bool,
+    ) {
+        let full = root.join(relative);
+        let Ok(metadata) = std::fs::metadata(&full) else {
+            return;
+        };
+        if metadata.is_file() {
+            if matches(relative) {
+                self.files.insert(
+                    relative.to_owned(),
+                    metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                );
+            }
+            return;
+        }
+
+        let Ok(entries) = std::fs::read_dir(&full) else {
+            return;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let child = match relative {
+                "" => name,
+                _ => format!("{relative}/{name}"),
+            };
+            // Only what's found while walking is excluded, so paths given explicitly are watched
+            if !set.excludes(&child) && !set.is_state_dir(&entry.path()) {
+                self.walk(root, &child, set, matches);
+            }
+        }
+    }
+
+    /// Files added, removed or modified in `newer`, sorted by path
+    pub fn changes(&self, newer: &Snapshot) -> Vec<String> {
+        let changed = newer
+            .files
+            .iter()
+            .filter(|(file, modified)| self.files.get(*file) != Some(modified))
+            .map(|(file, _)| file.clone());
+        let removed = self
+            .files
+            .keys()
+            .filter(|file| !newer.files.contains_key(*file))
+            .cloned();
+
+        let mut changes = changed.chain(removed).collect::<Vec<_>>();
+        changes.sort();
+        changes
+    }
+}
+
+/// Scan again until the files under `root` matched by `set` stop changing for [`DEBOUNCE`],
+/// starting from `latest`
+fn settle(root: &Path, set: &WatchSet, mut latest: Snapshot) -> Snapshot {
+    loop {
+        std::thread::sleep(DEBOUNCE);
+        let newer = Snapshot::scan(root, set);
+        if newer == latest {
+            return latest;
+        }
+        latest = newer;
+    }
+}
+
+/// Wait until a file under `root` matched by `set` differs from `since`, returning the changed
+/// files, or `None` once `cancel` is set. Changes made before the wait started count, so several
+/// changes made during a run start a single new run.
+pub fn wait_for_change(
+    root: &Path,
+    set: &WatchSet,
+    since: Snapshot,
+    cancel: &AtomicBool,
+) -> Option<Vec<String>> {
+    while !cancel.load(Ordering::Relaxed) {
+        std::thread::sleep(POLL_INTERVAL);
+        let newer = Snapshot::scan(root, set);
+        if !since.changes(&newer).is_empty() {
+            return Some(since.changes(&settle(root, set, newer)));
+        }
+    }
+    None
+}
+
+/// How often watching a run checks whether it ended
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Watch the files under `root` matched by `set` until `run` ends, cancelling it once one
+/// differs from `since` or `interrupt` is set. Returns the changed files if they cancelled it.
+pub fn watch_run(
+    root: &Path,
+    set: &WatchSet,
+    since: &Snapshot,
+    interrupt: &AtomicBool,
+    run: &RunHandle,
+) -> Option<Vec<String>> {
+    let mut next_scan = Instant::now() + POLL_INTERVAL;
+    while !run.is_finished() {
+        if interrupt.load(Ordering::Relaxed) {
+            run.cancel();
+            return None;
+        }
+        std::thread::sleep(RUN_POLL_INTERVAL);
+        if Instant::now() < next_scan {
+            continue;
+        }
+        next_scan = Instant::now() + POLL_INTERVAL;
+
+        let newer = Snapshot::scan(root, set);
+        if !since.changes(&newer).is_empty() {
+            run.cancel();
+            return Some(since.changes(&settle(root, set, newer)));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        job::RunOptions,
+        runfile::{Job, Runfile, Step},
+        testing::TempDir,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_snapshot_changes() {
+        let root = TempDir::new("watch");
+        std::fs::create_dir_all(root.join("src/bin")).unwrap();
+        std::fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("src/bin/tool.rs"), "fn main() {}").unwrap();
+        std::fs::write(root.join("README.md"), "").unwrap();
+        let paths = WatchSet::new(
+            [String::from("src"), String::from("*.toml")],
+            None,
+            &root.join(".unirun"),
+        );
+
+        let before = Snapshot::scan(&root, &paths);
+        assert_eq!(before.files.len(), 2);
+
+        std::fs::write(root.join("Cargo.toml"), "").unwrap();
+        std::fs::write(root.join("README.md"), "# unirun").unwrap();
+        std::fs::remove_file(root.join("src/bin/tool.rs")).unwrap();
+        let after = Snapshot::scan(&root, &paths);
+
+        assert_eq!(
+            before.changes(&after),
+            vec![String::from("Cargo.toml"), String::from("src/bin/tool.rs")]
+        );
+        assert!(after.changes(&after).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_excludes() {
+        let root = TempDir::new("excludes");
+        for dir in ["src", "target/debug", "web/node_modules/react", "gen", "st"] {
+            std::fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "target/debug/uni",
+            "web/index.js",
+            "web/node_modules/react/index.js",
+            "gen/schema.rs",
+            "gen/keep.rs",
+            "st/last-run.json",
+        ] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let files = |paths: &[&str], ignore: Option<&[String]>| {
+            // A state directory given with `--state-dir st` rather than the default `.unirun`
+            let set = WatchSet::new(
+                paths.iter().map(|&p| p.to_owned()),
+                ignore,
+                &root.join("st"),
+            );
+            Snapshot::scan(&root, &set)
+                .files
+                .into_keys()
+                .collect::<Vec<_>>()
+        };
+
+        let watched = files(&[".", "!gen/schema.rs"], None);
+        let unignored = files(&["web"], Some(&[]));
+        let state = files(&["."], Some(&[]));
+        let explicit = files(&["target/debug"], None);
+
+        assert_eq!(watched, vec!["gen/keep.rs", "src/main.rs", "web/index.js"]);
+        assert_eq!(
+            unignored,
+            vec!["web/index.js", "web/node_modules/react/index.js"]
+        );
+        assert_eq!(explicit, vec!["target/debug/uni"]);
+        assert!(!state.iter().any(|file| file.starts_with("st/")));
+    }
+
+    fn slow_runfile() -> Runfile {
+        Runfile {
+            default: String::from("slow"),
+            jobs: HashMap::from([(
+                "slow".into(),
+                Job {
+                    steps: vec![Step {
+                        command: "sleep 10".into(),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_watch_run_cancels_run() {
+        let root = TempDir::new("watch-run");
+        std::fs::write(root.join("main.rs"), "fn main() {}").unwrap();
+        let paths = WatchSet::new([String::from("*.rs")], None, &root.join(".unirun"));
+        let since = Snapshot::scan(&root, &paths);
+
+        let started = Instant::now();
+        let run = RunHandle::spawn(slow_runfile(), vec!["slow".into()], RunOptions::default());
+        std::fs::write(root.join("lib.rs"), "").unwrap();
+        let changes = watch_run(&root, &paths, &since, &AtomicBool::new(false), &run);
+        run.join().unwrap();
+
+        assert_eq!(changes, Some(vec![String::from("lib.rs")]));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn test_watch_run_interrupted() {
+        let started = Instant::now();
+        let run = RunHandle::spawn(slow_runfile(), vec!["slow".into()], RunOptions::default());
+
+        let changes = watch_run(
+            &std::env::temp_dir(),
+            &WatchSet::default(),
+            &Snapshot::default(),
+            &AtomicBool::new(true),
+            &run,
+        );
+
+        assert_eq!(changes, None);
+        assert!(run.join().is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}